@@ -11,10 +11,12 @@ use serde::{Deserialize, Serialize};
 use std::os::unix::fs::MetadataExt;
 
 use crate::core::{
-    add::{FileContent, compress, compute_hash, get_file_metadata, index::IndexEntry, smart_read},
+    add::{
+        FileContent, compress_zstd, compute_hash, get_file_metadata, index::IndexEntry, smart_read,
+    },
     commit::{blob::Blob, error::CommitError},
     error::GatoResult,
-    storage::{StorageEngine, StorageError, local::LocalStorage},
+    storage::{ObjectStore, StorageError},
 };
 
 pub fn cut(data: &FileContent) -> Vec<&[u8]> {
@@ -28,7 +30,8 @@ pub fn cut(data: &FileContent) -> Vec<&[u8]> {
         .collect()
 }
 
-pub fn process_chunk(chunks: Vec<&[u8]>, storage: &LocalStorage) -> ChunkerResult {
+pub fn process_chunk(chunks: Vec<&[u8]>, storage: &(impl ObjectStore + ?Sized)) -> ChunkerResult {
+    let level = storage.compression_level();
     let mut data = BTreeMap::new();
 
     let mut ordered_hash = Vec::new();
@@ -36,9 +39,9 @@ pub fn process_chunk(chunks: Vec<&[u8]>, storage: &LocalStorage) -> ChunkerResul
         .par_iter()
         .map(|chunk| {
             let hash = compute_hash(chunk).to_vec();
-            if !storage.exist(&hex::encode(&hash)) {
+            if !storage.freshen(&hex::encode(&hash)) {
                 let compressed_data =
-                    compress(chunk, storage.work_dir()).expect("failed to compress chunk");
+                    compress_zstd(chunk, level).expect("failed to compress chunk");
                 (hash, Some(compressed_data))
             } else {
                 (hash, None)
@@ -82,7 +85,7 @@ impl IndexData {
     pub fn restore_file(
         self,
         target_path: &Path,
-        storage: &LocalStorage,
+        storage: &(impl ObjectStore + ?Sized),
     ) -> Result<(), StorageError> {
         let mut file = std::fs::File::create(target_path)?;
         for chunk_hash in &self.path {
@@ -99,7 +102,7 @@ impl IndexData {
         Ok(())
     }
 
-    pub fn restore_data(&self, storage: &LocalStorage) -> GatoResult<Vec<u8>> {
+    pub fn restore_data(&self, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<Vec<u8>> {
         // let mut file = std::fs::File::create(target_path)?;
         let mut data = Vec::new();
         for chunk_hash in &self.path {
@@ -124,7 +127,7 @@ pub struct ChunkerResult {
 }
 
 impl ChunkerResult {
-    pub fn save_chunks(&self, storage: &impl StorageEngine) {
+    pub fn save_chunks(&self, storage: &(impl ObjectStore + ?Sized)) {
         self.chunks.par_iter().for_each(|(hash, data)| {
             match storage.put(&hex::encode(hash), data.to_vec()) {
                 Ok(_) => {}
@@ -145,7 +148,7 @@ impl ChunkerResult {
 
 pub fn add_as_chunk(
     path: &Path,
-    storage: &LocalStorage,
+    storage: &(impl ObjectStore + ?Sized),
 ) -> Result<(PathBuf, IndexEntry, Vec<String>), CommitError> {
     let buffer = smart_read(path)?;
 
@@ -182,7 +185,10 @@ pub fn add_as_chunk(
     Ok((path.to_owned(), index, hashs))
 }
 
-pub fn get_dry_chunck_hash(path: &Path, storage: &LocalStorage) -> GatoResult<String> {
+pub fn get_dry_chunck_hash(
+    path: &Path,
+    storage: &(impl ObjectStore + ?Sized),
+) -> GatoResult<String> {
     let buffer = smart_read(path)?;
 
     let chunker_result = process_chunk(cut(&buffer), storage);
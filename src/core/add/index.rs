@@ -3,9 +3,15 @@ use std::{collections::BTreeMap, path::PathBuf};
 use bincode::{
     Decode, Encode,
     config::{self},
+    decode_from_slice, encode_to_vec,
+    error::DecodeError,
 };
 
-use crate::core::storage::local::LocalStorage;
+use crate::core::storage::{RefStore, StorageError, local::LocalStorage, lock::LockFile};
+
+/// Start of the index file since it records merge conflicts; older files are
+/// a bare [`LegacyIndex`].
+const MAGIC: &[u8; 4] = b"GIX1";
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct IndexEntry {
@@ -15,10 +21,58 @@ pub struct IndexEntry {
     pub mode: u32,
 }
 
-#[derive(Encode, Decode, Debug, Clone)]
+/// Why a merge could not resolve a path.
+#[derive(Encode, Decode, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both sides changed the file differently.
+    Content,
+    /// Both sides added a file with different contents.
+    AddAdd,
+    /// We deleted the file, they changed it.
+    DeletedByUs,
+    /// They deleted the file, we changed it.
+    DeletedByThem,
+    /// One side has a file where the other has a directory; the file was
+    /// moved aside to `<name>~ours` or `<name>~theirs`.
+    FileDirectory,
+}
+
+impl std::fmt::Display for ConflictKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            ConflictKind::Content => "both modified",
+            ConflictKind::AddAdd => "both added",
+            ConflictKind::DeletedByUs => "deleted by us",
+            ConflictKind::DeletedByThem => "deleted by them",
+            ConflictKind::FileDirectory => "file/directory",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+/// The base, ours and theirs versions of a path a merge could not resolve.
+/// `None` means the path does not exist on that side.
+#[derive(Encode, Decode, Debug, Clone, PartialEq)]
+pub struct ConflictEntry {
+    pub kind: ConflictKind,
+    pub base: Option<Vec<u8>>,
+    pub ours: Option<Vec<u8>>,
+    pub theirs: Option<Vec<u8>>,
+}
+
+#[derive(Encode, Decode, Debug, Clone, Default)]
 pub struct Index {
     pub entries: BTreeMap<PathBuf, IndexEntry>,
     pub dependencies: Vec<String>,
+    /// Unresolved merge conflicts; staging a path with `add` resolves it.
+    pub conflicts: BTreeMap<PathBuf, ConflictEntry>,
+}
+
+/// The index as written before merges recorded conflicts.
+#[derive(Decode)]
+struct LegacyIndex {
+    entries: BTreeMap<PathBuf, IndexEntry>,
+    dependencies: Vec<String>,
 }
 
 impl Index {
@@ -26,11 +80,12 @@ impl Index {
         Index {
             entries: BTreeMap::new(),
             dependencies: Vec::new(),
+            conflicts: BTreeMap::new(),
         }
     }
 
     pub fn index_file_path(storage: &LocalStorage) -> PathBuf {
-        storage.repo_path().join("index")
+        storage.state_file("index")
     }
 
     // pub fn add_dependency(&mut self, dependency: String) {
@@ -40,6 +95,7 @@ impl Index {
     // }
 
     pub fn add_entry(&mut self, path: PathBuf, entry: IndexEntry) {
+        self.conflicts.remove(&path);
         self.entries.insert(path, entry);
     }
 
@@ -47,19 +103,55 @@ impl Index {
     //     self.entries.get(path)
     // }
 
-    pub fn load(storage: &LocalStorage) -> std::io::Result<Self> {
-        let data = std::fs::read(Self::index_file_path(&storage))?;
-        let (index, _): (Index, usize) =
-            bincode::decode_from_slice(&data.as_slice(), config::standard())
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-        Ok(index)
+    /// The staged index; [`StorageError::NothingStaged`] when there is none.
+    pub fn load(storage: &(impl RefStore + ?Sized)) -> Result<Self, StorageError> {
+        storage.load_index()?.ok_or(StorageError::NothingStaged)
+    }
+
+    /// Take the lock on the index file, so it can be loaded, changed and saved
+    /// with [`Index::save_locked`] without another process writing in between.
+    pub fn lock(storage: &LocalStorage) -> Result<LockFile, StorageError> {
+        storage.lock(&Self::index_file_path(storage))
+    }
+
+    pub fn save(&self, storage: &(impl RefStore + ?Sized)) -> Result<(), StorageError> {
+        storage.save_index(self)
+    }
+
+    /// Objects the index needs: what is staged, with the chunks of chunked
+    /// files, and the versions of conflicting paths.
+    pub fn objects(&self) -> Vec<String> {
+        let mut objects = self.dependencies.clone();
+        objects.extend(self.entries.values().map(|e| hex::encode(&e.hash)));
+        for conflict in self.conflicts.values() {
+            let sides = [&conflict.base, &conflict.ours, &conflict.theirs];
+            objects.extend(sides.into_iter().flatten().map(hex::encode));
+        }
+        objects
+    }
+
+    /// Decode an index file, reading older files as having no conflicts.
+    pub fn decode(data: &[u8]) -> Result<Self, DecodeError> {
+        if let Some(data) = data.strip_prefix(MAGIC) {
+            return Ok(decode_from_slice(data, config::standard())?.0);
+        }
+        let (legacy, _): (LegacyIndex, usize) = decode_from_slice(data, config::standard())?;
+        Ok(Index {
+            entries: legacy.entries,
+            dependencies: legacy.dependencies,
+            conflicts: BTreeMap::new(),
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend(encode_to_vec(self, config::standard()).expect("Encoding failed"));
+        data
     }
 
-    pub fn save(&self, storage: &LocalStorage) -> std::io::Result<()> {
-        let encoded: Vec<u8> =
-            bincode::encode_to_vec(self, config::standard()).expect("Encoding failed");
-        std::fs::write(Self::index_file_path(storage), encoded)?;
-        Ok(())
+    /// Replace the index file under a lock taken with [`Index::lock`].
+    pub fn save_locked(&self, lock: LockFile) -> Result<(), StorageError> {
+        lock.commit(&self.encode())
     }
 
     // pub fn debug_print(&self) {
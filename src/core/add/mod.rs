@@ -9,16 +9,15 @@ use std::ops::Deref;
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 
 use crate::core::add::chunker::{add_as_chunk, get_dry_chunck_hash};
-use crate::core::add::index::{Index, IndexEntry};
+use crate::core::add::index::IndexEntry;
 use crate::core::commit::blob::Blob;
 use crate::core::config::load::load_config;
 
 use crate::core::error::{self, GatoResult};
-use crate::core::storage::StorageEngine;
 use crate::core::storage::local::LocalStorage;
+use crate::core::storage::{ObjectStore, StorageEngine};
 
 pub mod chunker;
 pub mod index;
@@ -77,12 +76,9 @@ fn compress_zstd(data: &[u8], level: i32) -> GatoResult<Vec<u8>> {
 }
 
 pub fn decompress_zstd(data: &[u8]) -> GatoResult<Vec<u8>> {
-    let mut decoder =
-        zstd::stream::read::Decoder::new(&data[..]).expect("Failed to create zstd decoder");
+    let mut decoder = zstd::stream::read::Decoder::new(data)?;
     let mut decompressed_data = Vec::new();
-    decoder
-        .read_to_end(&mut decompressed_data)
-        .expect("Failed to read data from zstd decoder");
+    decoder.read_to_end(&mut decompressed_data)?;
     Ok(decompressed_data)
 }
 
@@ -148,12 +144,12 @@ pub fn compute_hash(data: &[u8]) -> [u8; 32] {
     *hash
 }
 
-pub fn add_file_dry(buffer: &[u8], storage: &LocalStorage) -> GatoResult<Vec<u8>> {
+pub fn add_file_dry(buffer: &[u8], storage: &(impl ObjectStore + ?Sized)) -> GatoResult<Vec<u8>> {
     let hash = compute_hash(&buffer);
     let hash_str = hex::encode(hash);
 
-    if !storage.exist(&hash_str) {
-        let compressed_data = compress(&buffer, storage.work_dir())?;
+    if !storage.freshen(&hash_str) {
+        let compressed_data = compress_zstd(&buffer, storage.compression_level())?;
         let data = Blob::Normal(compressed_data);
 
         storage.put(&hash_str, data.encode()?)?;
@@ -161,21 +157,29 @@ pub fn add_file_dry(buffer: &[u8], storage: &LocalStorage) -> GatoResult<Vec<u8>
     Ok(hash.to_vec())
 }
 
-pub fn add_file(file_path: &Path, storage: &LocalStorage) -> GatoResult<index::IndexEntry> {
+pub fn add_file(
+    file_path: &Path,
+    storage: &(impl ObjectStore + ?Sized),
+) -> GatoResult<index::IndexEntry> {
     let buffer = smart_read(file_path)?;
     let hash = compute_hash(&buffer);
     let hash_str = hex::encode(hash);
 
-    if !storage.exist(&hash_str) {
-        let compressed_data = compress(&buffer, storage.work_dir())?;
+    if !storage.freshen(&hash_str) {
+        let compressed_data = compress_zstd(&buffer, storage.compression_level())?;
         let data = Blob::Normal(compressed_data);
 
         storage.put(&hash_str, data.encode()?)?;
     }
 
+    index_entry(file_path, hash.to_vec())
+}
+
+/// Build the index entry for a file already in the store under `hash`.
+pub fn index_entry(file_path: &Path, hash: Vec<u8>) -> GatoResult<index::IndexEntry> {
     let metadata = get_file_metadata(file_path)?;
     let index_entry = index::IndexEntry {
-        hash: hash.to_vec(),
+        hash,
         size: metadata.len(),
         mtime: metadata.modified()?.elapsed().unwrap().as_secs() as u32,
         #[cfg(unix)]
@@ -199,39 +203,44 @@ pub fn get_dry_hash(file_path: &Path, storage: &LocalStorage) -> GatoResult<Stri
     }
 }
 
-pub fn add_all(paths: Vec<PathBuf>, storage: Arc<LocalStorage>) -> GatoResult<()> {
-    let mut index = Index::load(storage.as_ref()).unwrap_or(Index::new());
+/// Store the files at `paths`, relative to `work_dir`, and stage them.
+pub fn add_all(
+    paths: Vec<PathBuf>,
+    work_dir: &Path,
+    storage: &(impl StorageEngine + ?Sized),
+) -> GatoResult<()> {
     let new_entries: Vec<Result<(PathBuf, IndexEntry, Vec<String>), error::Error>> = paths
         .par_iter()
         .map(|path| {
-            let file_len = get_file_metadata(&storage.work_dir().join(path))?.len();
+            let file_len = get_file_metadata(&work_dir.join(path))?.len();
             if file_len < 1024 * 1024 * 8 {
-                let storage_clone = Arc::clone(&storage);
-                let entry = add_file(&storage_clone.work_dir().join(path), storage_clone.as_ref())?;
+                let entry = add_file(&work_dir.join(path), storage)?;
                 let deps = vec![hex::encode(&entry.hash)];
                 Ok((path.clone(), entry, deps))
             } else {
-                let storage_clone = Arc::clone(&storage);
-                let (_, entry, hashs) =
-                    add_as_chunk(&storage_clone.work_dir().join(path), storage_clone.as_ref())?;
+                let (_, entry, hashs) = add_as_chunk(&work_dir.join(path), storage)?;
 
                 Ok((path.clone(), entry, hashs))
             }
         })
         .collect();
+    let mut staged = Vec::with_capacity(new_entries.len());
     for result in new_entries {
         match result {
-            Ok((path, entry, deps)) => {
-                index.add_entry(path, entry.clone());
-                index.dependencies.extend(deps);
-            }
+            Ok(entry) => staged.push(entry),
             Err(e) => {
                 eprintln!("Failed to process file: {}", e);
                 return Err(e);
             }
         }
     }
-    index.save(storage.as_ref())?;
+    // the objects are stored already, only the index needs the lock
+    storage.update_index(&mut |index| {
+        for (path, entry, deps) in staged.drain(..) {
+            index.add_entry(path, entry);
+            index.dependencies.extend(deps);
+        }
+    })?;
     Ok(())
 }
 
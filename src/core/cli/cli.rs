@@ -1,5 +1,7 @@
 use std::path::PathBuf;
 
+use crate::core::commit::merge::{MergeStrategy, Side};
+
 #[derive(clap::Parser)]
 #[command(name = "Gato")]
 #[command(about = "A High-Performance, Parallelized Version Control System", long_about = None)]
@@ -21,9 +23,23 @@ pub enum Commands {
     Init {
         #[arg(short, long)]
         id: Option<String>,
+        /// Keep objects in this store, relative to the working directory,
+        /// instead of the shared one
+        #[arg(long)]
+        store: Option<PathBuf>,
         // #[arg(short, long)]
         // force: bool,
     },
+    #[clap(
+        name = "clone",
+        about = "Copy a repository of the store into a new working directory"
+    )]
+    Clone {
+        /// Working directory of the repository to clone
+        source: PathBuf,
+        /// New working directory; created if missing, must be empty
+        dest: PathBuf,
+    },
     #[clap(name = "add", about = "Add file contents to the index", alias = "a")]
     Add { paths: Vec<String> },
     #[clap(
@@ -31,14 +47,48 @@ pub enum Commands {
         about = "Record changes to the repository",
         alias = "c"
     )]
-    Commit { message: String },
+    Commit {
+        /// Commit message; the editor is opened when neither this nor `-F` is given
+        #[arg(conflicts_with = "file")]
+        message: Option<String>,
+        /// Read the message from a file (`-` for standard input)
+        #[arg(short = 'F', long)]
+        file: Option<PathBuf>,
+        /// Stage the current content of every tracked file first
+        #[arg(short, long)]
+        all: bool,
+        /// Replace the last commit, keeping its parents
+        #[arg(long)]
+        amend: bool,
+        /// With `--amend`, keep the last message without opening the editor
+        #[arg(long, requires = "amend")]
+        no_edit: bool,
+        /// Override the author, as `Name <email>`
+        #[arg(long)]
+        author: Option<String>,
+        /// Record an extra `KEY=VALUE` header in the commit; may be repeated
+        #[arg(long = "trailer", value_name = "KEY=VALUE", value_parser = parse_key_value, conflicts_with = "amend")]
+        trailers: Vec<(String, String)>,
+        /// Sign the commit with the ed25519 key configured under [signing]
+        #[arg(short = 'S', long)]
+        sign: bool,
+    },
 
     #[clap(
         name = "checkout",
         about = "Checkout a specific commit 0 for last commit",
         alias = "co"
     )]
-    Checkout { commit_index: usize },
+    Checkout {
+        #[arg(required_unless_present_any = ["ours", "theirs"])]
+        commit_index: Option<usize>,
+        /// Restore our version of a conflicted path
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["commit_index", "theirs"])]
+        ours: Option<PathBuf>,
+        /// Restore their version of a conflicted path
+        #[arg(long, value_name = "PATH", conflicts_with = "commit_index")]
+        theirs: Option<PathBuf>,
+    },
     #[clap(name = "new-branch", about = "Create a new branch", alias = "nb")]
     NewBranch { branch_name: String },
     #[clap(
@@ -59,7 +109,37 @@ pub enum Commands {
         about = "Garbage collect unreferenced objects",
         alias = "gc"
     )]
-    Gc,
+    Gc {
+        /// Report what would be deleted and how many bytes it would reclaim
+        #[arg(long)]
+        dry_run: bool,
+        /// Keep unreferenced objects written in the last MINUTES minutes
+        #[arg(long, value_name = "MINUTES")]
+        grace_period: Option<u64>,
+    },
+
+    #[clap(
+        name = "stats",
+        about = "Count the objects of every repository and what deduplication saves"
+    )]
+    Stats {
+        /// How many of the largest files to list
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+    },
+
+    #[clap(
+        name = "reflog",
+        about = "Show where HEAD or a branch pointed to over time",
+        args_conflicts_with_subcommands = true
+    )]
+    Reflog {
+        #[command(subcommand)]
+        action: Option<ReflogAction>,
+        /// HEAD by default
+        #[arg(value_name = "REF")]
+        ref_name: Option<String>,
+    },
 
     #[clap(
         name = "list-repos",
@@ -71,6 +151,27 @@ pub enum Commands {
     #[clap(name = "delete-repo", about = "Delete a repository", alias = "dr")]
     DeleteRepo,
 
+    #[clap(
+        name = "worktree",
+        about = "Check branches out in more working directories of this repository"
+    )]
+    Worktree {
+        #[command(subcommand)]
+        action: WorktreeAction,
+    },
+
+    #[clap(name = "repo", about = "Manage this repository's entry in the store")]
+    Repo {
+        #[command(subcommand)]
+        action: RepoAction,
+    },
+
+    #[clap(name = "store", about = "Manage the object store of this repository")]
+    Store {
+        #[command(subcommand)]
+        action: StoreAction,
+    },
+
     #[clap(
         name = "delete-branch",
         about = "Delete a branch from the repository",
@@ -87,22 +188,174 @@ pub enum Commands {
         alias = "m"
     )]
     Merge {
-        target_branch: String,
-        message: String,
+        /// Branches to merge; more than one makes an octopus merge
+        #[arg(required_unless_present_any = ["continue_merge", "abort"])]
+        targets: Vec<String>,
+        #[arg(short, long)]
+        message: Option<String>,
+        /// Create a merge commit even when the branch could be fast-forwarded
+        #[arg(long, conflicts_with = "ff_only")]
+        no_ff: bool,
+        /// Refuse to merge unless the branch can be fast-forwarded
+        #[arg(long)]
+        ff_only: bool,
+        /// How to settle hunks changed differently on both sides
+        #[arg(short = 'X', long = "strategy-option", value_enum, default_value_t)]
+        strategy: MergeStrategy,
+        /// Side to take for binary files changed on both sides
+        #[arg(long, value_enum)]
+        binary: Option<Side>,
+        /// Commit a conflicted merge once all conflicts are staged
+        #[arg(long = "continue", conflicts_with = "abort")]
+        continue_merge: bool,
+        /// Abandon a conflicted merge and restore the last commit
+        #[arg(long)]
+        abort: bool,
+    },
+    #[clap(
+        name = "cherry-pick",
+        about = "Apply the changes introduced by an existing commit",
+        alias = "cp"
+    )]
+    CherryPick {
+        #[arg(required_unless_present_any = ["continue_pick", "abort"])]
+        rev: Option<String>,
+        /// How to settle hunks changed differently on both sides
+        #[arg(short = 'X', long = "strategy-option", value_enum, default_value_t)]
+        strategy: MergeStrategy,
+        /// Side to take for binary files changed on both sides
+        #[arg(long, value_enum)]
+        binary: Option<Side>,
+        /// Commit once all conflicts are staged
+        #[arg(long = "continue", conflicts_with_all = ["abort", "rev"])]
+        continue_pick: bool,
+        /// Give up and restore the last commit
+        #[arg(long, conflicts_with = "rev")]
+        abort: bool,
+    },
+    #[clap(name = "revert", about = "Create a commit undoing an existing commit")]
+    Revert {
+        #[arg(required_unless_present_any = ["continue_pick", "abort"])]
+        rev: Option<String>,
+        /// How to settle hunks changed differently on both sides
+        #[arg(short = 'X', long = "strategy-option", value_enum, default_value_t)]
+        strategy: MergeStrategy,
+        /// Side to take for binary files changed on both sides
+        #[arg(long, value_enum)]
+        binary: Option<Side>,
+        /// Commit once all conflicts are staged
+        #[arg(long = "continue", conflicts_with_all = ["abort", "rev"])]
+        continue_pick: bool,
+        /// Give up and restore the last commit
+        #[arg(long, conflicts_with = "rev")]
+        abort: bool,
+    },
+    #[clap(
+        name = "rebase",
+        about = "Replay the current branch on top of another commit"
+    )]
+    Rebase {
+        #[arg(required_unless_present_any = ["continue_rebase", "skip", "abort"])]
+        upstream: Option<String>,
+        /// Replay onto this commit instead of `upstream`
+        #[arg(long)]
+        onto: Option<String>,
+        /// Fold `fixup!` and `squash!` commits into the commit they name
+        #[arg(long)]
+        autosquash: bool,
+        /// How to settle hunks changed differently on both sides
+        #[arg(short = 'X', long = "strategy-option", value_enum, default_value_t)]
+        strategy: MergeStrategy,
+        /// Side to take for binary files changed on both sides
+        #[arg(long, value_enum)]
+        binary: Option<Side>,
+        /// Commit the resolved step and replay the rest
+        #[arg(long = "continue", conflicts_with_all = ["skip", "abort", "upstream"])]
+        continue_rebase: bool,
+        /// Drop the conflicted commit and replay the rest
+        #[arg(long, conflicts_with_all = ["abort", "upstream"])]
+        skip: bool,
+        /// Give up and put the branch back where it was
+        #[arg(long, conflicts_with = "upstream")]
+        abort: bool,
+    },
+    #[clap(
+        name = "conflicts",
+        about = "List paths with unresolved merge conflicts"
+    )]
+    Conflicts,
+    #[clap(
+        name = "merge-base",
+        about = "Find the best common ancestor(s) of two commits",
+        alias = "mb"
+    )]
+    MergeBase {
+        first: String,
+        second: String,
+        /// Print every best common ancestor instead of only the first one
+        #[arg(short, long)]
+        all: bool,
     },
     #[clap(
         name = "verify-commit",
-        about = "Verify the integrity of a specific commit",
+        about = "Verify the integrity and signature of a specific commit",
         alias = "vc"
     )]
     VerifyCommit { commit_hash: String },
 
+    #[clap(
+        name = "keygen",
+        about = "Generate an ed25519 key for signing commits and print its public key"
+    )]
+    Keygen { path: PathBuf },
+
     #[clap(
         name = "list-commits",
         about = "List all commits in the repository",
-        alias = "lc"
+        aliases = ["lc", "log"]
     )]
-    ListCommits,
+    ListCommits {
+        /// Only list commits of the current branch that changed this file,
+        /// following it across renames
+        #[arg(long, value_name = "PATH")]
+        follow: Option<PathBuf>,
+        /// Check and print the signature of every commit
+        #[arg(long)]
+        show_signature: bool,
+    },
+
+    #[clap(name = "diff", about = "Show the files changed between two revisions")]
+    Diff {
+        from: String,
+        /// Defaults to HEAD
+        to: Option<String>,
+        /// Minimum similarity, in percent, for a file to count as renamed
+        #[arg(short = 'M', long = "find-renames", default_value_t = 50,
+              value_parser = clap::value_parser!(u8).range(0..=100))]
+        find_renames: u8,
+        /// Report renames as a deletion and an addition
+        #[arg(long)]
+        no_renames: bool,
+        /// Also detect files copied from ones that still exist
+        #[arg(short = 'C', long, conflicts_with = "no_renames")]
+        find_copies: bool,
+    },
+
+    #[clap(
+        name = "fsck",
+        about = "Check the connectivity and validity of the repository objects"
+    )]
+    Fsck {
+        /// Delete empty or truncated objects so they can be written again
+        #[arg(long)]
+        repair: bool,
+    },
+
+    #[clap(
+        name = "migrate-trees",
+        about = "Rewrite existing trees in canonical order (rewrites history)"
+    )]
+    MigrateTrees,
 
     #[clap(
         name = "mount",
@@ -112,3 +365,58 @@ pub enum Commands {
     #[cfg(unix)]
     Mount { mount_point: String },
 }
+
+#[derive(clap::Subcommand)]
+pub enum ReflogAction {
+    #[clap(about = "Drop old reflog entries")]
+    Expire {
+        /// Keep entries younger than this many days (0 drops all); defaults to
+        /// `[gc] reflog_expire_days`, or 90
+        #[arg(long, value_name = "DAYS")]
+        expire: Option<u64>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+pub enum RepoAction {
+    #[clap(about = "Record that the repository was moved to the current directory")]
+    Relocate,
+}
+
+#[derive(clap::Subcommand)]
+pub enum StoreAction {
+    #[clap(about = "Move the repository and the objects it needs to another store")]
+    Migrate {
+        /// New store, recorded in `gato.toml`; the default store if omitted
+        to: Option<PathBuf>,
+    },
+}
+
+#[derive(clap::Subcommand)]
+pub enum WorktreeAction {
+    #[clap(about = "Check a branch out in a new working directory")]
+    Add {
+        /// New working directory; created if missing, must be empty
+        path: PathBuf,
+        branch: String,
+    },
+    #[clap(about = "List the worktrees and the branch each has checked out")]
+    List,
+    #[clap(about = "Forget a linked worktree, leaving its files in place")]
+    Remove {
+        /// Name or path of the worktree
+        worktree: String,
+        /// Remove it even with staged changes or an operation in progress
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+fn parse_key_value(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got `{value}`")),
+    }
+}
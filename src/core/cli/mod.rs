@@ -1,4 +1,7 @@
-use std::{path::PathBuf, sync::OnceLock};
+use std::{
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 pub mod cli;
 use directories::ProjectDirs;
 
@@ -9,9 +12,12 @@ use crate::core::{
 };
 
 static GLOBAL_STORE_PATH: OnceLock<PathBuf> = OnceLock::new();
+/// The default object store: `$GATO_STORE`, else the user's data directory.
 pub fn get_store_path() -> &'static PathBuf {
     GLOBAL_STORE_PATH.get_or_init(|| {
-        if let Some(proj_dirs) = ProjectDirs::from("com", "gatocloud", "gato") {
+        if let Some(store) = std::env::var_os("GATO_STORE").filter(|store| !store.is_empty()) {
+            PathBuf::from(store)
+        } else if let Some(proj_dirs) = ProjectDirs::from("com", "gatocloud", "gato") {
             proj_dirs.data_local_dir().to_path_buf()
         } else {
             PathBuf::from(".gato")
@@ -19,15 +25,27 @@ pub fn get_store_path() -> &'static PathBuf {
     })
 }
 
-pub fn init(path: PathBuf) {
+/// The store of the repository in `work_dir`: its `[storage] path`, else the
+/// default store.
+pub fn store_for(work_dir: &Path) -> PathBuf {
+    load_config(&work_dir.to_path_buf())
+        .ok()
+        .and_then(|config| config.store_path(work_dir))
+        .unwrap_or_else(|| get_store_path().clone())
+}
+
+pub fn init(path: PathBuf, store: Option<PathBuf>) {
     let id = load_config(&path);
     match id {
         Ok(_) => {
             println!("Repo already initialized");
         }
         Err(_) => {
-            let storage = LocalStorage::new(get_store_path().clone(), new_id(), path);
-            match create_file_layout(&storage) {
+            let store_path = store
+                .as_ref()
+                .map_or(get_store_path().clone(), |s| path.join(s));
+            let storage = LocalStorage::new(store_path, new_id(), path);
+            match create_file_layout(&storage, store.as_deref()) {
                 Ok(()) => println!("initialized successfuly"),
                 Err(_) => {}
             }
@@ -35,15 +53,18 @@ pub fn init(path: PathBuf) {
     }
 }
 
-pub fn init_from(path: PathBuf, target_id: String) {
+pub fn init_from(path: PathBuf, target_id: String, store: Option<PathBuf>) {
     let id = load_config(&path);
     match id {
         Ok(_) => {
             println!("Repo already initialized");
         }
         Err(_) => {
-            let storage = LocalStorage::new(get_store_path().clone(), target_id, path);
-            match create_file_layout(&storage) {
+            let store_path = store
+                .as_ref()
+                .map_or(get_store_path().clone(), |s| path.join(s));
+            let storage = LocalStorage::new(store_path, target_id, path);
+            match create_file_layout(&storage, store.as_deref()) {
                 Ok(()) => println!("initialized successfuly"),
                 Err(_) => {}
             }
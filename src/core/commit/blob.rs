@@ -4,10 +4,7 @@ use bincode::{Decode, Encode, encode_to_vec};
 use tracing::instrument;
 
 use crate::core::{
-    add::chunker::IndexData,
-    commit::error::CommitError,
-    error::GatoResult,
-    storage::{StorageEngine, local::LocalStorage},
+    add::chunker::IndexData, commit::error::CommitError, error::GatoResult, storage::ObjectStore,
 };
 
 #[derive(Debug, Decode, Encode)]
@@ -17,7 +14,7 @@ pub enum Blob {
 }
 
 impl Blob {
-    pub fn new(hash: String, storage: &LocalStorage) -> GatoResult<Self> {
+    pub fn new(hash: String, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<Self> {
         let data = storage.get(&hash)?;
         let (decoded, _): (Self, usize) =
             bincode::decode_from_slice(&data, bincode::config::standard())?;
@@ -25,7 +22,11 @@ impl Blob {
     }
 
     #[instrument]
-    pub fn restore(self, path: PathBuf, storage: &LocalStorage) -> Result<(), CommitError> {
+    pub fn restore(
+        self,
+        path: PathBuf,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> Result<(), CommitError> {
         match self {
             Blob::Normal(content) => {
                 let decompressed_data = crate::core::add::decompress(&content).unwrap();
@@ -38,16 +39,30 @@ impl Blob {
         Ok(())
     }
     #[instrument]
-    pub fn restore_data(&self, storage: &LocalStorage) -> GatoResult<Vec<u8>> {
+    pub fn restore_data(&self, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<Vec<u8>> {
         match self {
             Blob::Normal(content) => {
                 return Ok(crate::core::add::decompress(&content)?);
             }
             Blob::ChunksMap(index_data) => {
-                return Ok(index_data.restore_data(&storage)?);
+                return Ok(index_data.restore_data(storage)?);
             }
         }
     }
+    /// Hashes of every object needed to restore the blob stored under `hash`:
+    /// the blob itself plus, for chunked files, each of its chunks.
+    #[instrument]
+    pub fn dependencies(
+        hash: String,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<Vec<String>> {
+        let mut deps = Vec::new();
+        if let Blob::ChunksMap(index_data) = Self::new(hash.clone(), storage)? {
+            deps.extend(index_data.path.iter().map(hex::encode));
+        }
+        deps.push(hash);
+        Ok(deps)
+    }
     #[instrument]
     pub fn encode(&self) -> Result<Vec<u8>, CommitError> {
         let bindata = encode_to_vec(self, bincode::config::standard())?;
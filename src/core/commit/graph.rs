@@ -0,0 +1,291 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use tracing::instrument;
+
+use crate::core::{
+    commit::{
+        Commit, Tree,
+        graph_file::{GraphEntry, GraphFile},
+        merge::MergeContext,
+    },
+    error::GatoResult,
+    storage::{ObjectStore, local::LocalStorage},
+};
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// Lazily loaded view of the commit DAG.
+///
+/// Parents are read from the commit-graph file, or from the commit objects for
+/// commits it does not know yet, and cached together with each commit's
+/// generation number: 1 for a root commit, otherwise one more than the highest
+/// generation among its parents. A commit can only be an ancestor of commits
+/// with a strictly higher generation, which lets the walks below stop early.
+#[derive(Debug)]
+pub struct CommitGraph<'a, S: ObjectStore + ?Sized = LocalStorage> {
+    storage: &'a S,
+    file: Option<GraphFile>,
+    parents: HashMap<String, Vec<String>>,
+    generations: HashMap<String, u64>,
+}
+
+impl<'a, S: ObjectStore + ?Sized> CommitGraph<'a, S> {
+    pub fn new(storage: &'a S) -> Self {
+        Self {
+            storage,
+            file: storage.graph_file(),
+            parents: HashMap::new(),
+            generations: HashMap::new(),
+        }
+    }
+
+    pub fn parents(&mut self, hash: &String) -> Vec<String> {
+        if let Some(parents) = self.parents.get(hash) {
+            return parents.clone();
+        }
+        let parents = match self.file.as_ref().and_then(|file| file.parents(hash)) {
+            Some(parents) => parents,
+            None => Commit::load(hash.clone(), self.storage).parents(),
+        };
+        self.parents.insert(hash.clone(), parents.clone());
+        parents
+    }
+
+    pub fn generation(&mut self, hash: &String) -> u64 {
+        let mut stack = vec![hash.clone()];
+        while let Some(current) = stack.last().cloned() {
+            if self.generations.contains_key(&current) {
+                stack.pop();
+                continue;
+            }
+            if let Some(generation) = self.file.as_ref().and_then(|f| f.generation(&current)) {
+                self.generations.insert(current, generation);
+                stack.pop();
+                continue;
+            }
+            let parents = self.parents(&current);
+            let missing: Vec<String> = parents
+                .iter()
+                .filter(|p| !self.generations.contains_key(*p))
+                .cloned()
+                .collect();
+            if missing.is_empty() {
+                let generation = parents
+                    .iter()
+                    .map(|p| self.generations[p])
+                    .max()
+                    .unwrap_or(0)
+                    + 1;
+                self.generations.insert(current, generation);
+                stack.pop();
+            } else {
+                stack.extend(missing);
+            }
+        }
+        self.generations[hash]
+    }
+
+    /// What the commit-graph file records, or would record, about `hash`.
+    pub fn entry(&mut self, hash: &String) -> GraphEntry {
+        if let Some(entry) = self.file.as_ref().and_then(|file| file.get(hash)) {
+            return entry;
+        }
+        let commit = Commit::load(hash.clone(), self.storage);
+        GraphEntry {
+            tree_hash: commit.tree_hash(),
+            timestamp: commit.committer().timestamp,
+            generation: self.generation(hash),
+            parents: self.parents(hash),
+        }
+    }
+
+    /// `head` followed by its first parent, that one's first parent and so on.
+    pub fn first_parents(&mut self, head: &str) -> Vec<String> {
+        let mut chain = vec![head.to_owned()];
+        while let Some(parent) = self.parents(chain.last().expect("non-empty")).first() {
+            chain.push(parent.clone());
+        }
+        chain
+    }
+
+    /// Every commit reachable from `heads`, following all parents, with parents
+    /// before their children.
+    #[instrument]
+    pub fn reachable(&mut self, heads: &[String]) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut order = Vec::new();
+        let mut stack: Vec<(String, bool)> = heads.iter().map(|h| (h.clone(), false)).collect();
+        while let Some((hash, expanded)) = stack.pop() {
+            if expanded {
+                order.push(hash);
+                continue;
+            }
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            stack.push((hash.clone(), true));
+            for parent in self.parents(&hash) {
+                if !seen.contains(&parent) {
+                    stack.push((parent, false));
+                }
+            }
+        }
+        order
+    }
+
+    /// Whether `ancestor` is reachable from `descendant` (a commit is its own ancestor).
+    #[instrument]
+    pub fn is_ancestor(&mut self, ancestor: &String, descendant: &String) -> bool {
+        let min_generation = self.generation(ancestor);
+        let mut seen = HashSet::new();
+        let mut stack = vec![descendant.clone()];
+        while let Some(hash) = stack.pop() {
+            if &hash == ancestor {
+                return true;
+            }
+            if !seen.insert(hash.clone()) || self.generation(&hash) <= min_generation {
+                continue;
+            }
+            stack.extend(self.parents(&hash));
+        }
+        false
+    }
+
+    /// Commits reachable from `head` but not from `upstream`, parents before
+    /// their children.
+    #[instrument]
+    pub fn only_in(&mut self, head: &String, upstream: &String) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![upstream.clone()];
+        while let Some(hash) = stack.pop() {
+            if seen.insert(hash.clone()) {
+                stack.extend(self.parents(&hash));
+            }
+        }
+
+        let mut commits = Vec::new();
+        let mut stack = vec![(head.clone(), false)];
+        while let Some((hash, visited)) = stack.pop() {
+            if visited {
+                commits.push(hash);
+                continue;
+            }
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            stack.push((hash.clone(), true));
+            // reversed so the first parent is walked first
+            for parent in self.parents(&hash).into_iter().rev() {
+                stack.push((parent, false));
+            }
+        }
+        commits
+    }
+
+    /// The best common ancestors of `ones` (taken together) and `two`.
+    ///
+    /// A common ancestor is "best" when it is not an ancestor of any other common
+    /// ancestor. Criss-cross histories can have several; they are returned with the
+    /// highest generation first.
+    #[instrument]
+    pub fn merge_bases(&mut self, ones: &[String], two: &String) -> Vec<String> {
+        let mut flags: HashMap<String, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        // how often each commit is queued, and how many queued entries are
+        // not stale yet: the walk is over once that reaches zero
+        let mut queued: HashMap<String, usize> = HashMap::new();
+        let mut active = 0usize;
+
+        for one in ones {
+            *flags.entry(one.clone()).or_default() |= PARENT1;
+            queue.push((self.generation(one), one.clone()));
+            *queued.entry(one.clone()).or_default() += 1;
+            active += 1;
+        }
+        *flags.entry(two.clone()).or_default() |= PARENT2;
+        queue.push((self.generation(two), two.clone()));
+        *queued.entry(two.clone()).or_default() += 1;
+        active += 1;
+
+        let mut candidates = Vec::new();
+        while active > 0 {
+            let Some((_, hash)) = queue.pop() else { break };
+            *queued.get_mut(&hash).expect("queued commit") -= 1;
+            if flags[&hash] & STALE == 0 {
+                active -= 1;
+            }
+            let mut current = flags[&hash] & (PARENT1 | PARENT2 | STALE);
+            if current == PARENT1 | PARENT2 {
+                let f = flags.get_mut(&hash).expect("flagged commit");
+                if *f & RESULT == 0 {
+                    *f |= RESULT;
+                    candidates.push(hash.clone());
+                }
+                current |= STALE;
+            }
+            for parent in self.parents(&hash) {
+                let f = flags.entry(parent.clone()).or_default();
+                if *f & current == current {
+                    continue;
+                }
+                let was_stale = *f & STALE != 0;
+                *f |= current;
+                let stale = *f & STALE != 0;
+                let count = queued.entry(parent.clone()).or_default();
+                if stale && !was_stale {
+                    active -= *count;
+                }
+                *count += 1;
+                if !stale {
+                    active += 1;
+                }
+                queue.push((self.generation(&parent), parent));
+            }
+        }
+
+        let mut bases: Vec<String> = Vec::new();
+        for candidate in &candidates {
+            let redundant = candidates
+                .iter()
+                .any(|other| other != candidate && self.is_ancestor(candidate, other));
+            if !redundant && !bases.contains(candidate) {
+                bases.push(candidate.clone());
+            }
+        }
+        bases.sort_by_cached_key(|b| (std::cmp::Reverse(self.generation(b)), b.clone()));
+        bases
+    }
+
+    /// The tree to use as the base of a three-way merge between `ones` and `two`.
+    ///
+    /// With a single best common ancestor this is simply its tree. When there are
+    /// several, they are merged into a virtual base first: each further base is
+    /// merged into the running result, using the merge base between the bases
+    /// merged so far and the new one. Without any common ancestor the base is empty.
+    #[instrument]
+    pub fn base_tree(&mut self, ones: &[String], two: &String) -> GatoResult<Tree> {
+        let bases = self.merge_bases(ones, two);
+        let Some((first, rest)) = bases.split_first() else {
+            return Ok(Tree::new("root".to_string()));
+        };
+
+        let storage = self.storage;
+        let mut tree = Self::tree_of(first, storage)?;
+        let mut merged = vec![first.clone()];
+        for next in rest {
+            let inner = self.base_tree(&merged, next)?;
+            let next_tree = Self::tree_of(next, storage)?;
+            tree = Tree::merge(inner, tree, next_tree, &mut MergeContext::new(), storage)?;
+            merged.push(next.clone());
+        }
+        Ok(tree)
+    }
+
+    fn tree_of(hash: &str, storage: &S) -> GatoResult<Tree> {
+        let commit = Commit::load(hash.to_owned(), storage);
+        Tree::load(hex::encode(commit.tree_hash()), storage)
+    }
+}
@@ -0,0 +1,337 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+
+use memmap2::Mmap;
+use tracing::instrument;
+
+use crate::core::{
+    commit::{Commit, graph::CommitGraph},
+    config::FsyncPolicy,
+    error::GatoResult,
+    storage::{local::LocalStorage, lock::LockFile},
+};
+
+const MAGIC: &[u8; 4] = b"GCGR";
+const VERSION: u32 = 2;
+const HEADER_LEN: usize = 16;
+/// hash, tree hash, timestamp, generation, first parent slot, parent count.
+const RECORD_LEN: usize = 32 + 32 + 8 + 8 + 4 + 4;
+const HASH_LEN: usize = 32;
+/// hash, tree hash, timestamp, generation, parent count; the parents follow.
+const TAIL_RECORD_LEN: usize = 32 + 32 + 8 + 8 + 4;
+/// The table is rewritten once the tail holds more than one commit for this
+/// many in the table, so rewriting stays linear in the length of history.
+const TAIL_SHARE: usize = 8;
+
+/// What the commit-graph file records about a commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphEntry {
+    pub tree_hash: Vec<u8>,
+    /// Committer time, in seconds since the epoch.
+    pub timestamp: i64,
+    pub generation: u64,
+    pub parents: Vec<String>,
+}
+
+/// The `commit-graph` file of a repository: a memory-mapped table of commits
+/// sorted by hash, so history can be walked without decoding commit objects.
+///
+/// After a 16-byte header (`GCGR`, version, record count, parent count) come
+/// fixed-size little-endian records, the parent hashes they point into and a
+/// blake3 checksum of everything before it. Commits saved since the table was
+/// written are appended to `commit-graph-tail`, each followed by its own
+/// checksum. The file is only a cache: commits missing from it are read from
+/// the store.
+#[derive(Debug)]
+pub struct GraphFile {
+    map: Mmap,
+    count: usize,
+    tail: HashMap<String, GraphEntry>,
+}
+
+impl GraphFile {
+    pub fn path(storage: &LocalStorage) -> PathBuf {
+        storage.repo_path().join("commit-graph")
+    }
+
+    pub fn tail_path(storage: &LocalStorage) -> PathBuf {
+        storage.repo_path().join("commit-graph-tail")
+    }
+
+    /// Map the commit-graph file, if there is a valid one, and read its tail.
+    pub fn open(storage: &LocalStorage) -> Option<Self> {
+        let file = File::open(Self::path(storage)).ok()?;
+        // Safety: the file is only ever replaced by renaming a new one over it,
+        // never modified in place, so the mapped bytes cannot change.
+        let map = unsafe { Mmap::map(&file) }.ok()?;
+        if map.len() < HEADER_LEN + HASH_LEN || &map[..4] != MAGIC || read_u32(&map, 4) != VERSION {
+            return None;
+        }
+        let count = read_u32(&map, 8) as usize;
+        let parents = read_u32(&map, 12) as usize;
+        let len = HEADER_LEN + count * RECORD_LEN + parents * HASH_LEN;
+        if map.len() != len + HASH_LEN || blake3::hash(&map[..len]).as_bytes() != &map[len..] {
+            return None;
+        }
+        let tail = fs::read(Self::tail_path(storage))
+            .map(|data| read_tail(&data))
+            .unwrap_or_default();
+        Some(Self { map, count, tail })
+    }
+
+    fn record(&self, index: usize) -> &[u8] {
+        let start = HEADER_LEN + index * RECORD_LEN;
+        &self.map[start..start + RECORD_LEN]
+    }
+
+    fn find(&self, hash: &str) -> Option<&[u8]> {
+        let hash = hex::decode(hash).ok()?;
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.record(mid)[..HASH_LEN].cmp(&hash[..]) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(self.record(mid)),
+            }
+        }
+        None
+    }
+
+    fn parents_of(&self, record: &[u8]) -> Vec<String> {
+        let first = read_u32(record, 80) as usize;
+        let count = read_u32(record, 84) as usize;
+        let base = HEADER_LEN + self.count * RECORD_LEN;
+        (first..first + count)
+            .map(|slot| hex::encode(&self.map[base + slot * HASH_LEN..][..HASH_LEN]))
+            .collect()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.tail.contains_key(hash) || self.find(hash).is_some()
+    }
+
+    pub fn parents(&self, hash: &str) -> Option<Vec<String>> {
+        self.get(hash).map(|entry| entry.parents)
+    }
+
+    pub fn generation(&self, hash: &str) -> Option<u64> {
+        match self.tail.get(hash) {
+            Some(entry) => Some(entry.generation),
+            None => self.find(hash).map(|record| read_u64(record, 72)),
+        }
+    }
+
+    pub fn get(&self, hash: &str) -> Option<GraphEntry> {
+        if let Some(entry) = self.tail.get(hash) {
+            return Some(entry.clone());
+        }
+        let record = self.find(hash)?;
+        Some(GraphEntry {
+            tree_hash: record[32..64].to_vec(),
+            timestamp: read_u64(record, 64) as i64,
+            generation: read_u64(record, 72),
+            parents: self.parents_of(record),
+        })
+    }
+
+    /// Every commit in the file and its tail, by hash.
+    pub fn entries(&self) -> BTreeMap<String, GraphEntry> {
+        let mut entries: BTreeMap<String, GraphEntry> = (0..self.count)
+            .map(|index| {
+                let hash = hex::encode(&self.record(index)[..HASH_LEN]);
+                let entry = self.get(&hash).expect("hash read from the table");
+                (hash, entry)
+            })
+            .collect();
+        entries.extend(self.tail.clone());
+        entries
+    }
+
+    /// Replace the commit-graph file with one holding `entries`, and drop the
+    /// tail.
+    #[instrument(skip(entries))]
+    pub fn write(storage: &LocalStorage, entries: &BTreeMap<String, GraphEntry>) -> GatoResult<()> {
+        let tail_lock = storage.lock(&Self::tail_path(storage))?;
+        Self::write_locked(storage, entries, tail_lock)
+    }
+
+    /// [`GraphFile::write`] under the tail lock, which keeps appends out until
+    /// the tail is dropped.
+    fn write_locked(
+        storage: &LocalStorage,
+        entries: &BTreeMap<String, GraphEntry>,
+        _tail_lock: LockFile,
+    ) -> GatoResult<()> {
+        let parent_count: usize = entries.values().map(|e| e.parents.len()).sum();
+        let mut data =
+            Vec::with_capacity(HEADER_LEN + entries.len() * RECORD_LEN + parent_count * HASH_LEN);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(parent_count as u32).to_le_bytes());
+        let mut parents = Vec::with_capacity(parent_count * HASH_LEN);
+        let mut slot = 0u32;
+        for (hash, entry) in entries {
+            data.extend_from_slice(&hex::decode(hash).unwrap_or_default());
+            data.extend_from_slice(&entry.tree_hash);
+            data.extend_from_slice(&entry.timestamp.to_le_bytes());
+            data.extend_from_slice(&entry.generation.to_le_bytes());
+            data.extend_from_slice(&slot.to_le_bytes());
+            data.extend_from_slice(&(entry.parents.len() as u32).to_le_bytes());
+            for parent in &entry.parents {
+                parents.extend_from_slice(&hex::decode(parent).unwrap_or_default());
+            }
+            slot += entry.parents.len() as u32;
+        }
+        data.extend_from_slice(&parents);
+        let checksum = blake3::hash(&data);
+        data.extend_from_slice(checksum.as_bytes());
+
+        storage.write_atomic(&Self::path(storage), &data)?;
+        match fs::remove_file(Self::tail_path(storage)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Append one commit to the tail, under its lock.
+    #[instrument(skip(entry, _tail_lock))]
+    fn append(
+        storage: &LocalStorage,
+        hash: &str,
+        entry: &GraphEntry,
+        _tail_lock: &LockFile,
+    ) -> GatoResult<()> {
+        let mut data = Vec::with_capacity(TAIL_RECORD_LEN + (entry.parents.len() + 1) * HASH_LEN);
+        data.extend_from_slice(&hex::decode(hash).unwrap_or_default());
+        data.extend_from_slice(&entry.tree_hash);
+        data.extend_from_slice(&entry.timestamp.to_le_bytes());
+        data.extend_from_slice(&entry.generation.to_le_bytes());
+        data.extend_from_slice(&(entry.parents.len() as u32).to_le_bytes());
+        for parent in &entry.parents {
+            data.extend_from_slice(&hex::decode(parent).unwrap_or_default());
+        }
+        let checksum = blake3::hash(&data);
+        data.extend_from_slice(checksum.as_bytes());
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::tail_path(storage))?;
+        file.write_all(&data)?;
+        if storage.fsync_policy() == FsyncPolicy::Always {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Rebuild the file from every commit reachable from a branch or from
+    /// `extra`, dropping commits that are no longer reachable.
+    #[instrument]
+    pub fn rebuild(storage: &LocalStorage, extra: &[String]) -> GatoResult<usize> {
+        let tail_lock = storage.lock(&Self::tail_path(storage))?;
+        Self::rebuild_locked(storage, extra, tail_lock)
+    }
+
+    /// [`GraphFile::rebuild`] under the tail lock.
+    fn rebuild_locked(
+        storage: &LocalStorage,
+        extra: &[String],
+        tail_lock: LockFile,
+    ) -> GatoResult<usize> {
+        let mut heads = extra.to_vec();
+        for branch in storage.list_branchs()? {
+            if let Ok(hash) = storage.read_ref_vec(branch) {
+                heads.push(hex::encode(hash));
+            }
+        }
+        let mut graph = CommitGraph::new(storage);
+        let mut entries = BTreeMap::new();
+        for hash in graph.reachable(&heads) {
+            let entry = graph.entry(&hash);
+            entries.insert(hash, entry);
+        }
+        Self::write_locked(storage, &entries, tail_lock)?;
+        Ok(entries.len())
+    }
+
+    /// Record a newly stored commit in the tail, folding the tail into the
+    /// table once it has grown. Without a commit-graph file yet, one is built
+    /// for the whole history. The tail lock is taken before the file is read,
+    /// so concurrent commits each see the other's entry.
+    #[instrument(skip(commit))]
+    pub fn add(storage: &LocalStorage, hash: &str, commit: &Commit) -> GatoResult<()> {
+        let tail_lock = storage.lock(&Self::tail_path(storage))?;
+        let Some(file) = Self::open(storage) else {
+            Self::rebuild_locked(storage, &[hash.to_string()], tail_lock)?;
+            return Ok(());
+        };
+        if file.contains(hash) {
+            return Ok(());
+        }
+        let mut graph = CommitGraph::new(storage);
+        let parents = commit.parents();
+        let generation = parents
+            .iter()
+            .map(|parent| graph.generation(parent))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let entry = GraphEntry {
+            tree_hash: commit.tree_hash(),
+            timestamp: commit.committer().timestamp,
+            generation,
+            parents,
+        };
+        if (file.tail.len() + 1) * TAIL_SHARE <= file.count {
+            return Self::append(storage, hash, &entry, &tail_lock);
+        }
+        let mut entries = file.entries();
+        entries.insert(hash.to_string(), entry);
+        drop(file);
+        Self::write_locked(storage, &entries, tail_lock)
+    }
+}
+
+/// The commits of a tail file, up to the first one that is cut short or does
+/// not match its checksum.
+fn read_tail(data: &[u8]) -> HashMap<String, GraphEntry> {
+    let mut entries = HashMap::new();
+    let mut at = 0;
+    while data.len() >= at + TAIL_RECORD_LEN {
+        let record = &data[at..];
+        let parent_count = read_u32(record, 80) as usize;
+        let len = TAIL_RECORD_LEN + parent_count * HASH_LEN;
+        if record.len() < len + HASH_LEN
+            || blake3::hash(&record[..len]).as_bytes() != &record[len..len + HASH_LEN]
+        {
+            break;
+        }
+        let parents = record[TAIL_RECORD_LEN..len]
+            .chunks(HASH_LEN)
+            .map(hex::encode)
+            .collect();
+        let entry = GraphEntry {
+            tree_hash: record[32..64].to_vec(),
+            timestamp: read_u64(record, 64) as i64,
+            generation: read_u64(record, 72),
+            parents,
+        };
+        entries.insert(hex::encode(&record[..HASH_LEN]), entry);
+        at += len + HASH_LEN;
+    }
+    entries
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().expect("4 bytes"))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().expect("8 bytes"))
+}
@@ -0,0 +1,148 @@
+use std::fmt::Display;
+
+use bincode::{Decode, Encode};
+use chrono::{DateTime, FixedOffset, Local};
+
+use crate::core::{
+    error::{Error, GatoResult},
+    storage::StorageEngine,
+};
+
+/// Who made a commit and when, in their own time zone.
+#[derive(Encode, Decode, Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: Option<String>,
+    /// Seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Offset from UTC in minutes, e.g. `120` for `+0200`.
+    pub utc_offset: i32,
+}
+
+/// Which identity of a commit is meant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Author,
+    Committer,
+}
+
+impl Role {
+    fn var(self, field: &str) -> String {
+        match self {
+            Role::Author => format!("GATO_AUTHOR_{field}"),
+            Role::Committer => format!("GATO_COMMITTER_{field}"),
+        }
+    }
+}
+
+impl Identity {
+    /// The identity for `role` at the current time, from the backend's user
+    /// (`gato.toml` for a local store) overridden by `GATO_AUTHOR_NAME`, `GATO_AUTHOR_EMAIL`, `GATO_AUTHOR_DATE`
+    /// (or their `GATO_COMMITTER_*` counterparts).
+    pub fn from_env(role: Role, storage: &(impl StorageEngine + ?Sized)) -> GatoResult<Self> {
+        Self::from_vars(role, storage, |name| std::env::var(name).ok())
+    }
+
+    /// Like [`Identity::from_env`], reading the variables through `var`.
+    pub fn from_vars(
+        role: Role,
+        storage: &(impl StorageEngine + ?Sized),
+        var: impl Fn(&str) -> Option<String>,
+    ) -> GatoResult<Self> {
+        let (name, email) = storage.user()?;
+        let (timestamp, utc_offset) = match var(&role.var("DATE")) {
+            Some(date) => parse_date(&date).ok_or(Error::InvalidDate(date))?,
+            None => {
+                let now = Local::now();
+                (now.timestamp(), now.offset().local_minus_utc() / 60)
+            }
+        };
+        Ok(Self {
+            name: var(&role.var("NAME")).unwrap_or(name),
+            email: var(&role.var("EMAIL")).or(email),
+            timestamp,
+            utc_offset,
+        })
+    }
+
+    /// Parse `Name <email>` (or just `Name`), as given to `--author`, keeping
+    /// the time of `self`.
+    pub fn with_person(mut self, person: &str) -> GatoResult<Self> {
+        let invalid = || Error::InvalidIdentity(person.to_string());
+        match person.split_once('<') {
+            Some((name, rest)) => {
+                let email = rest.strip_suffix('>').ok_or_else(invalid)?.trim();
+                self.name = name.trim().to_string();
+                self.email = (!email.is_empty()).then(|| email.to_string());
+            }
+            None => self.name = person.trim().to_string(),
+        }
+        if self.name.is_empty() {
+            return Err(invalid());
+        }
+        Ok(self)
+    }
+
+    /// An identity of a commit made before time zones were recorded.
+    pub(crate) fn legacy(name: &str, email: &Option<String>, timestamp: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            email: email.clone(),
+            timestamp: timestamp as i64,
+            utc_offset: 0,
+        }
+    }
+
+    /// The time of the identity in its own time zone.
+    pub fn date(&self) -> Option<DateTime<FixedOffset>> {
+        let offset = FixedOffset::east_opt(self.utc_offset * 60)?;
+        Some(DateTime::from_timestamp(self.timestamp, 0)?.with_timezone(&offset))
+    }
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        if let Some(email) = &self.email {
+            write!(f, " <{email}>")?;
+        }
+        match self.date() {
+            Some(date) => write!(f, " {}", date.format("%Y-%m-%d %H:%M:%S %z")),
+            None => write!(f, " @{}", self.timestamp),
+        }
+    }
+}
+
+/// Parse a date given as `<seconds> <+hhmm>` (optionally prefixed with `@`),
+/// RFC 3339 or RFC 2822, into seconds since the epoch and a UTC offset in minutes.
+pub fn parse_date(date: &str) -> Option<(i64, i32)> {
+    let date = date.trim();
+    if let Ok(parsed) = DateTime::parse_from_rfc3339(date) {
+        return Some((parsed.timestamp(), parsed.offset().local_minus_utc() / 60));
+    }
+    if let Ok(parsed) = DateTime::parse_from_rfc2822(date) {
+        return Some((parsed.timestamp(), parsed.offset().local_minus_utc() / 60));
+    }
+    let mut parts = date.trim_start_matches('@').split_whitespace();
+    let timestamp = parts.next()?.parse().ok()?;
+    let utc_offset = match parts.next() {
+        Some(offset) => parse_offset(offset)?,
+        None => 0,
+    };
+    parts.next().is_none().then_some((timestamp, utc_offset))
+}
+
+/// `+hhmm` or `-hhmm` in minutes.
+pub(crate) fn parse_offset(offset: &str) -> Option<i32> {
+    let (sign, digits) = match offset.split_at_checked(1)? {
+        ("+", digits) => (1, digits),
+        ("-", digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
@@ -0,0 +1,418 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+
+use clap::ValueEnum;
+use diffy::{ConflictStyle, merge};
+use tracing::instrument;
+
+use crate::core::{
+    add::{
+        add_file_dry,
+        index::{ConflictEntry, ConflictKind},
+    },
+    commit::{Tree, TreeEntry, blob::Blob},
+    diff::{Change, RenameOptions, diff_files},
+    error::GatoResult,
+    storage::ObjectStore,
+};
+
+/// How hunks changed differently on both sides of a text file are settled (`-X`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum MergeStrategy {
+    /// Leave conflict markers in the file and record a conflict.
+    #[default]
+    Normal,
+    /// Keep our version of each conflicting hunk.
+    Ours,
+    /// Keep their version of each conflicting hunk.
+    Theirs,
+    /// Keep both versions of each conflicting hunk, ours first.
+    Union,
+}
+
+/// One side of a merge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Side {
+    Ours,
+    Theirs,
+}
+
+/// Options for [`Tree::merge`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MergeOptions {
+    pub strategy: MergeStrategy,
+    /// Side to take for binary files changed on both sides. Without it they only
+    /// resolve under the `ours` and `theirs` strategies.
+    pub binary: Option<Side>,
+}
+
+/// Bookkeeping shared by every level of a recursive [`Tree::merge`].
+#[derive(Debug, Default)]
+pub struct MergeContext {
+    /// Directory of the trees being merged, relative to the root tree.
+    path: PathBuf,
+    options: MergeOptions,
+    /// Paths that could not be merged automatically, with their three versions.
+    pub conflicts: BTreeMap<PathBuf, ConflictEntry>,
+}
+
+impl MergeContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_options(options: MergeOptions) -> Self {
+        Self {
+            options,
+            ..Self::default()
+        }
+    }
+
+    fn conflict(
+        &mut self,
+        name: &str,
+        kind: ConflictKind,
+        base: Option<Vec<u8>>,
+        ours: Option<&Vec<u8>>,
+        theirs: Option<&Vec<u8>>,
+    ) {
+        let entry = ConflictEntry {
+            kind,
+            base,
+            ours: ours.cloned(),
+            theirs: theirs.cloned(),
+        };
+        self.conflicts.insert(self.path.join(name), entry);
+    }
+}
+
+impl Tree {
+    /// Three-way merge of `current` and `target` against their common `base`.
+    ///
+    /// A file one side moved (see [`diff_files`]) is first moved on the other
+    /// side and in the base too, so edits made to it under the old name follow
+    /// it to the new one. Then, per entry: text files changed on both sides are
+    /// merged line by line and hunks that still collide are settled by the
+    /// context's [`MergeStrategy`], or kept with conflict markers. Binary files
+    /// changed on both sides take the side picked by the options, or keep the
+    /// current version. A file deleted on one side and changed on the other
+    /// keeps the changed version, and a file facing a directory is moved aside
+    /// to `<name>~ours` or `<name>~theirs`. Whatever cannot be settled is
+    /// recorded in `ctx.conflicts`.
+    #[instrument]
+    pub fn merge(
+        base: Tree,
+        current: Tree,
+        target: Tree,
+        ctx: &mut MergeContext,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<Tree> {
+        let (base, current, target) = Self::follow_renames(base, current, target, storage)?;
+        Self::merge_entries(base, current, target, ctx, storage)
+    }
+
+    fn merge_entries(
+        base: Tree,
+        current: Tree,
+        target: Tree,
+        ctx: &mut MergeContext,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<Tree> {
+        let mut result_tree = Tree::new(current.name.clone());
+        let mut all_filenames = BTreeSet::new();
+        for e in current
+            .entries
+            .iter()
+            .chain(&target.entries)
+            .chain(&base.entries)
+        {
+            all_filenames.insert(e.name().clone());
+        }
+        for name in all_filenames {
+            let b = base.get_entry(&name);
+            let c = current.get_entry(&name);
+            let t = target.get_entry(&name);
+
+            // Clean cases: both sides agree, or only one side changed the entry
+            // (a missing entry on the changed side is a clean deletion).
+            if c == t || t == b || c == b {
+                let kept = if c == b { t } else { c };
+                if let Some(entry) = kept {
+                    result_tree.add_entry(entry);
+                }
+                continue;
+            }
+
+            let base_blob = match &b {
+                Some(TreeEntry::Blob(_, hash)) => Some(hash.clone()),
+                _ => None,
+            };
+            match (c, t) {
+                (Some(TreeEntry::Blob(_, ours)), Some(TreeEntry::Blob(_, theirs))) => {
+                    let entry = Self::merge_blob(name, base_blob, ours, theirs, ctx, storage)?;
+                    result_tree.add_entry(entry);
+                }
+                (Some(TreeEntry::Blob(_, ours)), None) => {
+                    ctx.conflict(
+                        &name,
+                        ConflictKind::DeletedByThem,
+                        base_blob,
+                        Some(&ours),
+                        None,
+                    );
+                    result_tree.add_entry(TreeEntry::Blob(name, ours));
+                }
+                (None, Some(TreeEntry::Blob(_, theirs))) => {
+                    ctx.conflict(
+                        &name,
+                        ConflictKind::DeletedByUs,
+                        base_blob,
+                        None,
+                        Some(&theirs),
+                    );
+                    result_tree.add_entry(TreeEntry::Blob(name, theirs));
+                }
+                (Some(TreeEntry::Blob(_, ours)), Some(TreeEntry::Tree(_, theirs))) => {
+                    let moved = format!("{name}~ours");
+                    ctx.conflict(
+                        &moved,
+                        ConflictKind::FileDirectory,
+                        base_blob,
+                        Some(&ours),
+                        None,
+                    );
+                    result_tree.add_entry(TreeEntry::Blob(moved, ours));
+                    let subtree = Self::merge_subtree(&name, b, None, Some(theirs), ctx, storage)?;
+                    result_tree.add_entry(subtree.into_entry());
+                }
+                (Some(TreeEntry::Tree(_, ours)), Some(TreeEntry::Blob(_, theirs))) => {
+                    let moved = format!("{name}~theirs");
+                    let kind = ConflictKind::FileDirectory;
+                    ctx.conflict(&moved, kind, base_blob, None, Some(&theirs));
+                    result_tree.add_entry(TreeEntry::Blob(moved, theirs));
+                    let subtree = Self::merge_subtree(&name, b, Some(ours), None, ctx, storage)?;
+                    result_tree.add_entry(subtree.into_entry());
+                }
+                (ours, theirs) => {
+                    let ours = ours.and_then(|e| e.as_tree_hash());
+                    let theirs = theirs.and_then(|e| e.as_tree_hash());
+                    let subtree = Self::merge_subtree(&name, b, ours, theirs, ctx, storage)?;
+                    if !subtree.entries.is_empty() {
+                        result_tree.add_entry(subtree.into_entry());
+                    }
+                }
+            }
+        }
+        result_tree.save(storage);
+        Ok(result_tree)
+    }
+
+    /// Merge the directory `name`; a side without a directory there counts as empty.
+    fn merge_subtree(
+        name: &String,
+        base: Option<TreeEntry>,
+        ours: Option<Vec<u8>>,
+        theirs: Option<Vec<u8>>,
+        ctx: &mut MergeContext,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<Tree> {
+        let load = |hash: Option<Vec<u8>>| match hash {
+            Some(hash) => Tree::load(hex::encode(hash), storage),
+            None => Ok(Tree::new(name.clone())),
+        };
+        let base_tree = load(base.and_then(|e| e.as_tree_hash()))?;
+        let (current_tree, target_tree) = (load(ours)?, load(theirs)?);
+        ctx.path.push(name);
+        let merged = Self::merge_entries(base_tree, current_tree, target_tree, ctx, storage);
+        ctx.path.pop();
+        merged
+    }
+
+    /// Apply each side's renames to the other side and to the base, when the
+    /// other side still has the file at its old path.
+    fn follow_renames(
+        base: Tree,
+        current: Tree,
+        target: Tree,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<(Tree, Tree, Tree)> {
+        let base_files = base.files(storage)?;
+        let mut current_files = current.files(storage)?;
+        let mut target_files = target.files(storage)?;
+        let ours_moves = renames(&base_files, &current_files, storage)?;
+        let theirs_moves = renames(&base_files, &target_files, storage)?;
+        if ours_moves.is_empty() && theirs_moves.is_empty() {
+            return Ok((base, current, target));
+        }
+
+        let mut new_base = base_files.clone();
+        for (moves, other) in [
+            (ours_moves, &mut target_files),
+            (theirs_moves, &mut current_files),
+        ] {
+            for (from, to) in moves {
+                if other.contains_key(&to) || new_base.contains_key(&to) {
+                    continue;
+                }
+                let Some(hash) = other.remove(&from) else {
+                    continue;
+                };
+                other.insert(to.clone(), hash);
+                if let Some(hash) = new_base.remove(&from) {
+                    new_base.insert(to, hash);
+                }
+            }
+        }
+        Ok((
+            Tree::from_files(new_base, storage)?,
+            Tree::from_files(current_files, storage)?,
+            Tree::from_files(target_files, storage)?,
+        ))
+    }
+
+    /// Merge a file changed on both sides, recording a conflict when needed.
+    fn merge_blob(
+        name: String,
+        base: Option<Vec<u8>>,
+        ours: Vec<u8>,
+        theirs: Vec<u8>,
+        ctx: &mut MergeContext,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<TreeEntry> {
+        let kind = match base {
+            Some(_) => ConflictKind::Content,
+            None => ConflictKind::AddAdd,
+        };
+
+        let (Ok(current_file), Ok(target_file)) =
+            (blob_text(&ours, storage), blob_text(&theirs, storage))
+        else {
+            let side = match (ctx.options.binary, ctx.options.strategy) {
+                (Some(side), _) => Some(side),
+                (None, MergeStrategy::Ours) => Some(Side::Ours),
+                (None, MergeStrategy::Theirs) => Some(Side::Theirs),
+                _ => None,
+            };
+            return Ok(match side {
+                Some(Side::Ours) => TreeEntry::Blob(name, ours),
+                Some(Side::Theirs) => TreeEntry::Blob(name, theirs),
+                None => {
+                    ctx.conflict(&name, kind, base, Some(&ours), Some(&theirs));
+                    TreeEntry::Blob(name, ours)
+                }
+            });
+        };
+
+        let base_content = base
+            .as_ref()
+            .and_then(|hash| blob_text(hash, storage).ok())
+            .unwrap_or_default();
+
+        let merged = match ctx.options.strategy {
+            MergeStrategy::Normal => merge(&base_content, &current_file, &target_file),
+            strategy => merge_favoring(&base_content, &current_file, &target_file, strategy),
+        };
+        match merged {
+            Ok(merged) => {
+                let hash = add_file_dry(merged.as_bytes(), storage)?;
+                Ok(TreeEntry::Blob(name, hash))
+            }
+            Err(conflict_content) => {
+                let hash = add_file_dry(conflict_content.as_bytes(), storage)?;
+                ctx.conflict(&name, kind, base, Some(&ours), Some(&theirs));
+                Ok(TreeEntry::Blob(name, hash))
+            }
+        }
+    }
+}
+
+/// The content of the blob `hash`, failing for files that are not UTF-8 text.
+fn blob_text(hash: &[u8], storage: &(impl ObjectStore + ?Sized)) -> GatoResult<String> {
+    let blob = Blob::new(hex::encode(hash), storage)?;
+    Ok(String::from_utf8(blob.restore_data(storage)?)?)
+}
+
+/// Files `side` moved away from `base` as `(old, new)` pairs, possibly edited.
+fn renames(
+    base: &BTreeMap<PathBuf, Vec<u8>>,
+    side: &BTreeMap<PathBuf, Vec<u8>>,
+    storage: &(impl ObjectStore + ?Sized),
+) -> GatoResult<Vec<(PathBuf, PathBuf)>> {
+    let changes = diff_files(base, side, RenameOptions::default(), storage)?;
+    Ok(changes
+        .into_iter()
+        .filter_map(|change| match change {
+            Change::Renamed { from, to, .. } => Some((from, to)),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Line merge that settles every conflicting hunk according to `strategy`.
+///
+/// diffy only reports conflicts as marked-up text, so the merge is rendered with
+/// markers longer than any marker-like line in the inputs and the hunks are
+/// then rewritten. Falls back to the marked-up text if it cannot be parsed.
+fn merge_favoring(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    strategy: MergeStrategy,
+) -> Result<String, String> {
+    let longest_marker = [base, ours, theirs]
+        .iter()
+        .flat_map(|text| text.lines())
+        .map(|line| {
+            let first = line.chars().next().unwrap_or(' ');
+            match first {
+                '<' | '=' | '>' => line.chars().take_while(|c| *c == first).count(),
+                _ => 0,
+            }
+        })
+        .max()
+        .unwrap_or(0);
+    let len = longest_marker.max(6) + 1;
+    let conflicted = match diffy::MergeOptions::new()
+        .set_conflict_marker_length(len)
+        .set_conflict_style(ConflictStyle::Merge)
+        .merge(base, ours, theirs)
+    {
+        Ok(merged) => return Ok(merged),
+        Err(conflicted) => conflicted,
+    };
+
+    let start = format!("{} ours\n", "<".repeat(len));
+    let middle = format!("{}\n", "=".repeat(len));
+    let end = format!("{} theirs\n", ">".repeat(len));
+    let mut result = String::new();
+    let (mut our_hunk, mut their_hunk) = (String::new(), String::new());
+    let mut section = None;
+    for line in conflicted.split_inclusive('\n') {
+        match section {
+            None if line == start => section = Some(Side::Ours),
+            None => result.push_str(line),
+            Some(Side::Ours) if line == middle => section = Some(Side::Theirs),
+            Some(Side::Ours) => our_hunk.push_str(line),
+            Some(Side::Theirs) if line == end => {
+                match strategy {
+                    MergeStrategy::Ours => result.push_str(&our_hunk),
+                    MergeStrategy::Theirs => result.push_str(&their_hunk),
+                    _ => {
+                        result.push_str(&our_hunk);
+                        result.push_str(&their_hunk);
+                    }
+                }
+                our_hunk.clear();
+                their_hunk.clear();
+                section = None;
+            }
+            Some(Side::Theirs) => their_hunk.push_str(line),
+        }
+    }
+    match section {
+        None => Ok(result),
+        Some(_) => Err(conflicted),
+    }
+}
@@ -0,0 +1,59 @@
+use std::{fs, process::Command};
+
+use tracing::instrument;
+
+use crate::core::{
+    error::{Error, GatoResult},
+    storage::local::LocalStorage,
+};
+
+const EDIT_HINT: &str = "\n# Please enter the commit message for your changes. Lines starting\n# with '#' will be ignored, and an empty message aborts the commit.\n";
+
+/// Tidy up a commit message: trailing whitespace is trimmed, runs of blank
+/// lines are collapsed and leading/trailing blank lines dropped. With
+/// `strip_comments`, lines starting with `#` are removed first.
+pub fn cleanup(message: &str, strip_comments: bool) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in message.lines() {
+        if strip_comments && line.starts_with('#') {
+            continue;
+        }
+        let line = line.trim_end();
+        if line.is_empty() && lines.last().is_none_or(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
+
+/// Let the user write a commit message in `$GATO_EDITOR`, `$VISUAL` or
+/// `$EDITOR` (falling back to `vi`), starting from `initial`.
+///
+/// The message goes through `COMMIT_EDITMSG` in the repository state and is
+/// cleaned up, comments included; an empty result aborts.
+#[instrument]
+pub fn edit(initial: &str, storage: &LocalStorage) -> GatoResult<String> {
+    let path = storage.state_file("COMMIT_EDITMSG");
+    fs::write(&path, format!("{initial}\n{EDIT_HINT}"))?;
+
+    let editor = ["GATO_EDITOR", "VISUAL", "EDITOR"]
+        .into_iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.trim().is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    let status = Command::new(program).args(words).arg(&path).status()?;
+    if !status.success() {
+        return Err(Error::EditorFailed(editor));
+    }
+
+    let message = cleanup(&fs::read_to_string(&path)?, true);
+    if message.is_empty() {
+        return Err(Error::EmptyCommitMessage);
+    }
+    Ok(message)
+}
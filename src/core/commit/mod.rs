@@ -1,5 +1,5 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     fmt::Display,
     fs::{self},
     path::{Path, PathBuf},
@@ -11,18 +11,27 @@ use bincode::{
     decode_from_slice, encode_to_vec,
 };
 use blake3::hash;
-use diffy::merge;
-use tracing::instrument;
+use tracing::{instrument, warn};
 
 use crate::core::{
-    add::{add_file_dry, index::Index},
-    commit::{blob::Blob, error::CommitError},
-    config::load::load_config,
+    add::index::Index,
+    commit::{
+        blob::Blob,
+        error::CommitError,
+        graph::CommitGraph,
+        identity::{Identity, Role},
+    },
     error::{Error, GatoResult},
-    storage::{StorageEngine, local::LocalStorage},
+    storage::{ObjectStore, StorageEngine},
 };
 pub mod blob;
 pub mod error;
+pub mod graph;
+pub mod graph_file;
+pub mod identity;
+pub mod merge;
+pub mod message;
+pub mod signature;
 
 #[derive(Encode, Decode, Debug, Clone)]
 pub enum Commit {
@@ -45,6 +54,17 @@ pub enum Commit {
         parent_hash2: Vec<u8>,
         dependencies: Vec<String>,
     },
+    /// Any number of parents, first parent first, and extra `headers` such as
+    /// signatures, trailers or the message encoding. New commits use this.
+    V2 {
+        message: String,
+        author: Identity,
+        committer: Identity,
+        tree_hash: Vec<u8>,
+        parents: Vec<Vec<u8>>,
+        dependencies: Vec<String>,
+        headers: BTreeMap<String, String>,
+    },
 }
 
 impl Display for Commit {
@@ -115,13 +135,40 @@ impl Display for Commit {
                     deps_str
                 )
             }
+            Commit::V2 {
+                message,
+                author,
+                committer,
+                tree_hash,
+                parents,
+                dependencies,
+                headers,
+            } => {
+                write!(
+                    f,
+                    "Commit (V2):\nMessage: {}\nAuthor: {}\nCommitter: {}\nTree Hash: {}\nParents: {}\n",
+                    message,
+                    author,
+                    committer,
+                    hex::encode(tree_hash),
+                    parents
+                        .iter()
+                        .map(hex::encode)
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                )?;
+                for (key, value) in headers {
+                    writeln!(f, "{key}: {value}")?;
+                }
+                writeln!(f, "Dependencies: {}", dependencies.join(", "))
+            }
         }
     }
 }
 
 impl Commit {
-    pub fn verify(&self, storage: &LocalStorage) -> GatoResult<Vec<String>> {
-        let store_files = storage.list_files()?;
+    pub fn verify(&self, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<Vec<String>> {
+        let store_files = storage.list_objects()?;
 
         let commit_deps = self.dependices();
         let mut needed_files = Vec::new();
@@ -138,14 +185,20 @@ impl Commit {
         Ok(needed_files)
     }
 
-    pub fn verify_commit(&self, storage: &LocalStorage) -> GatoResult<bool> {
+    pub fn verify_commit(&self, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<bool> {
         Ok(self.verify(storage)?.is_empty())
     }
 
+    /// The first line of the message.
+    pub fn subject(&self) -> &str {
+        self.message().lines().next().unwrap_or_default()
+    }
+
     pub fn message(&self) -> &String {
         match self {
-            Commit::V1 { message, .. } => message,
-            Commit::MergedCommitV1 { message, .. } => message,
+            Commit::V1 { message, .. }
+            | Commit::MergedCommitV1 { message, .. }
+            | Commit::V2 { message, .. } => message,
         }
     }
 
@@ -157,17 +210,54 @@ impl Commit {
         Ok(hash_hex)
     }
 
+    /// Store the commit and move the active branch to it, logging a `commit`
+    /// (or `commit (merge)`) entry in the reflog.
+    #[instrument]
+    pub fn save(&self, storage: &(impl StorageEngine + ?Sized)) -> Result<(), CommitError> {
+        let kind = match self.parents().len() {
+            0 => "commit (initial)",
+            1 => "commit",
+            _ => "commit (merge)",
+        };
+        self.save_as(storage, &format!("{kind}: {}", self.subject()))
+    }
+
+    /// Like [`Commit::save`], with the reflog entry given as `reason`. Fails
+    /// if the branch no longer points at the first parent.
     #[instrument]
-    pub fn save(&self, storage: &LocalStorage) -> Result<(), CommitError> {
+    pub fn save_as(
+        &self,
+        storage: &(impl StorageEngine + ?Sized),
+        reason: &str,
+    ) -> Result<(), CommitError> {
+        self.save_over(storage, self.parents().first().map(String::as_str), reason)
+    }
+
+    /// Store the commit and move the active branch to it from `replaced`, for
+    /// commits that take the place of the branch tip, such as an amend.
+    #[instrument]
+    pub fn save_over(
+        &self,
+        storage: &(impl StorageEngine + ?Sized),
+        replaced: Option<&str>,
+        reason: &str,
+    ) -> Result<(), CommitError> {
+        let hash_bytes = self.put(storage)?;
+        storage.add_to_graph(&hex::encode(&hash_bytes), self);
+        storage.update_ref_from(storage.head(), replaced, hash_bytes, reason)?;
+        Ok(())
+    }
+
+    /// Store the commit object without moving any ref, returning its hash.
+    #[instrument]
+    pub fn put(&self, storage: &(impl ObjectStore + ?Sized)) -> Result<Vec<u8>, CommitError> {
         let data = encode_to_vec(self, config::standard())?;
 
         let hash = hash(&data);
         let hash_hex = hash.to_hex().to_string();
-        let hash_bytes = hash.as_bytes().to_vec();
 
         storage.put(&hash_hex, data)?;
-        storage.write_ref(storage.get_active_branche(), hash_bytes)?;
-        Ok(())
+        Ok(hash.as_bytes().to_vec())
     }
     #[instrument]
     // pub fn compute_hash(&self) -> String {
@@ -176,84 +266,71 @@ impl Commit {
     //     hash.to_hex().to_string()
     // }
     #[instrument]
-    pub fn parents_hashes(&self, storage: &LocalStorage) -> Vec<String> {
-        let mut parents = Vec::new();
-        let mut c = self.clone();
-        while let Some(hash) = c.parent_hash() {
-            c = Self::load(hash.clone(), storage);
-            parents.push(hash);
-        }
-        parents
+    pub fn get_parent_hash(storage: &(impl StorageEngine + ?Sized)) -> Option<Vec<u8>> {
+        let hash = storage.read_ref(storage.head()).ok();
+        hash
     }
+    /// Build the tree for the next commit from the staged index, returning its hash
+    /// and the objects it depends on. Fails while merge conflicts are unresolved.
     #[instrument]
-    pub fn base(commit_a: &Self, commit_b: &Self, storage: &LocalStorage) -> Option<Self> {
-        let parents = commit_a.parents_hashes(storage);
-        let parents_b = commit_b.parents_hashes(storage);
-
-        // println!("{parents:?} \n {parents_b:?}");
-
-        for hash in parents_b {
-            if parents.contains(&hash) {
-                let commit = Self::load(hash, &storage);
-                return Some(commit);
-            }
+    pub fn tree_from_index(
+        storage: &(impl StorageEngine + ?Sized),
+    ) -> GatoResult<(Vec<u8>, Vec<String>)> {
+        let index = Index::load(storage).map_err(|_| Error::NoFilesAddedError)?;
+        if !index.conflicts.is_empty() {
+            return Err(Error::UnresolvedConflicts(index.conflicts.len()));
         }
-
-        None
+        Ok(Tree::create_from_index(index, storage))
     }
     #[instrument]
-    pub fn get_parent_hash(storage: &LocalStorage) -> Option<Vec<u8>> {
-        let hash = storage.read_ref_vec(storage.get_active_branche()).ok();
-        hash
+    pub fn new(message: String, storage: &(impl StorageEngine + ?Sized)) -> GatoResult<Self> {
+        let (tree_hash, dependencies) = Self::tree_from_index(storage)?;
+        Self::from_tree(message, tree_hash, dependencies, storage)
     }
+    /// A commit of `tree_hash` on top of the current branch.
     #[instrument]
-    pub fn new(message: String, storage: &LocalStorage) -> GatoResult<Self> {
-        let (tree_hash, dependencies) = Tree::create_from_index(
-            Index::load(&storage).map_err(|_| Error::NoFilesAddedError)?,
-            storage,
-        );
-        let author = load_config(storage.work_dir())?.author;
-        let parent_hash = Self::get_parent_hash(&storage);
-        let timestamp = chrono::Utc::now().timestamp() as u64;
-        let email = load_config(storage.work_dir())?.email;
-        Ok(Commit::V1 {
-            message,
-            author,
-            timestamp,
-            email,
-            tree_hash,
-            parent_hash,
-            dependencies,
-        })
+    pub fn from_tree(
+        message: String,
+        tree_hash: Vec<u8>,
+        dependencies: Vec<String>,
+        storage: &(impl StorageEngine + ?Sized),
+    ) -> GatoResult<Self> {
+        let parents = Self::get_parent_hash(storage).into_iter().collect();
+        Self::new_merged(message, tree_hash, parents, dependencies, storage)
     }
 
+    /// A commit of `tree_hash` with the given parents, first parent first.
     pub fn new_merged(
         message: String,
         tree_hash: Vec<u8>,
-        parent_hash1: Vec<u8>,
-        parent_hash2: Vec<u8>,
+        parents: Vec<Vec<u8>>,
         dependencies: Vec<String>,
-        storage: &LocalStorage,
+        storage: &(impl StorageEngine + ?Sized),
     ) -> GatoResult<Self> {
-        let config = load_config(storage.work_dir())?;
-        let author = config.author;
-        let email = config.email;
-
-        let timestamp = chrono::Utc::now().timestamp() as u64;
-
-        Ok(Commit::MergedCommitV1 {
+        Ok(Commit::V2 {
             message,
-            author,
-            timestamp,
-            email,
+            author: Identity::from_env(Role::Author, storage)?,
+            committer: Identity::from_env(Role::Committer, storage)?,
             tree_hash,
-            parent_hash1,
-            parent_hash2,
+            parents,
             dependencies,
+            headers: BTreeMap::new(),
         })
     }
+    /// The commit stored under `hash`, or an error when there is no object or
+    /// it is not a whole commit, such as a tree, blob or chunk.
+    #[instrument]
+    pub fn try_load(hash: &String, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<Self> {
+        let data = storage.get(hash)?;
+        let (commit, read): (Commit, usize) =
+            bincode::decode_from_slice(&data, config::standard())?;
+        if read != data.len() {
+            return Err(Error::UnknownRevision(hash.clone()));
+        }
+        Ok(commit)
+    }
     #[instrument]
-    pub fn load(hash: String, storage: &LocalStorage) -> Self {
+    pub fn load(hash: String, storage: &(impl ObjectStore + ?Sized)) -> Self {
         let data = storage.get(&hash).expect("cannot read this commit");
         let commit: Commit = bincode::decode_from_slice(&data, config::standard())
             .expect("Decoding failed")
@@ -261,49 +338,208 @@ impl Commit {
         commit
     }
     #[instrument]
-    pub fn get_last_commit_hash(storage: &LocalStorage) -> Option<String> {
-        let hash_bytes = Self::get_parent_hash(&storage)?;
+    pub fn get_last_commit_hash(storage: &(impl StorageEngine + ?Sized)) -> Option<String> {
+        let hash_bytes = Self::get_parent_hash(storage)?;
         let hash_str = hex::encode(hash_bytes);
         Some(hash_str)
     }
     #[instrument]
-    pub fn get_hash_from_index(index: usize, storage: &LocalStorage) -> Option<String> {
-        let mut current_hash = Self::get_last_commit_hash(&storage)?;
+    pub fn get_hash_from_index(
+        index: usize,
+        storage: &(impl StorageEngine + ?Sized),
+    ) -> Option<String> {
+        let mut graph = CommitGraph::new(storage);
+        let mut current_hash = Self::get_last_commit_hash(storage)?;
         for _ in 0..index {
-            let commit = Commit::load(current_hash, storage);
-            match commit {
-                Commit::V1 { parent_hash, .. } => match parent_hash {
-                    Some(parent_hash) => {
-                        current_hash = hex::encode(parent_hash);
-                    }
-                    None => return None,
-                },
-                Commit::MergedCommitV1 { parent_hash1, .. } => {
-                    current_hash = hex::encode(parent_hash1)
+            current_hash = graph.parents(&current_hash).into_iter().next()?;
+        }
+        Some(current_hash)
+    }
+    /// All parents of the commit, first parent first.
+    pub fn parents(&self) -> Vec<String> {
+        match self {
+            Commit::V1 { parent_hash, .. } => parent_hash.iter().map(hex::encode).collect(),
+            Commit::MergedCommitV1 {
+                parent_hash1,
+                parent_hash2,
+                ..
+            } => vec![hex::encode(parent_hash1), hex::encode(parent_hash2)],
+            Commit::V2 { parents, .. } => parents.iter().map(hex::encode).collect(),
+        }
+    }
+
+    /// Point the commit at a new tree, parents and dependency list while keeping
+    /// its message and author. Used by migrations that rewrite history.
+    pub fn rewrite(&mut self, new_tree: Vec<u8>, new_parents: Vec<Vec<u8>>, new_deps: Vec<String>) {
+        self.unsign();
+        match self {
+            Commit::V1 {
+                tree_hash,
+                parent_hash,
+                dependencies,
+                ..
+            } => {
+                *tree_hash = new_tree;
+                *parent_hash = new_parents.into_iter().next();
+                *dependencies = new_deps;
+            }
+            Commit::MergedCommitV1 {
+                tree_hash,
+                parent_hash1,
+                parent_hash2,
+                dependencies,
+                ..
+            } => {
+                let mut parents = new_parents.into_iter();
+                *tree_hash = new_tree;
+                if let Some(p) = parents.next() {
+                    *parent_hash1 = p;
+                }
+                if let Some(p) = parents.next() {
+                    *parent_hash2 = p;
                 }
+                *dependencies = new_deps;
+            }
+            Commit::V2 {
+                tree_hash,
+                parents,
+                dependencies,
+                ..
+            } => {
+                *tree_hash = new_tree;
+                *parents = new_parents;
+                *dependencies = new_deps;
             }
         }
-        Some(current_hash)
     }
+
+    /// Replace the commit message.
+    pub fn set_message(&mut self, new_message: String) {
+        self.unsign();
+        match self {
+            Commit::V1 { message, .. }
+            | Commit::MergedCommitV1 { message, .. }
+            | Commit::V2 { message, .. } => *message = new_message,
+        }
+    }
+
+    /// Who wrote the changes. Commits made before author and committer were
+    /// kept apart have the same identity for both, in UTC.
+    pub fn author(&self) -> Identity {
+        match self {
+            Commit::V1 {
+                author,
+                email,
+                timestamp,
+                ..
+            }
+            | Commit::MergedCommitV1 {
+                author,
+                email,
+                timestamp,
+                ..
+            } => Identity::legacy(author, email, *timestamp),
+            Commit::V2 { author, .. } => author.clone(),
+        }
+    }
+
+    /// Who recorded the commit.
+    pub fn committer(&self) -> Identity {
+        match self {
+            Commit::V2 { committer, .. } => committer.clone(),
+            Commit::V1 { .. } | Commit::MergedCommitV1 { .. } => self.author(),
+        }
+    }
+
+    /// Replace the author and committer, upgrading the commit to `V2`.
+    pub fn set_identities(&mut self, author: Identity, committer: Identity) {
+        self.unsign();
+        if let Commit::V2 {
+            author: old_author,
+            committer: old_committer,
+            ..
+        } = self.upgrade()
+        {
+            *old_author = author;
+            *old_committer = committer;
+        }
+    }
+
+    /// Extra headers of the commit; older formats have none.
+    pub fn headers(&self) -> BTreeMap<String, String> {
+        match self {
+            Commit::V2 { headers, .. } => headers.clone(),
+            _ => BTreeMap::new(),
+        }
+    }
+
+    /// Set an extra header, upgrading the commit to `V2`.
+    pub fn set_header(&mut self, key: String, value: String) {
+        if key != signature::SIGNATURE_HEADER {
+            self.unsign();
+        }
+        if let Commit::V2 { headers, .. } = self.upgrade() {
+            headers.insert(key, value);
+        }
+    }
+
+    /// Drop the signature, which no longer matches once the commit changes.
+    fn unsign(&mut self) {
+        if let Commit::V2 { headers, .. } = self {
+            headers.remove(signature::SIGNATURE_HEADER);
+        }
+    }
+
+    /// Convert the commit to `V2` in place, keeping everything it records.
+    fn upgrade(&mut self) -> &mut Commit {
+        let (author, committer) = (self.author(), self.committer());
+        let parents = self.parents().iter().flat_map(hex::decode).collect();
+        match self.clone() {
+            Commit::V1 {
+                message,
+                tree_hash,
+                dependencies,
+                ..
+            }
+            | Commit::MergedCommitV1 {
+                message,
+                tree_hash,
+                dependencies,
+                ..
+            } => {
+                *self = Commit::V2 {
+                    message,
+                    author,
+                    committer,
+                    tree_hash,
+                    parents,
+                    dependencies,
+                    headers: BTreeMap::new(),
+                }
+            }
+            Commit::V2 { .. } => {}
+        }
+        self
+    }
+
     #[instrument]
     pub fn parent_hash(&self) -> Option<String> {
         match self {
-            Commit::V1 { parent_hash, .. } => match parent_hash {
-                Some(parent_hash) => Some(hex::encode(parent_hash)),
-                None => return None,
-            },
+            Commit::V1 { parent_hash, .. } => parent_hash.as_ref().map(hex::encode),
             Commit::MergedCommitV1 { parent_hash1, .. } => Some(hex::encode(parent_hash1)),
+            Commit::V2 { parents, .. } => parents.first().map(hex::encode),
         }
     }
     #[instrument]
     pub fn dependices(&self) -> Vec<String> {
         match self {
-            Commit::V1 { dependencies, .. } => dependencies.clone(),
-            Commit::MergedCommitV1 { dependencies, .. } => dependencies.clone(),
+            Commit::V1 { dependencies, .. }
+            | Commit::MergedCommitV1 { dependencies, .. }
+            | Commit::V2 { dependencies, .. } => dependencies.clone(),
         }
     }
     #[instrument]
-    pub fn load_by_index(index: usize, storage: &LocalStorage) -> Option<Self> {
+    pub fn load_by_index(index: usize, storage: &(impl StorageEngine + ?Sized)) -> Option<Self> {
         let hash = Self::get_hash_from_index(index, storage)?;
         let commit = Commit::load(hash, storage);
         Some(commit)
@@ -311,12 +547,17 @@ impl Commit {
     #[instrument]
     pub fn tree_hash(&self) -> Vec<u8> {
         match self {
-            Commit::V1 { tree_hash, .. } => tree_hash.clone(),
-            Commit::MergedCommitV1 { tree_hash, .. } => tree_hash.clone(),
+            Commit::V1 { tree_hash, .. }
+            | Commit::MergedCommitV1 { tree_hash, .. }
+            | Commit::V2 { tree_hash, .. } => tree_hash.clone(),
         }
     }
     #[instrument]
-    pub fn write_tree(&self, out_path: &Path, storage: &LocalStorage) -> GatoResult<()> {
+    pub fn write_tree(
+        &self,
+        out_path: &Path,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<()> {
         let tree_hash_hex = hex::encode(&self.tree_hash());
         let tree = Tree::load(tree_hash_hex, storage)?;
         for entry in tree.entries {
@@ -334,7 +575,7 @@ pub enum TreeEntry {
 
 impl TreeEntry {
     #[instrument]
-    fn write(&self, parent_path: &Path, storage: &LocalStorage) -> GatoResult<()> {
+    fn write(&self, parent_path: &Path, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<()> {
         match self {
             TreeEntry::Blob(name, hash) => {
                 let hash_hex = hex::encode(hash);
@@ -364,6 +605,14 @@ impl TreeEntry {
         }
     }
     #[instrument]
+    /// The hash of the entry if it is a directory.
+    pub fn as_tree_hash(self) -> Option<Vec<u8>> {
+        match self {
+            TreeEntry::Tree(_, hash) => Some(hash),
+            TreeEntry::Blob(..) => None,
+        }
+    }
+
     pub fn hash(&self) -> Vec<u8> {
         match self {
             TreeEntry::Blob(_, items) => items.clone(),
@@ -386,6 +635,11 @@ impl TreeEntry {
     }
 }
 
+/// A directory listing.
+///
+/// Entries are kept in canonical order: sorted by the byte-wise ordering of
+/// their names, with every name appearing at most once. The encoded tree (and so
+/// its hash) only depends on its content, never on the order files were added.
 #[derive(Encode, Decode, Debug, Clone)]
 pub struct Tree {
     pub name: String,
@@ -408,8 +662,25 @@ impl Tree {
         }
     }
     #[instrument]
-    fn add_entry(&mut self, entry: TreeEntry) {
-        self.entries.push(entry);
+    pub(crate) fn add_entry(&mut self, entry: TreeEntry) {
+        match self
+            .entries
+            .binary_search_by(|e| e.name().as_str().cmp(entry.name()))
+        {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Sort the entries into canonical order.
+    pub fn canonicalize(&mut self) {
+        self.entries.sort_by(|a, b| a.name().cmp(b.name()));
+        self.entries.dedup_by(|a, b| a.name() == b.name());
+    }
+
+    /// Whether the entries are strictly sorted by name.
+    pub fn is_canonical(&self) -> bool {
+        self.entries.windows(2).all(|w| w[0].name() < w[1].name())
     }
 
     #[instrument]
@@ -417,7 +688,7 @@ impl Tree {
         TreeEntry::Tree(self.name.clone(), self.hash())
     }
     #[instrument]
-    fn get_entry(&self, name: &String) -> Option<TreeEntry> {
+    pub(crate) fn get_entry(&self, name: &String) -> Option<TreeEntry> {
         for a in &self.entries {
             if a.name() == name {
                 return Some(a.clone());
@@ -425,9 +696,49 @@ impl Tree {
         }
         None
     }
+    /// Hashes of the tree, every tree below it and every object its files need.
     #[instrument]
-    fn get_entry_hash(&self, name: &String) -> Option<String> {
-        self.get_entry(name).map(|a| hex::encode(a.hash()))
+    pub fn dependencies(&self, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<Vec<String>> {
+        let mut deps = vec![hex::encode(self.hash())];
+        let mut stack = vec![self.clone()];
+        while let Some(tree) = stack.pop() {
+            for entry in tree.entries {
+                match entry {
+                    TreeEntry::Blob(_, hash) => {
+                        deps.extend(Blob::dependencies(hex::encode(hash), storage)?);
+                    }
+                    TreeEntry::Tree(_, hash) => {
+                        deps.push(hex::encode(&hash));
+                        stack.push(Tree::load(hex::encode(hash), storage)?);
+                    }
+                }
+            }
+        }
+        Ok(deps)
+    }
+
+    /// Every file below the tree, keyed by its path relative to the tree.
+    #[instrument]
+    pub fn files(
+        &self,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<BTreeMap<PathBuf, Vec<u8>>> {
+        let mut files = BTreeMap::new();
+        let mut stack = vec![(PathBuf::new(), self.clone())];
+        while let Some((prefix, tree)) = stack.pop() {
+            for entry in tree.entries {
+                match entry {
+                    TreeEntry::Blob(name, hash) => {
+                        files.insert(prefix.join(name), hash);
+                    }
+                    TreeEntry::Tree(name, hash) => {
+                        let subtree = Tree::load(hex::encode(hash), storage)?;
+                        stack.push((prefix.join(name), subtree));
+                    }
+                }
+            }
+        }
+        Ok(files)
     }
     #[instrument]
     // encode Object to bincode bytes
@@ -450,7 +761,8 @@ impl Tree {
     }
     #[instrument]
     // save the tree object to .gato/objects/<first 2 chars>/<rest chars>
-    pub fn save(&self, storage: &LocalStorage) -> String {
+    pub fn save(&mut self, storage: &(impl ObjectStore + ?Sized)) -> String {
+        self.canonicalize();
         let tree_hash = self.hash_str();
         let tree_data = self.tree_bytes();
         match storage.put(&tree_hash, tree_data) {
@@ -473,14 +785,40 @@ impl Tree {
 
     #[instrument]
     // load tree object from .gato/objects/<first 2 chars>/<rest chars>
-    pub fn load(hash: String, storage: &LocalStorage) -> GatoResult<Self> {
+    pub fn load(hash: String, storage: &(impl ObjectStore + ?Sized)) -> GatoResult<Self> {
         let data = storage.get(&hash)?;
         let tree: Tree = bincode::decode_from_slice(&data, config::standard())?.0;
         Ok(tree)
     }
+    /// Rewrite the tree stored under `hash`, and every tree below it, in canonical
+    /// order. `rewritten` maps old tree hashes to new ones and doubles as a memo, so
+    /// shared subtrees are only visited once. Returns the hash of the new tree.
+    pub fn rewrite_canonical(
+        hash: &[u8],
+        rewritten: &mut HashMap<Vec<u8>, Vec<u8>>,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<Vec<u8>> {
+        if let Some(new_hash) = rewritten.get(hash) {
+            return Ok(new_hash.clone());
+        }
+        let mut tree = Tree::load(hex::encode(hash), storage)?;
+        for entry in &mut tree.entries {
+            if let TreeEntry::Tree(_, sub_hash) = entry {
+                *sub_hash = Self::rewrite_canonical(sub_hash, rewritten, storage)?;
+            }
+        }
+        tree.save(storage);
+        let new_hash = tree.hash();
+        rewritten.insert(hash.to_vec(), new_hash.clone());
+        Ok(new_hash)
+    }
+
     // return hash of the root tree created from index
     #[instrument]
-    pub fn create_from_index(index: Index, storage: &LocalStorage) -> (Vec<u8>, Vec<String>) {
+    pub fn create_from_index(
+        index: Index,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> (Vec<u8>, Vec<String>) {
         let mut file_dependencies = index.dependencies;
         let entries: Vec<(PathBuf, Vec<u8>)> = index
             .entries
@@ -501,13 +839,28 @@ impl Tree {
         }
     }
 
+    /// Build (and save) a tree holding exactly `files`, keyed by path.
+    #[instrument]
+    pub fn from_files(
+        files: BTreeMap<PathBuf, Vec<u8>>,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> GatoResult<Tree> {
+        let root = Self::build_recursive_tree(
+            files.into_iter().collect(),
+            "root".to_string(),
+            &mut Vec::new(),
+            storage,
+        );
+        Tree::load(hex::encode(root.hash()), storage)
+    }
+
     // recursively build tree from entries
     #[instrument]
     fn build_recursive_tree(
         entries: Vec<(PathBuf, Vec<u8>)>,
         name: String,
         dependencies: &mut Vec<String>,
-        storage: &LocalStorage,
+        storage: &(impl ObjectStore + ?Sized),
     ) -> TreeEntry {
         let mut current_tree = Tree::new(name.clone());
 
@@ -543,108 +896,4 @@ impl Tree {
 
         current_tree.into_entry()
     }
-    #[instrument]
-    pub fn merge(
-        base: Tree,
-        current: Tree,
-        target: Tree,
-        deps: &mut Vec<String>,
-        storage: &LocalStorage,
-    ) -> GatoResult<Tree> {
-        let mut result_tree = Tree::new(current.name.clone());
-        let mut all_filenames = std::collections::HashSet::new();
-        for e in &current.entries {
-            all_filenames.insert(e.name());
-        }
-        for e in &target.entries {
-            all_filenames.insert(e.name());
-        }
-        for e in &base.entries {
-            all_filenames.insert(e.name());
-        }
-        for name in all_filenames {
-            let b = base.get_entry_hash(&name);
-            let c = current.get_entry_hash(&name);
-            let t = target.get_entry_hash(&name);
-
-            if c == t {
-                if let Some(entry) = current.get_entry(&name) {
-                    deps.push(hex::encode(entry.hash()));
-                    result_tree.add_entry(entry.clone());
-                }
-            } else if c == b {
-                if let Some(entry) = target.get_entry(&name) {
-                    deps.push(hex::encode(entry.hash()));
-                    result_tree.add_entry(entry.clone());
-                }
-            } else if t == b {
-                if let Some(entry) = current.get_entry(&name) {
-                    deps.push(hex::encode(entry.hash()));
-                    result_tree.add_entry(entry.clone());
-                }
-            } else {
-                match (current.get_entry(&name), target.get_entry(&name)) {
-                    (Some(TreeEntry::Blob(_, hash1)), Some(TreeEntry::Blob(_, hash2))) => {
-                        if let (Ok(current_file), Ok(target_file)) = (
-                            storage.get_as_string(&hex::encode(hash1), storage),
-                            storage.get_as_string(&hex::encode(hash2), storage),
-                        ) {
-                            let base_content = if let Some(base_hash) = b {
-                                storage
-                                    .get_as_string(&base_hash, storage)
-                                    .unwrap_or(String::new())
-                            } else {
-                                String::new()
-                            };
-
-                            let merged = merge(&base_content, &current_file, &target_file);
-
-                            match merged {
-                                Ok(v) => {
-                                    let hash = add_file_dry(v.as_bytes(), &storage)?;
-                                    let entry = TreeEntry::Blob(name.clone(), hash);
-                                    deps.push(hex::encode(entry.hash()));
-                                    result_tree.add_entry(entry);
-                                }
-                                Err(conflict_content) => {
-                                    println!("CONFLICT detected in file: {}", name);
-                                    let hash = add_file_dry(conflict_content.as_bytes(), &storage)?;
-                                    let entry = TreeEntry::Blob(name.clone(), hash);
-                                    deps.push(hex::encode(entry.hash()));
-                                    result_tree.add_entry(entry);
-                                }
-                            }
-                        } else {
-                            return Err(Error::MergeConflict(format!(
-                                "Binary file conflict: {}",
-                                name
-                            )));
-                        }
-                    }
-                    (Some(TreeEntry::Tree(_, hash1)), Some(TreeEntry::Tree(_, hash2))) => {
-                        let current_tree = Tree::load(hex::encode(hash1), &storage)?;
-                        let target_tree = Tree::load(hex::encode(hash2), &storage)?;
-                        let base_tree =
-                            if let Some(TreeEntry::Tree(_, hash_base)) = base.get_entry(&name) {
-                                Tree::load(hex::encode(hash_base), storage)?
-                            } else {
-                                Tree::new(name.clone())
-                            };
-                        let merged_subtree =
-                            Self::merge(base_tree, current_tree, target_tree, deps, storage)?;
-                        deps.push(hex::encode(merged_subtree.into_entry().hash()));
-                        result_tree.add_entry(merged_subtree.into_entry());
-                    }
-                    _ => {
-                        return Err(Error::MergeConflict(format!(
-                            "{} renamed to file or directory",
-                            name
-                        )));
-                    }
-                }
-            }
-        }
-        result_tree.save(&storage);
-        Ok(result_tree)
-    }
 }
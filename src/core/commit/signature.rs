@@ -0,0 +1,208 @@
+use std::{
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use bincode::{config, encode_to_vec};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use tracing::instrument;
+
+use crate::core::{
+    commit::Commit,
+    config::{
+        SigningConfig,
+        load::{load_config, load_global_config},
+    },
+    error::{Error, GatoResult},
+    storage::local::LocalStorage,
+};
+
+/// The commit header holding `ed25519 <public key> <signature>`, both in hex.
+pub const SIGNATURE_HEADER: &str = "signature";
+
+/// A key trusted by `verify-commit`, from the allowed signers file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedSigner {
+    pub principal: String,
+    pub key: VerifyingKey,
+}
+
+/// The outcome of checking a commit signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verification {
+    Unsigned,
+    /// Signed by a key listed in the allowed signers.
+    Good {
+        principal: String,
+        key: String,
+    },
+    /// A valid signature by a key nobody vouched for.
+    Untrusted {
+        key: String,
+    },
+    /// The signature does not match the commit.
+    Bad,
+}
+
+impl Verification {
+    pub fn is_good(&self) -> bool {
+        matches!(self, Verification::Good { .. })
+    }
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Verification::Unsigned => write!(f, "no signature"),
+            Verification::Good { principal, key } => {
+                write!(f, "good signature from {principal} (ed25519 {key})")
+            }
+            Verification::Untrusted { key } => {
+                write!(f, "valid signature by untrusted key ed25519 {key}")
+            }
+            Verification::Bad => write!(f, "BAD signature"),
+        }
+    }
+}
+
+impl Commit {
+    /// Sign the commit with `key`, upgrading it to `V2`. The signature covers
+    /// the encoded commit without its signature header.
+    pub fn sign(&mut self, key: &SigningKey) -> GatoResult<()> {
+        self.upgrade().unsign();
+        let payload = encode_to_vec(&*self, config::standard())?;
+        let signature = key.sign(&payload);
+        self.set_header(
+            SIGNATURE_HEADER.to_string(),
+            format!(
+                "ed25519 {} {}",
+                key_hex(&key.verifying_key()),
+                hex::encode(signature.to_bytes())
+            ),
+        );
+        Ok(())
+    }
+
+    /// Check the signature of the commit against the `allowed` keys.
+    pub fn verify_signature(&self, allowed: &[AllowedSigner]) -> Verification {
+        let Some(value) = self.headers().remove(SIGNATURE_HEADER) else {
+            return Verification::Unsigned;
+        };
+        let Some((key, signature)) = parse_signature(&value) else {
+            return Verification::Bad;
+        };
+        let mut unsigned = self.clone();
+        unsigned.unsign();
+        let Ok(payload) = encode_to_vec(&unsigned, config::standard()) else {
+            return Verification::Bad;
+        };
+        if key.verify_strict(&payload, &signature).is_err() {
+            return Verification::Bad;
+        }
+        match allowed.iter().find(|signer| signer.key == key) {
+            Some(signer) => Verification::Good {
+                principal: signer.principal.clone(),
+                key: key_hex(&key),
+            },
+            None => Verification::Untrusted { key: key_hex(&key) },
+        }
+    }
+}
+
+fn parse_signature(value: &str) -> Option<(VerifyingKey, Signature)> {
+    let mut words = value.split_whitespace();
+    if words.next()? != "ed25519" {
+        return None;
+    }
+    let key = parse_public_key(words.next()?)?;
+    let signature = Signature::from_slice(&hex::decode(words.next()?).ok()?).ok()?;
+    words.next().is_none().then_some((key, signature))
+}
+
+fn parse_public_key(text: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(text).ok()?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+/// The hex form of a public key, as written to allowed signers files.
+pub fn key_hex(key: &VerifyingKey) -> String {
+    hex::encode(key.as_bytes())
+}
+
+/// The signing settings of the repository, taking whatever `gato.toml` leaves
+/// out from the store's `config.toml`.
+fn settings(storage: &LocalStorage) -> GatoResult<SigningConfig> {
+    let local = load_config(storage.work_dir())?.signing.unwrap_or_default();
+    let global = load_global_config(&storage.root_path)?
+        .signing
+        .unwrap_or_default();
+    let pick = |local: Option<PathBuf>, global: Option<PathBuf>| {
+        local
+            .map(|path| storage.work_dir().join(path))
+            .or_else(|| global.map(|path| storage.root_path.join(path)))
+    };
+    Ok(SigningConfig {
+        key: pick(local.key, global.key),
+        allowed_signers: pick(local.allowed_signers, global.allowed_signers),
+    })
+}
+
+/// The key `commit -S` signs with.
+#[instrument]
+pub fn signing_key(storage: &LocalStorage) -> GatoResult<SigningKey> {
+    let path = settings(storage)?.key.ok_or(Error::NoSigningKey)?;
+    read_signing_key(&path)
+}
+
+/// Read a secret key written by [`generate_key`].
+pub fn read_signing_key(path: &Path) -> GatoResult<SigningKey> {
+    let text = fs::read_to_string(path)?;
+    let bytes: [u8; 32] = hex::decode(text.trim())
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::InvalidSigningKey(path.to_path_buf()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Create a new secret key at `path`, readable only by its owner, and return
+/// its public key. Never overwrites an existing file.
+#[instrument]
+pub fn generate_key(path: &Path) -> GatoResult<VerifyingKey> {
+    let key = SigningKey::generate(&mut OsRng);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    writeln!(file, "{}", hex::encode(key.to_bytes()))?;
+    Ok(key.verifying_key())
+}
+
+/// The trusted keys of the repository; none when no file is configured.
+#[instrument]
+pub fn allowed_signers(storage: &LocalStorage) -> GatoResult<Vec<AllowedSigner>> {
+    match settings(storage)?.allowed_signers {
+        Some(path) => parse_allowed_signers(&fs::read_to_string(path)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Parse an allowed signers file: `<principal> <public key>` per line, with
+/// blank lines and `#` comments ignored.
+pub fn parse_allowed_signers(text: &str) -> GatoResult<Vec<AllowedSigner>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let invalid = || Error::InvalidAllowedSigner(line.to_string());
+            let (principal, key) = line.split_once(char::is_whitespace).ok_or_else(invalid)?;
+            Ok(AllowedSigner {
+                principal: principal.to_string(),
+                key: parse_public_key(key.trim()).ok_or_else(invalid)?,
+            })
+        })
+        .collect()
+}
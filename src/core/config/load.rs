@@ -1,6 +1,10 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::core::{add::smart_read, config::Config, error::GatoResult};
+use crate::core::{
+    add::smart_read,
+    config::{Config, GlobalConfig},
+    error::GatoResult,
+};
 
 pub fn load_config(path: &PathBuf) -> GatoResult<Config> {
     let config_path = path.join("gato.toml");
@@ -10,3 +14,12 @@ pub fn load_config(path: &PathBuf) -> GatoResult<Config> {
     let config: Config = toml::from_str(config_string)?;
     Ok(config)
 }
+
+/// Load `config.toml` from the store, or the defaults when there is none.
+pub fn load_global_config(store_path: &Path) -> GatoResult<GlobalConfig> {
+    let config_path = store_path.join("config.toml");
+    if !config_path.exists() {
+        return Ok(GlobalConfig::default());
+    }
+    Ok(toml::from_str(&std::fs::read_to_string(config_path)?)?)
+}
@@ -1,3 +1,8 @@
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+};
+
 use serde::{Deserialize, Serialize};
 pub mod load;
 #[derive(Debug, Deserialize, Serialize)]
@@ -8,6 +13,14 @@ pub struct Config {
     pub email: Option<String>,
     pub description: String,
     pub compression: Option<CompressionConfig>,
+    pub signing: Option<SigningConfig>,
+    pub gc: Option<GcConfig>,
+    pub storage: Option<StorageConfig>,
+    /// Repositories this one was cloned from, by name.
+    pub remotes: Option<BTreeMap<String, RemoteConfig>>,
+    /// Set in the linked worktrees `gato worktree add` creates, which share
+    /// the repository id but have their own `HEAD` and index.
+    pub worktree: Option<String>,
     ignore: Vec<String>,
 }
 #[derive(Debug, Deserialize, Serialize)]
@@ -15,11 +28,91 @@ pub struct CompressionConfig {
     pub level: Option<i32>,
 }
 
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct GcConfig {
+    /// Days a reflog entry, and the commits it names, are kept (default 90).
+    pub reflog_expire_days: Option<u64>,
+    /// Minutes an unreferenced object is kept after it was written (default
+    /// 60); read from the store's `config.toml`.
+    pub grace_period_minutes: Option<u64>,
+}
+
+/// When written files are flushed to disk before they replace the old ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    /// Leave flushing to the operating system.
+    Never,
+    /// Flush every new object.
+    #[default]
+    Objects,
+    /// Flush objects, refs, `HEAD`, the index and the directories holding them.
+    Always,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct StorageConfig {
+    pub fsync: Option<FsyncPolicy>,
+    /// Object store of the repository, relative to the working directory;
+    /// only read from `gato.toml`.
+    pub path: Option<PathBuf>,
+}
+
+/// Another repository of the store, recorded by `gato clone` as `origin`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RemoteConfig {
+    /// Its working directory when it was cloned.
+    pub path: PathBuf,
+    pub id: String,
+}
+
+/// Commit signing settings; relative paths are taken from the directory
+/// holding the config file.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SigningConfig {
+    /// File with the hex-encoded ed25519 secret key used by `commit -S`.
+    pub key: Option<PathBuf>,
+    /// File of trusted keys, one `<principal> <public key>` per line.
+    pub allowed_signers: Option<PathBuf>,
+}
+
+/// Settings shared by every repository, from `config.toml` in the store.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct GlobalConfig {
+    pub signing: Option<SigningConfig>,
+    pub storage: Option<StorageConfig>,
+    pub gc: Option<GcConfig>,
+}
+
 impl Config {
     pub fn ignored(self) -> Vec<String> {
+        let store = self.storage.and_then(|storage| storage.path);
         let mut ignored = self.ignore;
         ignored.push(".gato".to_string());
         ignored.push("gato.toml".to_string());
+        // a store kept inside the working directory
+        if let Some(Component::Normal(name)) = store.as_deref().and_then(|p| p.components().next())
+        {
+            ignored.push(name.to_string_lossy().into_owned());
+        }
         ignored
     }
+
+    /// The object store `[storage] path` names, if any.
+    pub fn store_path(&self, work_dir: &Path) -> Option<PathBuf> {
+        let path = self.storage.as_ref()?.path.as_ref()?;
+        Some(work_dir.join(path))
+    }
+
+    /// Record `store` as the object store, relative to `work_dir` when it is
+    /// inside it. `None` drops `[storage] path`, and the table if left empty.
+    pub fn set_store_path(&mut self, store: Option<&Path>, work_dir: &Path) {
+        let path = store.map(|store| match store.strip_prefix(work_dir) {
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => store.to_path_buf(),
+        });
+        let storage = self.storage.take().unwrap_or_default();
+        let storage = StorageConfig { path, ..storage };
+        self.storage = (storage.path.is_some() || storage.fsync.is_some()).then_some(storage);
+    }
 }
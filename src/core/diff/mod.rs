@@ -0,0 +1,347 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use tracing::instrument;
+
+use crate::core::{
+    commit::{Commit, Tree, blob::Blob},
+    error::GatoResult,
+    storage::ObjectStore,
+};
+
+/// Above this many source × destination pairs only exact renames are detected.
+const RENAME_LIMIT: usize = 1_000_000;
+
+/// A change between two snapshots of the repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(PathBuf),
+    Deleted(PathBuf),
+    Modified(PathBuf),
+    /// `from` was deleted and `to` added with at least `similarity` percent of its content.
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+        similarity: u8,
+    },
+    /// `to` was added as a copy of `from`, which still exists.
+    Copied {
+        from: PathBuf,
+        to: PathBuf,
+        similarity: u8,
+    },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Added(path) => write!(f, "A\t{}", path.display()),
+            Change::Deleted(path) => write!(f, "D\t{}", path.display()),
+            Change::Modified(path) => write!(f, "M\t{}", path.display()),
+            Change::Renamed {
+                from,
+                to,
+                similarity,
+            } => write!(f, "R{similarity:03}\t{}\t{}", from.display(), to.display()),
+            Change::Copied {
+                from,
+                to,
+                similarity,
+            } => write!(f, "C{similarity:03}\t{}\t{}", from.display(), to.display()),
+        }
+    }
+}
+
+/// How renames and copies are looked for.
+#[derive(Debug, Clone, Copy)]
+pub struct RenameOptions {
+    /// Minimum similarity, in percent, for a rename or copy; `None` turns
+    /// detection off.
+    pub threshold: Option<u8>,
+    /// Also look for copies of files that still exist.
+    pub copies: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self {
+            threshold: Some(50),
+            copies: false,
+        }
+    }
+}
+
+/// Changes from `old` to `new`, two snapshots of path to blob hash.
+///
+/// Files with identical content are paired first without reading them. The
+/// remaining added files are scored against the candidates by the share of
+/// content they have in common: lines for plain blobs, chunk hashes for
+/// `ChunksMap` blobs, which needs no decompression at all. Best scores are
+/// paired first; each deleted file is renamed at most once.
+#[instrument(skip(old, new))]
+pub fn diff_files(
+    old: &BTreeMap<PathBuf, Vec<u8>>,
+    new: &BTreeMap<PathBuf, Vec<u8>>,
+    options: RenameOptions,
+    storage: &(impl ObjectStore + ?Sized),
+) -> GatoResult<Vec<Change>> {
+    let mut changes = Vec::new();
+    let mut deleted: Vec<&PathBuf> = Vec::new();
+    let mut added: Vec<&PathBuf> = Vec::new();
+    for (path, hash) in old {
+        match new.get(path) {
+            None => deleted.push(path),
+            Some(new_hash) if new_hash != hash => changes.push(Change::Modified(path.clone())),
+            Some(_) => {}
+        }
+    }
+    for path in new.keys() {
+        if !old.contains_key(path) {
+            added.push(path);
+        }
+    }
+
+    let Some(threshold) = options.threshold else {
+        changes.extend(deleted.into_iter().map(|p| Change::Deleted(p.clone())));
+        changes.extend(added.into_iter().map(|p| Change::Added(p.clone())));
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        return Ok(changes);
+    };
+    // Copies may come from any old file, renames only from deleted ones.
+    let sources: Vec<&PathBuf> = if options.copies {
+        old.keys().collect()
+    } else {
+        deleted.clone()
+    };
+
+    let mut renamed_from: Vec<PathBuf> = Vec::new();
+    let mut paired: HashMap<&PathBuf, Change> = HashMap::new();
+
+    // Exact matches, preferring a deleted source.
+    let mut remaining = Vec::new();
+    for to in &added {
+        let hash = &new[*to];
+        let exact = sources
+            .iter()
+            .filter(|from| &old[**from] == hash)
+            .min_by_key(|from| {
+                (
+                    !deleted.contains(*from) || renamed_from.contains(*from),
+                    **from,
+                )
+            });
+        match exact {
+            Some(from) if options.copies || !renamed_from.contains(from) => {
+                let change = pair(from, to, 100, &deleted, &mut renamed_from);
+                paired.insert(to, change);
+            }
+            _ => remaining.push(*to),
+        }
+    }
+
+    // Similarity, best scores first.
+    if !remaining.is_empty() && sources.len() * remaining.len() <= RENAME_LIMIT {
+        let mut signatures: HashMap<Vec<u8>, Signature> = HashMap::new();
+        let mut scores = Vec::new();
+        for to in &remaining {
+            let to_sig = signature(&new[*to], &mut signatures, storage)?;
+            for from in &sources {
+                if !options.copies && renamed_from.contains(from) {
+                    continue;
+                }
+                let from_sig = signature(&old[*from], &mut signatures, storage)?;
+                let score = to_sig.similarity(&from_sig);
+                if score >= threshold {
+                    scores.push((score, *from, *to));
+                }
+            }
+        }
+        scores.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)).then(a.2.cmp(b.2)));
+        for (score, from, to) in scores {
+            if paired.contains_key(to) || (!options.copies && renamed_from.contains(from)) {
+                continue;
+            }
+            let change = pair(from, to, score, &deleted, &mut renamed_from);
+            paired.insert(to, change);
+        }
+    }
+
+    for path in deleted {
+        if !renamed_from.contains(path) {
+            changes.push(Change::Deleted(path.clone()));
+        }
+    }
+    for path in added {
+        changes.push(paired.remove(path).unwrap_or(Change::Added(path.clone())));
+    }
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(changes)
+}
+
+/// A rename the first time a deleted file is paired, a copy otherwise.
+fn pair(
+    from: &Path,
+    to: &Path,
+    similarity: u8,
+    deleted: &[&PathBuf],
+    renamed_from: &mut Vec<PathBuf>,
+) -> Change {
+    let (from, to) = (from.to_path_buf(), to.to_path_buf());
+    if deleted.contains(&&from) && !renamed_from.contains(&from) {
+        renamed_from.push(from.clone());
+        Change::Renamed {
+            from,
+            to,
+            similarity,
+        }
+    } else {
+        Change::Copied {
+            from,
+            to,
+            similarity,
+        }
+    }
+}
+
+/// Changes between two trees.
+#[instrument]
+pub fn diff_trees(
+    old: &Tree,
+    new: &Tree,
+    options: RenameOptions,
+    storage: &(impl ObjectStore + ?Sized),
+) -> GatoResult<Vec<Change>> {
+    diff_files(&old.files(storage)?, &new.files(storage)?, options, storage)
+}
+
+/// First-parent history of `path` from commit `start`, following renames.
+///
+/// Returns each commit that touched the file, newest first, with the name the
+/// file had in that commit.
+#[instrument]
+pub fn follow(
+    start: String,
+    path: PathBuf,
+    options: RenameOptions,
+    storage: &(impl ObjectStore + ?Sized),
+) -> GatoResult<Vec<(String, PathBuf)>> {
+    let mut history = Vec::new();
+    let mut path = path;
+    let mut hash = start;
+    loop {
+        let commit = Commit::load(hash.clone(), storage);
+        let files = Tree::load(hex::encode(commit.tree_hash()), storage)?.files(storage)?;
+        if !files.contains_key(&path) {
+            break;
+        }
+        let Some(parent) = commit.parent_hash() else {
+            history.push((hash, path));
+            break;
+        };
+        let parent_commit = Commit::load(parent.clone(), storage);
+        let parent_files =
+            Tree::load(hex::encode(parent_commit.tree_hash()), storage)?.files(storage)?;
+        let previous = match parent_files.get(&path) {
+            Some(old) if *old == files[&path] => Some(path.clone()),
+            Some(_) => {
+                history.push((hash, path.clone()));
+                Some(path.clone())
+            }
+            None => {
+                history.push((hash, path.clone()));
+                diff_files(&parent_files, &files, options, storage)?
+                    .into_iter()
+                    .find_map(|change| match change {
+                        Change::Renamed { from, to, .. } | Change::Copied { from, to, .. }
+                            if to == path =>
+                        {
+                            Some(from)
+                        }
+                        _ => None,
+                    })
+            }
+        };
+        match previous {
+            Some(previous) => path = previous,
+            None => break,
+        }
+        hash = parent;
+    }
+    Ok(history)
+}
+
+impl Change {
+    /// The path the change is listed under: the new one for renames and copies.
+    pub fn path(&self) -> &PathBuf {
+        match self {
+            Change::Added(path) | Change::Deleted(path) | Change::Modified(path) => path,
+            Change::Renamed { to, .. } | Change::Copied { to, .. } => to,
+        }
+    }
+}
+
+/// Content fingerprint of a blob: weight of each line or chunk, keyed by its hash.
+#[derive(Debug, Clone)]
+struct Signature {
+    chunked: bool,
+    parts: HashMap<u64, u64>,
+    total: u64,
+}
+
+impl Signature {
+    /// Percentage of content shared by both blobs; plain and chunked blobs
+    /// never compare as similar.
+    fn similarity(&self, other: &Signature) -> u8 {
+        if self.chunked != other.chunked || self.total + other.total == 0 {
+            return 0;
+        }
+        let common: u64 = self
+            .parts
+            .iter()
+            .map(|(part, weight)| (*weight).min(*other.parts.get(part).unwrap_or(&0)))
+            .sum();
+        (common * 200 / (self.total + other.total)) as u8
+    }
+}
+
+fn signature(
+    hash: &Vec<u8>,
+    cache: &mut HashMap<Vec<u8>, Signature>,
+    storage: &(impl ObjectStore + ?Sized),
+) -> GatoResult<Signature> {
+    if let Some(signature) = cache.get(hash) {
+        return Ok(signature.clone());
+    }
+    let mut parts: HashMap<u64, u64> = HashMap::new();
+    let chunked = match Blob::new(hex::encode(hash), storage)? {
+        Blob::ChunksMap(index_data) => {
+            for chunk in &index_data.path {
+                *parts.entry(short_hash(chunk)).or_default() += 1;
+            }
+            true
+        }
+        blob => {
+            for line in blob.restore_data(storage)?.split_inclusive(|b| *b == b'\n') {
+                *parts.entry(short_hash(line)).or_default() += line.len() as u64;
+            }
+            false
+        }
+    };
+    let total = parts.values().sum();
+    let signature = Signature {
+        chunked,
+        parts,
+        total,
+    };
+    cache.insert(hash.clone(), signature.clone());
+    Ok(signature)
+}
+
+fn short_hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
@@ -1,6 +1,9 @@
 use thiserror::Error;
 
-use crate::core::{commit::error::CommitError, storage::StorageError};
+use crate::core::{
+    commit::{error::CommitError, signature::Verification},
+    storage::{StorageError, merge::Operation},
+};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -16,6 +19,9 @@ pub enum Error {
     #[error("{0}")]
     DeserialzeError(#[from] toml::de::Error),
 
+    #[error("{0}")]
+    SerializeError(#[from] toml::ser::Error),
+
     #[error("{0}")]
     DecodeError(#[from] bincode::error::DecodeError),
 
@@ -25,14 +31,108 @@ pub enum Error {
     #[error("Gc error: {0}")]
     GcError(String),
 
+    #[error("fsck found problems in the store")]
+    FsckFailed,
+
     #[error("Cannot delete the active branch")]
     ActiveBranchDeletionError,
 
     #[error("you don't add any file after last commit")]
     NoFilesAddedError,
 
-    #[error("Merge conflict detected in file: {0}")]
-    MergeConflict(String),
+    #[error("you have staged changes; commit them first")]
+    StagedChanges,
+
+    #[error(
+        "your local changes to {} would be overwritten; commit them first",
+        join_paths(.0)
+    )]
+    LocalChanges(Vec<std::path::PathBuf>),
+
+    #[error("{0} unresolved merge conflict(s); fix them and stage the files with `gato add`")]
+    UnresolvedConflicts(usize),
+
+    #[error("a {0} is already in progress; run `gato {0} --continue` or `gato {0} --abort`")]
+    OperationInProgress(Operation),
+
+    #[error("no {0} in progress")]
+    NotInProgress(Operation),
+
+    #[error("no branch to merge; name one, or use --continue or --abort")]
+    NoMergeTarget,
+
+    #[error("{} is not in conflict", .0.display())]
+    NotConflicted(std::path::PathBuf),
+
+    #[error(
+        "octopus merge failed, conflicts in: {}; merge the branches one at a time",
+        join_paths(.0)
+    )]
+    OctopusConflict(Vec<std::path::PathBuf>),
+
+    #[error("Not possible to fast-forward, aborting")]
+    NotFastForward,
+
+    #[error("aborting commit due to empty commit message")]
+    EmptyCommitMessage,
+
+    #[error("the editor `{0}` exited with an error")]
+    EditorFailed(String),
+
+    #[error("invalid date: {0}")]
+    InvalidDate(String),
+
+    #[error("invalid identity `{0}`, expected `Name <email>`")]
+    InvalidIdentity(String),
+
+    #[error("--author, --trailer and -S cannot be used to conclude a {0}")]
+    OptionsDuringOperation(Operation),
+
+    #[error("invalid rebase todo line: {0}")]
+    InvalidTodoLine(String),
+
+    #[error(
+        "no signing key configured, set `key` under [signing] in gato.toml or the store's config.toml"
+    )]
+    NoSigningKey,
+
+    #[error("{0} does not hold a hex-encoded ed25519 secret key")]
+    InvalidSigningKey(std::path::PathBuf),
+
+    #[error("invalid allowed signers line: {0}")]
+    InvalidAllowedSigner(String),
+
+    #[error("commit {0}: {1}")]
+    UnverifiedCommit(String, Verification),
+
+    #[error("invalid reflog line: {0}")]
+    InvalidReflogLine(String),
+
+    #[error("unknown revision: {0}")]
+    UnknownRevision(String),
+
+    #[error("ambiguous revision: {0}")]
+    AmbiguousRevision(String),
+
+    #[error("no repository {0} in the store")]
+    UnknownRepo(String),
+
+    #[error("{} already exists and is not an empty directory", .0.display())]
+    TargetNotEmpty(std::path::PathBuf),
+
+    #[error("no linked worktree {0}")]
+    UnknownWorktree(String),
+
+    #[error("{id} is already in the store {}", .store.display())]
+    AlreadyInStore {
+        id: String,
+        store: std::path::PathBuf,
+    },
+
+    #[error(
+        "worktree {0} has staged changes or an operation in progress; use --force to remove it anyway"
+    )]
+    WorktreeBusy(String),
 
     // #[error("cannot restore data from blob")]
     // RestoreDataError,
@@ -41,3 +141,8 @@ pub enum Error {
 }
 
 pub type GatoResult<T> = std::result::Result<T, Error>;
+
+fn join_paths(paths: &[std::path::PathBuf]) -> String {
+    let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    paths.join(", ")
+}
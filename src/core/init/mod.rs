@@ -1,15 +1,24 @@
-use std::fs::{self};
+use std::{
+    fs::{self},
+    path::Path,
+};
 
 use crate::core::{
     error::GatoResult,
-    storage::{StorageEngine, local::LocalStorage},
+    storage::{RefStore, local::LocalStorage},
 };
 
 pub mod lib;
-pub fn create_file_layout(storage: &LocalStorage) -> GatoResult<()> {
+/// Write `gato.toml` and register the repository; `store` is recorded as its
+/// `[storage] path` when it does not use the default store.
+pub fn create_file_layout(storage: &LocalStorage, store: Option<&Path>) -> GatoResult<()> {
     let id = storage.repo_id();
     let work_dir = storage.work_dir();
-    let config = include_str!("config.toml").replace("<repo_id>", &id);
+    let mut config = include_str!("config.toml").replace("<repo_id>", &id);
+    if let Some(store) = store {
+        let path = toml::Value::String(store.to_string_lossy().into_owned());
+        config = config.replace("# [storage]", &format!("[storage]\npath = {path}"));
+    }
     let config_path = work_dir.join("gato.toml");
     fs::write(config_path, config)?;
     storage.push_to_repos()?;
@@ -2,6 +2,7 @@ pub mod add;
 pub mod cli;
 pub mod commit;
 pub mod config;
+pub mod diff;
 pub mod error;
 pub mod init;
 pub mod storage;
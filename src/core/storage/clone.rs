@@ -0,0 +1,55 @@
+use std::{fs, path::Path};
+
+use tracing::instrument;
+
+use crate::core::{
+    commit::Commit,
+    config::{RemoteConfig, load::load_config},
+    error::{Error, GatoResult},
+    init::lib::new_id,
+    storage::{RefStore, local::LocalStorage},
+};
+
+impl LocalStorage {
+    /// Clone the repository into `dest` as a new repository with its own id,
+    /// branches and `HEAD`. Objects are already shared through the store, so
+    /// only refs are copied; `HEAD` is then checked out in `dest` and the
+    /// source is recorded as the `origin` remote in its `gato.toml`.
+    #[instrument]
+    pub fn clone_repo(&self, dest: &Path) -> GatoResult<LocalStorage> {
+        if dest.exists() && fs::read_dir(dest)?.next().is_some() {
+            return Err(Error::TargetNotEmpty(dest.to_path_buf()));
+        }
+        fs::create_dir_all(dest)?;
+        let mut config = load_config(self.work_dir())?;
+        config.id = new_id();
+        config.worktree = None;
+        // the clone's refs go in this store, wherever `dest` is
+        if config.store_path(self.work_dir()).is_some() {
+            config.set_store_path(Some(&self.root_path.canonicalize()?), dest);
+        }
+        config.remotes.get_or_insert_default().insert(
+            "origin".to_string(),
+            RemoteConfig {
+                path: self.work_dir().canonicalize()?,
+                id: self.repo_id().to_string(),
+            },
+        );
+        fs::write(dest.join("gato.toml"), toml::to_string(&config)?)?;
+        let clone = LocalStorage::new(self.root_path.clone(), config.id, dest.to_path_buf());
+        clone.setup()?;
+        clone.push_to_repos()?;
+
+        let head = self.get_active_branche();
+        clone.write_atomic(&clone.state_file("HEAD"), head.as_bytes())?;
+        let reason = format!("clone: from {}", self.work_dir().display());
+        for branch in self.list_branchs()? {
+            let hash = self.read_ref_vec(branch.clone())?;
+            clone.update_ref_from(branch, None, hash, &reason)?;
+        }
+        if let Ok(hash) = clone.read_ref_vec(head) {
+            Commit::load(hex::encode(hash), &clone).write_tree(clone.work_dir(), &clone)?;
+        }
+        Ok(clone)
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+
+use colored::Colorize;
+use tracing::instrument;
+
+use crate::core::{
+    commit::{Commit, Tree, TreeEntry},
+    error::GatoResult,
+    storage::{ObjectStore, local::LocalStorage},
+};
+
+/// Result of walking every object reachable from the repository's branches.
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    pub commits: usize,
+    pub trees: usize,
+    pub blobs: usize,
+    /// Reachable objects that are not in the store.
+    pub missing: Vec<String>,
+    /// Trees whose entries are not in canonical order.
+    pub non_canonical_trees: Vec<String>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.non_canonical_trees.is_empty()
+    }
+
+    #[instrument]
+    pub fn run(storage: &LocalStorage) -> GatoResult<Self> {
+        let mut report = FsckReport::default();
+        let mut seen_commits = HashSet::new();
+        let mut seen_objects = HashSet::new();
+
+        let mut stack = Vec::new();
+        for branch in storage.list_branchs()? {
+            stack.push(hex::encode(storage.read_ref_vec(branch)?));
+        }
+
+        while let Some(hash) = stack.pop() {
+            if !seen_commits.insert(hash.clone()) {
+                continue;
+            }
+            if !storage.exist(&hash) {
+                report.missing.push(hash);
+                continue;
+            }
+            let commit = Commit::load(hash, storage);
+            report.commits += 1;
+            report.check_tree(commit.tree_hash(), &mut seen_objects, storage)?;
+            stack.extend(commit.parents());
+        }
+
+        Ok(report)
+    }
+
+    fn check_tree(
+        &mut self,
+        root: Vec<u8>,
+        seen: &mut HashSet<Vec<u8>>,
+        storage: &LocalStorage,
+    ) -> GatoResult<()> {
+        let mut stack = vec![root];
+        while let Some(hash) = stack.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            let hash_hex = hex::encode(&hash);
+            if !storage.exist(&hash_hex) {
+                self.missing.push(hash_hex);
+                continue;
+            }
+            let tree = Tree::load(hash_hex.clone(), storage)?;
+            self.trees += 1;
+            if !tree.is_canonical() {
+                self.non_canonical_trees.push(hash_hex);
+            }
+            for entry in tree.entries {
+                match entry {
+                    TreeEntry::Tree(_, sub) => stack.push(sub),
+                    TreeEntry::Blob(_, blob) => {
+                        if !seen.insert(blob.clone()) {
+                            continue;
+                        }
+                        let blob_hex = hex::encode(&blob);
+                        if storage.exist(&blob_hex) {
+                            self.blobs += 1;
+                        } else {
+                            self.missing.push(blob_hex);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for FsckReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "checked {} commits, {} trees, {} blobs",
+            self.commits, self.trees, self.blobs
+        )?;
+        for hash in &self.missing {
+            writeln!(f, "\t{}: {}", "missing".red(), hash)?;
+        }
+        for hash in &self.non_canonical_trees {
+            writeln!(f, "\t{}: {}", "non-canonical tree".yellow(), hash)?;
+        }
+        Ok(())
+    }
+}
@@ -1,85 +1,229 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    time::Duration,
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use tracing::instrument;
 
 use crate::core::{
-    commit::Commit,
+    commit::{Commit, graph::CommitGraph},
+    config::load::load_global_config,
     error::{Error, GatoResult},
-    storage::local::LocalStorage,
+    storage::{RefStore, StorageEngine, local::LocalStorage, merge::Operation},
 };
 
+/// How long unreferenced objects are kept when `config.toml` does not say, so
+/// a concurrent `add` can still record them.
+pub const DEFAULT_GRACE_PERIOD_MINUTES: u64 = 60;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcOptions {
+    /// Only report what would be deleted.
+    pub dry_run: bool,
+    /// Overrides `[gc] grace_period_minutes` of the store's `config.toml`.
+    pub grace_period: Option<Duration>,
+}
+
+/// What a garbage collection deleted, or would delete on a dry run.
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub dry_run: bool,
+    pub removed: Vec<String>,
+    /// Size of the removed objects.
+    pub bytes: u64,
+    /// Unreferenced objects kept because they are younger than the grace period.
+    pub recent: usize,
+    /// Objects found in use, and how many of them are commits.
+    pub marked: usize,
+    pub commits: usize,
+    /// Objects in the store.
+    pub scanned: usize,
+    pub mark_time: Duration,
+    pub sweep_time: Duration,
+    /// Repositories whose working directory was not found; their refs were
+    /// still kept.
+    pub unreachable: Vec<String>,
+}
+
+impl Display for GcReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (each, total) = if self.dry_run {
+            ("would remove", "would reclaim")
+        } else {
+            ("removing file :", "reclaimed")
+        };
+        for hash in &self.removed {
+            writeln!(f, "{each} {hash}")?;
+        }
+        writeln!(
+            f,
+            "marked {} objects in {} commits in {:.2?}, swept {} objects in {:.2?}",
+            self.marked, self.commits, self.mark_time, self.scanned, self.sweep_time
+        )?;
+        writeln!(
+            f,
+            "{} objects, {total} {} bytes",
+            self.removed.len(),
+            self.bytes
+        )?;
+        if self.recent > 0 {
+            writeln!(
+                f,
+                "kept {} unreferenced objects younger than the grace period",
+                self.recent
+            )?;
+        }
+        for id in &self.unreachable {
+            writeln!(
+                f,
+                "kept the history of {id}, whose working directory was not found; see `gato list-repos`"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The grace period from `[gc] grace_period_minutes` in the store's `config.toml`.
+pub fn grace_period(storage: &LocalStorage) -> GatoResult<Duration> {
+    let minutes = load_global_config(&storage.root_path)?
+        .gc
+        .and_then(|gc| gc.grace_period_minutes)
+        .unwrap_or(DEFAULT_GRACE_PERIOD_MINUTES);
+    Ok(Duration::from_secs(minutes * 60))
+}
+
+/// The objects the mark phase found in use.
+#[derive(Debug, Default)]
+pub struct Mark {
+    pub objects: HashSet<String>,
+    /// How many of them are commits.
+    pub commits: usize,
+}
+
 #[derive(Debug)]
-pub struct Gc {
-    storages: Vec<LocalStorage>,
+pub struct Gc<S: StorageEngine = LocalStorage> {
+    storages: Vec<S>,
 }
 
-impl Gc {
+impl<S: StorageEngine> Gc<S> {
     #[instrument]
-    pub fn new(storages: Vec<LocalStorage>) -> Self {
+    pub fn new(storages: Vec<S>) -> Self {
         Self { storages }
     }
+    /// Mark every object some repository needs. The history of all roots is
+    /// walked through every parent, visiting each commit once across branches
+    /// and repositories; the commits are then decoded in parallel.
     #[instrument]
-    pub fn list_repo_commits(storage: &LocalStorage) -> GatoResult<Vec<Commit>> {
-        let branchs = storage
-            .list_branchs()
-            .map_err(|_| Error::GcError(format!("cannot list branchs!")))?;
-        let mut all_commits = Vec::new();
-        for branch in branchs {
-            let last_commit_hash = hex::encode(
-                storage
-                    .read_ref_vec(branch)
-                    .map_err(|_| Error::GcError(format!("cannot encode repos")))?,
-            );
-            let mut last_commit = Commit::load(last_commit_hash, &storage);
-            let mut commits = vec![last_commit.clone()];
-
-            while let Some(older_hash) = last_commit.parent_hash() {
-                last_commit = Commit::load(older_hash, &storage);
-                commits.push(last_commit.clone());
+    pub fn mark(&self) -> GatoResult<Mark> {
+        let mut commits: HashSet<String> = HashSet::new();
+        let mut objects: HashSet<String> = HashSet::new();
+        for storage in &self.storages {
+            let mut graph = CommitGraph::new(storage);
+            let mut stack = Gc::roots(storage)?;
+            while let Some(hash) = stack.pop() {
+                if commits.contains(&hash) {
+                    continue;
+                }
+                stack.extend(graph.parents(&hash));
+                commits.insert(hash);
             }
+            objects.extend(storage.kept_objects()?);
+        }
+        let Some(storage) = self.storages.first() else {
+            return Ok(Mark::default());
+        };
+        let dependices = commits
+            .par_iter()
+            .fold(HashSet::new, |mut marked, hash| {
+                marked.extend(Commit::load(hash.clone(), storage).dependices());
+                marked
+            })
+            .reduce(HashSet::new, |mut a, mut b| {
+                if a.len() < b.len() {
+                    std::mem::swap(&mut a, &mut b);
+                }
+                a.extend(b);
+                a
+            });
+        objects.extend(dependices);
+        let commit_count = commits.len();
+        objects.extend(commits);
+        Ok(Mark {
+            objects,
+            commits: commit_count,
+        })
+    }
+}
 
-            all_commits.extend(commits);
+impl Gc {
+    /// The tips of all branches of the repository.
+    fn branch_heads(storage: &(impl StorageEngine + ?Sized)) -> GatoResult<Vec<String>> {
+        let branchs = storage
+            .list_refs()
+            .map_err(|_| Error::GcError("cannot list branchs!".to_string()))?;
+        let mut heads = Vec::new();
+        for branch in branchs {
+            heads.push(hex::encode(storage.read_ref(branch)?));
+        }
+        Ok(heads)
+    }
+    /// The first-parent history of every branch, newest first, one branch after
+    /// the other. Each commit object is decoded once even when branches share it.
+    #[instrument]
+    pub fn list_repo_commits(storage: &(impl StorageEngine + ?Sized)) -> GatoResult<Vec<Commit>> {
+        let mut loaded: HashMap<String, Commit> = HashMap::new();
+        let mut all_commits = Vec::new();
+        for hash in Self::list_commits_hashs(storage)? {
+            let commit = loaded
+                .entry(hash.clone())
+                .or_insert_with(|| Commit::load(hash, storage));
+            all_commits.push(commit.clone());
         }
         Ok(all_commits)
     }
     #[instrument]
-    fn list_commits_hashs(storage: &LocalStorage) -> GatoResult<Vec<String>> {
-        let branchs = storage
-            .list_branchs()
-            .map_err(|_| Error::GcError(format!("cannot list branchs!")))?;
+    fn list_commits_hashs(storage: &(impl StorageEngine + ?Sized)) -> GatoResult<Vec<String>> {
+        let mut graph = CommitGraph::new(storage);
         let mut all_hashs = Vec::new();
-        for branch in branchs {
-            let last_commit_hash = hex::encode(storage.read_ref_vec(branch)?);
-
-            let mut hashes = vec![last_commit_hash.clone()];
-
-            let mut last_commit = Commit::load(last_commit_hash, &storage);
-
-            while let Some(older_hash) = last_commit.parent_hash() {
-                hashes.push(older_hash.clone());
-
-                last_commit = Commit::load(older_hash, &storage);
-            }
-
-            all_hashs.extend(hashes);
+        for head in Self::branch_heads(storage)? {
+            all_hashs.extend(graph.first_parents(&head));
         }
         Ok(all_hashs)
     }
+    /// Every commit reachable from any branch, following all parents, ordered so
+    /// that parents always come before their children.
     #[instrument]
-    pub fn repo_dependices(storage: &LocalStorage) -> GatoResult<Vec<String>> {
-        let mut dependices = Self::list_commits_hashs(storage)?;
-        let commits = Self::list_repo_commits(storage)?;
-        for commit in commits {
-            dependices.append(&mut commit.dependices());
-        }
-
-        Ok(dependices)
+    pub fn topo_commits(storage: &(impl StorageEngine + ?Sized)) -> GatoResult<Vec<String>> {
+        let heads = Self::branch_heads(storage)?;
+        Ok(CommitGraph::new(storage).reachable(&heads))
     }
+    /// The commits `storage` keeps alive: its branch tips and the commits that
+    /// young reflog entries name, e.g. from before a reset, including the
+    /// `HEAD` reflogs of linked worktrees.
     #[instrument]
-    pub fn global_dependices(&self) -> GatoResult<Vec<String>> {
-        let mut linked_files = Vec::new();
-        for storage in &self.storages {
-            let dependices = Self::repo_dependices(&storage)?;
-            linked_files.extend(dependices);
+    pub(crate) fn roots(storage: &(impl StorageEngine + ?Sized)) -> GatoResult<Vec<String>> {
+        let mut roots = Self::branch_heads(storage)?;
+        roots.extend(storage.kept_commits()?);
+        Ok(roots)
+    }
+    /// Objects the repository needs outside its history: whatever is staged
+    /// in the index, the versions of conflicting paths, and the commit a
+    /// stopped merge, cherry-pick, revert or rebase is applying.
+    #[instrument]
+    pub fn index_dependices(storage: &LocalStorage) -> GatoResult<Vec<String>> {
+        let mut dependices = Vec::new();
+        if let Some(index) = storage.load_index()? {
+            dependices.extend(index.objects());
         }
-        Ok(linked_files)
+        for operation in Operation::ALL {
+            if let Some(head) = storage.operation_head(operation) {
+                let head = hex::encode(head);
+                dependices.extend(Commit::load(head.clone(), storage).dependices());
+                dependices.push(head);
+            }
+        }
+        Ok(dependices)
     }
 }
@@ -1,20 +1,33 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fs,
     path::PathBuf,
-    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use bincode::{config, decode_from_slice, encode_to_vec};
-use tracing::instrument;
+use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use tracing::{instrument, warn};
 
 use crate::core::{
-    add::{add_all, find_files, index::Index},
-    cli::get_store_path,
-    commit::{Commit, Tree, blob::Blob},
-    config::load::load_config,
+    add::{add_all, compute_hash, find_files, index::Index},
+    commit::{
+        Commit, Tree,
+        blob::Blob,
+        graph_file::GraphFile,
+        identity::{Identity, Role},
+        merge::MergeOptions,
+        signature,
+    },
+    config::{Config, FsyncPolicy, load::load_config},
+    diff::{Change, RenameOptions, diff_files},
     error::{Error, GatoResult},
-    storage::{StorageEngine, StorageError, gc::Gc, status::FileStatus},
+    storage::{
+        ObjectStore, RefStore, StorageEngine, StorageError,
+        gc::{Gc, GcOptions, GcReport, grace_period},
+        merge::Operation,
+        objects::{configured_fsync, is_temp_object},
+        status::FileStatus,
+    },
 };
 
 #[derive(Clone, Debug)]
@@ -22,17 +35,43 @@ pub struct LocalStorage {
     pub root_path: PathBuf,
     repo_id: String,
     work_dir: PathBuf,
+    fsync: FsyncPolicy,
+    /// Name of the linked worktree `work_dir` is, `None` for the main one.
+    worktree: Option<String>,
 }
 
 impl LocalStorage {
     #[instrument]
     pub fn new(global_path: PathBuf, repo_id: String, path: PathBuf) -> Self {
         Self {
+            fsync: configured_fsync(&global_path, &path),
+            worktree: load_config(&path).ok().and_then(|config| config.worktree),
             root_path: global_path,
             repo_id: repo_id,
             work_dir: path,
         }
     }
+    /// The repository `repo_id` of the store without a working directory, for
+    /// one whose directory cannot be found: its main worktree, with default
+    /// settings instead of those of a `gato.toml`.
+    #[instrument]
+    pub fn in_store(global_path: PathBuf, repo_id: String) -> Self {
+        Self {
+            root_path: global_path,
+            repo_id,
+            work_dir: PathBuf::new(),
+            fsync: FsyncPolicy::default(),
+            worktree: None,
+        }
+    }
+    /// The same repository seen from another of its worktrees.
+    pub(crate) fn for_worktree(&self, worktree: Option<String>, work_dir: PathBuf) -> Self {
+        Self {
+            worktree,
+            work_dir,
+            ..self.clone()
+        }
+    }
     #[instrument]
     pub fn objects_path(&self, hash: &String) -> PathBuf {
         self.root_path
@@ -42,8 +81,7 @@ impl LocalStorage {
     }
     #[instrument]
     pub fn get_active_branche(&self) -> String {
-        let head_path = self.root_path.join(self.repo_id.to_owned()).join("HEAD");
-        let branche = fs::read_to_string(head_path).unwrap_or(String::from("master"));
+        let branche = fs::read_to_string(self.state_file("HEAD")).unwrap_or(String::from("master"));
         branche
     }
     #[instrument]
@@ -66,10 +104,36 @@ impl LocalStorage {
     pub fn tmp(store_path: PathBuf) -> Self {
         Self::new(store_path, "".to_string(), PathBuf::new())
     }
+    /// Open the repository in `work_dir`, in the store its `[storage] path`
+    /// names, else in `store_path`.
     #[instrument]
     pub fn load_from(store_path: PathBuf, work_dir: PathBuf) -> GatoResult<Self> {
-        let repo_id = load_config(&work_dir)?.id;
-        Ok(Self::new(store_path, repo_id, work_dir))
+        let config = load_config(&work_dir)?;
+        let store_path = config.store_path(&work_dir).unwrap_or(store_path);
+        Ok(Self::new(store_path, config.id, work_dir))
+    }
+
+    /// Path of a per-worktree state file such as `HEAD`, `index` or
+    /// `MERGE_HEAD`; linked worktrees keep theirs under `worktrees/<name>/`.
+    pub fn state_file(&self, name: &str) -> PathBuf {
+        match &self.worktree {
+            Some(worktree) => self.worktrees_dir().join(worktree).join(name),
+            None => self.repo_path().join(name),
+        }
+    }
+
+    /// Name of the linked worktree this is, `None` for the main one.
+    pub fn worktree(&self) -> Option<&str> {
+        self.worktree.as_deref()
+    }
+
+    /// `gato.toml` of the working directory; `None` for a repository opened
+    /// with [`LocalStorage::in_store`], which has none to read.
+    pub(crate) fn work_config(&self) -> Option<Config> {
+        if self.work_dir.as_os_str().is_empty() {
+            return None;
+        }
+        load_config(&self.work_dir).ok()
     }
 
     #[instrument(fields(branch = "repo_path"))]
@@ -85,22 +149,143 @@ impl LocalStorage {
     pub fn work_dir(&self) -> &PathBuf {
         &self.work_dir
     }
+    /// When writes are flushed to disk, from `[storage] fsync`.
+    pub fn fsync_policy(&self) -> FsyncPolicy {
+        self.fsync
+    }
     #[instrument]
     pub fn add_paths(&self, paths: Vec<String>) -> GatoResult<()> {
+        let paths = self.resolve_deleted_conflicts(paths)?;
         add_paths(paths, self)?;
         Ok(())
     }
     #[instrument]
     pub fn commit(&self, message: String) -> GatoResult<()> {
-        let commit = Commit::new(message, &self)?;
-        commit.save(&self)?;
+        match self.operation_in_progress() {
+            Some(Operation::Merge) => {
+                self.merge_continue(Some(message))?;
+                return Ok(());
+            }
+            Some(Operation::Rebase) => {
+                self.rebase_continue(Some(message), MergeOptions::default())?;
+                return Ok(());
+            }
+            Some(operation) => {
+                self.pick_continue(operation, Some(message))?;
+                return Ok(());
+            }
+            None => {}
+        }
+        let commit = Commit::new(message, self)?;
+        commit.save(self)?;
         fs::remove_file(Index::index_file_path(&self))?;
         Ok(())
     }
+    /// Like [`LocalStorage::commit`], with the author given as `Name <email>`
+    /// instead of coming from the environment or `gato.toml`, with extra
+    /// `headers` such as trailers, and signed with the configured key if `sign`.
+    #[instrument]
+    pub fn commit_as(
+        &self,
+        message: String,
+        author: Option<&str>,
+        headers: BTreeMap<String, String>,
+        sign: bool,
+    ) -> GatoResult<()> {
+        if let Some(operation) = self.operation_in_progress() {
+            return Err(Error::OptionsDuringOperation(operation));
+        }
+        let key = sign.then(|| signature::signing_key(self)).transpose()?;
+        let mut commit = Commit::new(message, self)?;
+        if let Some(author) = author {
+            let author = Identity::from_env(Role::Author, self)?.with_person(author)?;
+            commit.set_identities(author, commit.committer());
+        }
+        for (key, value) in headers {
+            commit.set_header(key, value);
+        }
+        if let Some(key) = &key {
+            commit.sign(key)?;
+        }
+        commit.save(self)?;
+        fs::remove_file(Index::index_file_path(self))?;
+        Ok(())
+    }
+    /// Replace the last commit with one of the staged index, or of the same
+    /// tree when nothing is staged, keeping its parents and, unless `message`
+    /// or `author` is given, its message and author. The old signature is
+    /// dropped; with `sign` the new commit is signed. Returns the new commit hash.
+    #[instrument]
+    pub fn amend(
+        &self,
+        message: Option<String>,
+        author: Option<&str>,
+        sign: bool,
+    ) -> GatoResult<String> {
+        if let Some(operation) = self.operation_in_progress() {
+            return Err(Error::OperationInProgress(operation));
+        }
+        let key = sign.then(|| signature::signing_key(self)).transpose()?;
+        let mut commit = Commit::load_by_index(0, self)
+            .ok_or_else(|| Error::UnknownRevision("HEAD".to_string()))?;
+        let replaced = commit.hash()?;
+        let index_path = Index::index_file_path(self);
+        let (tree_hash, dependencies) = if index_path.exists() {
+            Commit::tree_from_index(self)?
+        } else {
+            (commit.tree_hash(), commit.dependices())
+        };
+        let parents = commit
+            .parents()
+            .iter()
+            .map(|p| hex::decode(p).unwrap_or_default())
+            .collect();
+        if let Some(message) = message {
+            commit.set_message(message);
+        }
+        let author = match author {
+            Some(author) => commit.author().with_person(author)?,
+            None => commit.author(),
+        };
+        commit.set_identities(author, Identity::from_env(Role::Committer, self)?);
+        commit.rewrite(tree_hash, parents, dependencies);
+        if let Some(key) = &key {
+            commit.sign(key)?;
+        }
+        let reason = format!("commit (amend): {}", commit.subject());
+        commit.save_over(self, Some(&replaced), &reason)?;
+        if index_path.exists() {
+            fs::remove_file(index_path)?;
+        }
+        commit.hash()
+    }
+    /// Stage the current content of every tracked file, i.e. those of the last
+    /// commit or already in the index; tracked files that were deleted are left
+    /// out of the index.
+    #[instrument]
+    pub fn stage_tracked(&self) -> GatoResult<()> {
+        let mut tracked: BTreeSet<PathBuf> = match self.get_last_tree() {
+            Ok(tree) => tree.files(self)?.into_keys().collect(),
+            Err(_) => BTreeSet::new(),
+        };
+        if let Ok(mut index) = Index::load(self) {
+            tracked.extend(index.entries.keys().cloned());
+            index
+                .entries
+                .retain(|path, _| self.work_dir.join(path).is_file());
+            index.save(self)?;
+        }
+        let paths = tracked
+            .into_iter()
+            .filter(|path| self.work_dir.join(path).is_file())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        self.add_paths(paths)
+    }
     #[instrument]
     pub fn check_out(&self, commit_index: usize) -> GatoResult<()> {
-        if let Some(commit) = Commit::load_by_index(commit_index, &self) {
-            commit.write_tree(&self.work_dir(), &self)?;
+        if let Some(commit) = Commit::load_by_index(commit_index, self) {
+            commit.write_tree(&self.work_dir(), self)?;
         } else {
             eprintln!("unknown commit index {commit_index}");
         }
@@ -108,9 +293,10 @@ impl LocalStorage {
     }
     #[instrument]
     pub fn soft_reset(&self, commit_index: usize) {
-        if let Some(hash) = Commit::get_hash_from_index(commit_index, &self) {
-            if let Ok(bytes) = hex::decode(hash) {
-                if let Err(e) = self.write_ref(self.get_active_branche(), bytes) {
+        if let Some(hash) = Commit::get_hash_from_index(commit_index, self) {
+            if let Ok(bytes) = hex::decode(&hash) {
+                let reason = format!("reset: moving to {hash}");
+                if let Err(e) = self.update_ref(self.get_active_branche(), bytes, &reason) {
                     eprintln!("reset failed: {e}");
                 }
             }
@@ -124,37 +310,6 @@ impl LocalStorage {
     // pub fn init(path: PathBuf) {
     //     init(path)
     // }
-    #[instrument]
-    pub fn list_repos(&self) -> GatoResult<HashSet<PathBuf>> {
-        let repos_path = self.root_path.join("repos");
-        let past_data = fs::read(repos_path);
-        let data: HashSet<PathBuf> = match past_data {
-            Ok(v) => bincode::decode_from_slice(v.as_slice(), bincode::config::standard())?.0,
-            Err(_) => HashSet::new(),
-        };
-        Ok(data)
-    }
-    #[instrument]
-    pub fn push_to_repos(&self) -> GatoResult<()> {
-        let mut data = self.list_repos()?;
-        data.insert(self.work_dir().canonicalize()?.to_owned());
-        fs::write(
-            self.root_path.join("repos"),
-            encode_to_vec(data, bincode::config::standard())?,
-        )?;
-        Ok(())
-    }
-
-    pub fn pop_from_repos(&self) -> GatoResult<()> {
-        let mut data = self.list_repos()?;
-        data.remove(&self.work_dir().canonicalize()?);
-        fs::write(
-            self.root_path.join("repos"),
-            encode_to_vec(data, bincode::config::standard())?,
-        )?;
-        Ok(())
-    }
-
     #[instrument]
     fn remove(&self, hash: &String) -> GatoResult<()> {
         let object_path = self.objects_path(hash);
@@ -174,8 +329,11 @@ impl LocalStorage {
 
         for branch in branchs {
             let entry = branch?;
-            let name = entry.file_name();
-            branchs_names.push(name.to_string_lossy().into_owned());
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // a ref being written by another process
+            if !name.ends_with(".lock") {
+                branchs_names.push(name);
+            }
         }
 
         Ok(branchs_names)
@@ -194,6 +352,7 @@ impl LocalStorage {
                 let rest = file.file_name();
                 if let Ok(p) = prefix.clone().into_string()
                     && let Ok(r) = rest.into_string()
+                    && !is_temp_object(&r)
                 {
                     hashes.push(format!("{}{}", p, r));
                 }
@@ -202,28 +361,80 @@ impl LocalStorage {
 
         Ok(hashes)
     }
+    /// Delete every object that no linked repository needs: not reachable from
+    /// a branch or a young reflog entry, not staged in an index and not used
+    /// by a stopped operation. Objects written within the grace period are
+    /// kept, since a concurrent `add` may not have saved its index yet. Only
+    /// one collection runs at a time.
     #[instrument]
-    pub fn gc(&self) -> GatoResult<()> {
-        let repos: Vec<_> = self
-            .list_repos()?
-            .iter()
-            .map(|repo| Self::load_from(get_store_path().clone(), repo.clone()))
-            .map(|res| res.ok())
-            .flatten()
-            .collect();
-
-        let gc = Gc::new(repos);
-        let dependices = gc.global_dependices()?;
-        let all_data = self.list_files()?;
-
-        for a in all_data {
-            if !dependices.contains(&a) {
-                println!("removing file : {}", a);
-                self.remove(&a)?;
+    pub fn gc(&self, options: GcOptions) -> GatoResult<GcReport> {
+        let _lock = match options.dry_run {
+            true => None,
+            false => Some(self.lock(&self.root_path.join("gc"))?),
+        };
+        let grace_period = match options.grace_period {
+            Some(grace_period) => grace_period,
+            None => grace_period(self)?,
+        };
+        // a moved or deleted working directory leaves its refs in the store, and
+        // they stay roots until the repository is relocated or deleted
+        let mut repos = Vec::new();
+        let mut unreachable = Vec::new();
+        for (repo, registered) in self.repo_storages()? {
+            if !registered.is_present() {
+                unreachable.push(registered.id);
+            } else if !options.dry_run {
+                // drops unreachable commits from each graph, which then speeds up the walk
+                let days = repo.reflog_expire_days();
+                repo.reflog_expire(days)?;
+                for worktree in repo.linked_worktrees()? {
+                    worktree.reflog_expire(days)?;
+                }
+                GraphFile::rebuild(&repo, &Gc::roots(&repo)?)?;
             }
+            repos.push(repo);
         }
 
-        Ok(())
+        let mark_start = Instant::now();
+        let mark = Gc::new(repos).mark()?;
+        let mark_time = mark_start.elapsed();
+
+        let sweep_start = Instant::now();
+        let stored = self.list_files()?;
+        let scanned = stored.len();
+        let unreferenced = stored
+            .into_par_iter()
+            .filter(|hash| !mark.objects.contains(hash))
+            .map(|hash| {
+                let metadata = fs::metadata(self.objects_path(&hash))?;
+                let age = metadata.modified()?.elapsed().unwrap_or(Duration::ZERO);
+                Ok((hash, metadata.len(), age >= grace_period))
+            })
+            .collect::<GatoResult<Vec<_>>>()?;
+        let (expired, recent): (Vec<_>, Vec<_>) = unreferenced
+            .into_iter()
+            .partition(|(_, _, expired)| *expired);
+        if !options.dry_run {
+            expired
+                .par_iter()
+                .try_for_each(|(hash, _, _)| self.remove(hash))?;
+        }
+        let mut removed: Vec<String> = expired.iter().map(|(hash, _, _)| hash.clone()).collect();
+        removed.sort();
+
+        let report = GcReport {
+            dry_run: options.dry_run,
+            bytes: expired.iter().map(|(_, len, _)| len).sum(),
+            removed,
+            recent: recent.len(),
+            marked: mark.objects.len(),
+            commits: mark.commits,
+            scanned,
+            mark_time,
+            sweep_time: sweep_start.elapsed(),
+            unreachable,
+        };
+        Ok(report)
     }
     #[instrument]
     pub fn delete_repo(&self) -> GatoResult<()> {
@@ -238,8 +449,15 @@ impl LocalStorage {
 
         if name == active_branch {
             return Err(Error::ActiveBranchDeletionError);
+        } else if let Some(other) = self.checked_out_elsewhere(&name)? {
+            return Err(StorageError::BranchCheckedOut {
+                branch: name,
+                path: other.path,
+            }
+            .into());
         } else {
-            fs::remove_file(self.get_branch_path(name))?;
+            let old = hex::encode(self.read_ref_vec(name.clone())?);
+            self.delete_ref_from(name, &old, "branch: deleted")?;
         }
 
         Ok(())
@@ -247,19 +465,46 @@ impl LocalStorage {
     #[instrument]
     pub fn status(&self) -> GatoResult<()> {
         let all_files = get_all_files(vec![".".to_string()], self);
-        let mut index: HashMap<_, _> = Index::load(&self)
-            .map_err(|_| Error::NoFilesAddedError)?
+        let staged = Index::load(self).map_err(|_| Error::NoFilesAddedError)?;
+        let mut index: HashMap<_, _> = staged
             .entries
-            .into_iter()
-            .map(|(a, entry)| (a, hex::encode(entry.hash)))
+            .iter()
+            .map(|(a, entry)| (a.clone(), hex::encode(&entry.hash)))
             .collect();
         let mut deps = Vec::new();
-        if let Some(last_commit) = Commit::load_by_index(0, &self) {
+        let mut moved = Vec::new();
+        let mut moved_to = HashSet::new();
+        if let Some(last_commit) = Commit::load_by_index(0, self) {
             deps = last_commit.dependices();
+            let head_files = Tree::load(hex::encode(last_commit.tree_hash()), self)?.files(self)?;
+            let staged_files = staged
+                .entries
+                .into_iter()
+                .map(|(path, entry)| (path, entry.hash))
+                .collect();
+            for change in diff_files(&head_files, &staged_files, RenameOptions::default(), self)? {
+                match change {
+                    Change::Renamed { from, to, .. } => {
+                        moved_to.insert(to.clone());
+                        moved.push(FileStatus::Renamed { from, to });
+                    }
+                    Change::Copied { from, to, .. } => {
+                        moved_to.insert(to.clone());
+                        moved.push(FileStatus::Copied { from, to });
+                    }
+                    _ => {}
+                }
+            }
         }
 
         println!("Changes to be committed:");
+        for file_status in &moved {
+            println!("{file_status}");
+        }
         for file_path in all_files {
+            if moved_to.contains(&file_path) {
+                continue;
+            }
             let i = index.remove(&file_path);
             let file_status = FileStatus::from(file_path, &deps, i, &self)?;
             match file_status {
@@ -270,43 +515,132 @@ impl LocalStorage {
 
         Ok(())
     }
+    /// Bring the working directory from `from` to `to`: files that only exist in
+    /// `from` are removed and files that are new or changed in `to` are written.
     #[instrument]
-    pub fn get_as_string(&self, hash: &String, storage: &LocalStorage) -> GatoResult<String> {
-        let data = self.get(hash)?;
-        let file: Blob = decode_from_slice(&data, config::standard())?.0;
-        Ok(String::from_utf8(file.restore_data(storage)?)?)
+    pub fn update_work_dir(&self, from: &Tree, to: &Tree) -> GatoResult<()> {
+        self.update_work_files(&from.files(self)?, &to.files(self)?)
     }
+
+    /// Refuse to bring the working directory from `from` to `to` when that would
+    /// lose work: staged changes, or local changes to the files it rewrites.
     #[instrument]
-    pub fn merge(&self, target_branch: String, message: String) -> GatoResult<()> {
-        let current_hash = self.read_ref_vec(self.get_active_branche())?;
-        let current_commit = Commit::load(hex::encode(&current_hash), &self);
-        let current_tree = Tree::load(hex::encode(current_commit.tree_hash()), self)?;
+    pub fn ensure_clean(&self, from: &Tree, to: &Tree) -> GatoResult<()> {
+        if Index::load(self)
+            .is_ok_and(|index| !index.entries.is_empty() || !index.conflicts.is_empty())
+        {
+            return Err(Error::StagedChanges);
+        }
+        let overwritten = self.overwritten_files(&from.files(self)?, &to.files(self)?)?;
+        if !overwritten.is_empty() {
+            return Err(Error::LocalChanges(overwritten));
+        }
+        Ok(())
+    }
 
-        let target_hash = self.read_ref_vec(target_branch)?;
-        let target_commit = Commit::load(hex::encode(&target_hash), self);
-        let target_tree = Tree::load(hex::encode(target_commit.tree_hash()), self)?;
+    /// Files that [`Self::update_work_files`] would rewrite or remove although
+    /// they differ from `old_files`, and untracked files in the way of new ones.
+    #[instrument]
+    pub fn overwritten_files(
+        &self,
+        old_files: &BTreeMap<PathBuf, Vec<u8>>,
+        new_files: &BTreeMap<PathBuf, Vec<u8>>,
+    ) -> GatoResult<Vec<PathBuf>> {
+        let touched: BTreeSet<&PathBuf> = old_files
+            .iter()
+            .filter(|(path, hash)| new_files.get(*path) != Some(*hash))
+            .map(|(path, _)| path)
+            .chain(new_files.keys().filter(|p| !old_files.contains_key(*p)))
+            .collect();
+        let mut overwritten = Vec::new();
+        for path in touched {
+            let full_path = self.work_dir.join(path);
+            if !full_path.is_file() {
+                continue;
+            }
+            let expected = old_files.get(path).or_else(|| new_files.get(path));
+            let Some(hash) = expected else { continue };
+            let data = fs::read(&full_path)?;
+            if compute_hash(&data)[..] != hash[..]
+                && data != Blob::new(hex::encode(hash), self)?.restore_data(self)?
+            {
+                overwritten.push(path.clone());
+            }
+        }
+        Ok(overwritten)
+    }
 
-        let base = Commit::base(&current_commit, &target_commit, self);
-        let base_tree = if let Some(base_commit) = base {
-            Tree::load(hex::encode(base_commit.tree_hash()), self)?
-        } else {
-            Tree::new("root".to_string())
-        };
-        let mut deps: Vec<String> = Vec::new();
-        let merged = Tree::merge(base_tree, current_tree, target_tree, &mut deps, self)?;
-
-        let new_commit = Commit::new_merged(
-            message,
-            merged.hash(),
-            current_hash,
-            target_hash,
-            deps,
-            self,
-        )?;
-        new_commit.save(self)?;
+    /// Like [`Self::update_work_dir`], for flat maps of path to blob hash.
+    #[instrument]
+    pub fn update_work_files(
+        &self,
+        old_files: &BTreeMap<PathBuf, Vec<u8>>,
+        new_files: &BTreeMap<PathBuf, Vec<u8>>,
+    ) -> GatoResult<()> {
+        for path in old_files.keys().filter(|p| !new_files.contains_key(*p)) {
+            let full_path = self.work_dir.join(path);
+            match fs::remove_file(&full_path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            let mut dir = full_path.parent();
+            while let Some(d) = dir {
+                if d == self.work_dir || fs::remove_dir(d).is_err() {
+                    break;
+                }
+                dir = d.parent();
+            }
+        }
+
+        for (path, hash) in new_files {
+            if old_files.get(path) == Some(hash) && self.work_dir.join(path).exists() {
+                continue;
+            }
+            let full_path = self.work_dir.join(path);
+            if let Some(parent) = full_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            Blob::new(hex::encode(hash), self)?.restore(full_path, self)?;
+        }
         Ok(())
     }
 
+    /// Resolve a revision to a commit hash. Accepts `HEAD`, a branch name, a full
+    /// commit hash or an unambiguous prefix of one (at least 4 characters).
+    #[instrument]
+    pub fn resolve_rev(&self, rev: &str) -> GatoResult<String> {
+        let unknown = || Error::UnknownRevision(rev.to_string());
+        if rev == "HEAD" {
+            return Ok(hex::encode(self.read_ref_vec(self.get_active_branche())?));
+        }
+        if let Some((name, n)) = rev.strip_suffix('}').and_then(|r| r.split_once("@{")) {
+            let n = n.parse().map_err(|_| unknown())?;
+            return self.resolve_reflog(name, n);
+        }
+        if let Ok(hash) = self.read_ref_vec(rev.to_string()) {
+            return Ok(hex::encode(hash));
+        }
+        if rev.len() < 4 || !rev.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(unknown());
+        }
+        let rev = rev.to_ascii_lowercase();
+        if rev.len() == 64 && self.exist(&rev) {
+            return Commit::try_load(&rev, self)
+                .map(|_| rev.clone())
+                .map_err(|_| unknown());
+        }
+        // trees, blobs and chunks share the prefix space but do not name commits
+        let mut matches = self
+            .list_files()?
+            .into_iter()
+            .filter(|hash| hash.starts_with(&rev) && Commit::try_load(hash, self).is_ok());
+        match (matches.next(), matches.next()) {
+            (Some(hash), None) => Ok(hash),
+            (Some(_), Some(_)) => Err(Error::AmbiguousRevision(rev)),
+            _ => Err(unknown()),
+        }
+    }
+
     pub fn get_last_tree(&self) -> GatoResult<Tree> {
         let commit = Commit::load_by_index(0, self).ok_or(Error::IoError(std::io::Error::new(
             std::io::ErrorKind::NotFound,
@@ -319,7 +653,7 @@ impl LocalStorage {
     }
 }
 
-impl StorageEngine for LocalStorage {
+impl ObjectStore for LocalStorage {
     #[instrument]
     fn get(&self, hash: &String) -> Result<Vec<u8>, super::StorageError> {
         let object_path = self.objects_path(hash);
@@ -328,60 +662,167 @@ impl StorageEngine for LocalStorage {
     }
     #[instrument]
     fn put(&self, hash: &String, data: Vec<u8>) -> Result<(), super::StorageError> {
-        if !self.exist(hash) {
-            let object_path = self.objects_path(hash);
-
-            if let Some(parent) = object_path.parent() {
-                std::fs::create_dir_all(parent).map_err(|_| StorageError::WriteError)?;
-            }
-
-            fs::write(object_path, data).map_err(|_| StorageError::WriteError)?;
+        if !self.freshen(hash) {
+            self.write_object(hash, &data)
+                .map_err(|_| StorageError::WriteError)?;
         }
         Ok(())
     }
     #[instrument]
     fn exist(&self, hash: &String) -> bool {
-        self.objects_path(hash).exists()
+        // an empty object is what a crash mid-write used to leave behind
+        fs::metadata(self.objects_path(hash)).is_ok_and(|m| m.len() > 0)
+    }
+    fn freshen(&self, hash: &String) -> bool {
+        LocalStorage::freshen(self, hash)
     }
+    fn list_objects(&self) -> Result<Vec<String>, StorageError> {
+        self.list_files().map_err(|_| StorageError::ReadError)
+    }
+    fn compression_level(&self) -> i32 {
+        self.work_config()
+            .and_then(|config| config.compression)
+            .and_then(|compression| compression.level)
+            .unwrap_or(1)
+    }
+    fn graph_file(&self) -> Option<GraphFile> {
+        GraphFile::open(self)
+    }
+    fn add_to_graph(&self, hash: &str, commit: &Commit) {
+        // the commit-graph file is only a cache, a stale one is still correct
+        if let Err(err) = GraphFile::add(self, hash, commit) {
+            warn!(%err, "could not update the commit-graph file");
+        }
+    }
+}
+
+impl RefStore for LocalStorage {
+    fn read_ref(&self, ref_name: String) -> Result<Vec<u8>, StorageError> {
+        self.read_ref_vec(ref_name)
+    }
+
     #[instrument]
     fn write_ref(&self, ref_name: String, hash: Vec<u8>) -> Result<(), super::StorageError> {
-        let ref_path = self.get_branch_path(ref_name);
+        self.write_atomic(&self.get_branch_path(ref_name), &hash)
+    }
 
-        if let Some(parent) = ref_path.parent() {
-            std::fs::create_dir_all(parent).map_err(|_| StorageError::WriteError)?;
-        }
+    fn delete_ref(&self, ref_name: String) -> Result<(), StorageError> {
+        let lock = self.lock(&self.get_branch_path(ref_name))?;
+        Ok(fs::remove_file(lock.path())?)
+    }
 
-        fs::write(ref_path, hash).map_err(|_| StorageError::WriteError)
+    /// Also logs the change in the branch's reflog and, for the checked-out
+    /// branch, in the reflog of `HEAD`.
+    #[instrument]
+    fn update_ref_from(
+        &self,
+        ref_name: String,
+        expected: Option<&str>,
+        hash: Vec<u8>,
+        reason: &str,
+    ) -> Result<(), StorageError> {
+        self.move_ref(ref_name, Some(expected), hash, reason)
+    }
+
+    fn list_refs(&self) -> Result<Vec<String>, StorageError> {
+        self.list_branchs().map_err(|_| StorageError::ReadError)
+    }
+
+    fn head(&self) -> String {
+        self.get_active_branche()
+    }
+
+    fn set_head(&self, branch: String) -> Result<(), StorageError> {
+        self.write_atomic(&self.state_file("HEAD"), branch.as_bytes())
     }
 
-    // fn read_ref(&self, ref_name: String) -> Result<String, super::StorageError> {
-    //     self.read_ref_vec(ref_name).map(|a| hex::encode(a))
-    // }
     #[instrument]
     fn setup(&self) -> Result<(), StorageError> {
         let heads_path = self.repo_path().join("refs").join("heads");
         std::fs::create_dir_all(heads_path).map_err(|_| StorageError::WriteError)?;
         Ok(())
     }
-    #[instrument]
-    fn new_branch(&self, name: String) -> Result<(), StorageError> {
-        let branch_path = self.get_branch_path(name);
-        if let Some(parent) = branch_path.parent() {
-            std::fs::create_dir_all(parent).map_err(|_| StorageError::WriteError)?;
+
+    fn load_index(&self) -> Result<Option<Index>, StorageError> {
+        let data = match fs::read(Index::index_file_path(self)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let index = Index::decode(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        Ok(Some(index))
+    }
+
+    fn save_index(&self, index: &Index) -> Result<(), StorageError> {
+        index.save_locked(Index::lock(self)?)
+    }
+
+    fn update_index(&self, update: &mut dyn FnMut(&mut Index)) -> Result<(), StorageError> {
+        let lock = Index::lock(self)?;
+        let mut index = self.load_index()?.unwrap_or_default();
+        update(&mut index);
+        index.save_locked(lock)
+    }
+
+    fn delete_index(&self) -> Result<(), StorageError> {
+        match fs::remove_file(Index::index_file_path(self)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
         }
-        fs::write(branch_path, self.read_ref_vec(self.get_active_branche())?)
-            .map_err(|_| StorageError::WriteError)
     }
+}
+
+/// Branch operations that also log to the reflogs and keep a branch checked
+/// out in one worktree at a time.
+impl StorageEngine for LocalStorage {
     #[instrument]
     fn change_branch(&self, name: String) -> Result<(), StorageError> {
-        fs::write(self.repo_path().join("HEAD"), name)?;
-        Ok(())
+        if let Some(other) = self.checked_out_elsewhere(&name)? {
+            return Err(StorageError::BranchCheckedOut {
+                branch: name,
+                path: other.path,
+            });
+        }
+        let lock = self.lock(&self.state_file("HEAD"))?;
+        let from = self.get_active_branche();
+        let old = self.read_ref_vec(from.clone()).ok().map(hex::encode);
+        let new = self.read_ref_vec(name.clone()).ok().map(hex::encode);
+        self.log_ref_change(
+            "HEAD",
+            old,
+            new,
+            &format!("checkout: moving from {from} to {name}"),
+        )?;
+        lock.commit(name.as_bytes())
+    }
+    fn user(&self) -> GatoResult<(String, Option<String>)> {
+        let config = load_config(self.work_dir())?;
+        Ok((config.author, config.email))
+    }
+    /// Commits young reflog entries name, in this worktree and the linked ones.
+    fn kept_commits(&self) -> GatoResult<Vec<String>> {
+        let days = self.reflog_expire_days();
+        let mut kept = self.reflog_roots(days)?;
+        for worktree in self.linked_worktrees()? {
+            kept.extend(worktree.reflog_roots(days)?);
+        }
+        Ok(kept)
+    }
+    /// What the index of each worktree needs, and the commit a stopped merge,
+    /// cherry-pick, revert or rebase is applying.
+    fn kept_objects(&self) -> GatoResult<Vec<String>> {
+        let mut kept = Gc::index_dependices(self)?;
+        for worktree in self.linked_worktrees()? {
+            kept.extend(Gc::index_dependices(&worktree)?);
+        }
+        Ok(kept)
     }
 }
 #[instrument]
 fn add_paths(paths: Vec<String>, storage: &LocalStorage) -> GatoResult<()> {
     let all_files = get_all_files(paths, storage);
-    add_all(all_files, Arc::new(storage.clone()))?;
+    add_all(all_files, storage.work_dir(), storage)?;
     Ok(())
 }
 #[instrument]
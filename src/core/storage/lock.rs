@@ -0,0 +1,124 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use tracing::instrument;
+
+use crate::core::{
+    config::FsyncPolicy,
+    storage::{StorageError, local::LocalStorage},
+};
+
+/// An exclusive lock on a file, held by creating `<file>.lock` next to it.
+///
+/// The new content is written to the lock file and renamed over the target,
+/// so readers see either the old or the new file, never a partial one. The
+/// lock file is removed when the lock is dropped, or fails to commit, before
+/// it was renamed.
+#[derive(Debug)]
+pub struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+    sync: bool,
+    /// Set once the lock file was renamed over the target.
+    committed: bool,
+}
+
+impl LockFile {
+    /// Take the lock on `path`, failing with [`StorageError::Locked`] while
+    /// another process holds it.
+    #[instrument]
+    pub fn acquire(path: &Path) -> Result<Self, StorageError> {
+        let lock_path = lock_path(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(StorageError::Locked(lock_path));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            lock_path,
+            file: Some(file),
+            sync: false,
+            committed: false,
+        })
+    }
+
+    /// Flush the new content, and the directory entry of the rename, to disk
+    /// on commit.
+    pub fn synced(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// The file this lock protects.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace the locked file with `data` and release the lock.
+    #[instrument(skip(self, data), fields(path = %self.path.display()))]
+    pub fn commit(mut self, data: &[u8]) -> Result<(), StorageError> {
+        let mut file = self.file.take().expect("an uncommitted lock has its file");
+        file.write_all(data)?;
+        if self.sync {
+            file.sync_all()?;
+        }
+        drop(file);
+        fs::rename(&self.lock_path, &self.path)?;
+        self.committed = true;
+        if self.sync {
+            sync_parent(&self.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// `<path>.lock`, keeping any extension of `path`.
+pub fn lock_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".lock");
+    path.with_file_name(name)
+}
+
+/// Flush the directory holding `path`, so a rename into it survives a crash.
+pub fn sync_parent(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+impl LocalStorage {
+    /// Take the lock on `path`, syncing on commit under the `always` policy.
+    pub fn lock(&self, path: &Path) -> Result<LockFile, StorageError> {
+        Ok(LockFile::acquire(path)?.synced(self.fsync_policy() == FsyncPolicy::Always))
+    }
+
+    /// Replace `path` with `data` under its lock.
+    pub fn write_atomic(&self, path: &Path, data: &[u8]) -> Result<(), StorageError> {
+        self.lock(path)?.commit(data)
+    }
+}
@@ -0,0 +1,156 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+};
+
+use crate::core::{
+    add::index::Index,
+    error::GatoResult,
+    storage::{ObjectStore, RefStore, StorageEngine, StorageError},
+};
+
+/// A backend keeping objects, refs and the index in memory, for tests and
+/// tools that build history without a store on disk. It keeps no reflogs.
+#[derive(Debug)]
+pub struct MemoryStorage {
+    objects: RwLock<HashMap<String, Vec<u8>>>,
+    refs: RwLock<BTreeMap<String, Vec<u8>>>,
+    head: RwLock<String>,
+    index: RwLock<Option<Index>>,
+    user: (String, Option<String>),
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make commits as `name` and `email`.
+    pub fn with_user(mut self, name: &str, email: Option<&str>) -> Self {
+        self.user = (name.to_string(), email.map(str::to_string));
+        self
+    }
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        Self {
+            objects: RwLock::default(),
+            refs: RwLock::default(),
+            head: RwLock::new("master".to_string()),
+            index: RwLock::default(),
+            user: ("gato".to_string(), None),
+        }
+    }
+}
+
+impl ObjectStore for MemoryStorage {
+    fn get(&self, hash: &String) -> Result<Vec<u8>, StorageError> {
+        let objects = self.objects.read().map_err(|_| StorageError::ReadError)?;
+        objects.get(hash).cloned().ok_or(StorageError::ReadError)
+    }
+
+    fn put(&self, hash: &String, data: Vec<u8>) -> Result<(), StorageError> {
+        let mut objects = self.objects.write().map_err(|_| StorageError::WriteError)?;
+        objects.entry(hash.clone()).or_insert(data);
+        Ok(())
+    }
+
+    fn exist(&self, hash: &String) -> bool {
+        self.objects
+            .read()
+            .is_ok_and(|objects| objects.contains_key(hash))
+    }
+
+    fn list_objects(&self) -> Result<Vec<String>, StorageError> {
+        let objects = self.objects.read().map_err(|_| StorageError::ReadError)?;
+        Ok(objects.keys().cloned().collect())
+    }
+}
+
+impl RefStore for MemoryStorage {
+    fn read_ref(&self, ref_name: String) -> Result<Vec<u8>, StorageError> {
+        let refs = self.refs.read().map_err(|_| StorageError::ReadError)?;
+        refs.get(&ref_name).cloned().ok_or(StorageError::ReadError)
+    }
+
+    fn write_ref(&self, ref_name: String, hash: Vec<u8>) -> Result<(), StorageError> {
+        let mut refs = self.refs.write().map_err(|_| StorageError::WriteError)?;
+        refs.insert(ref_name, hash);
+        Ok(())
+    }
+
+    fn delete_ref(&self, ref_name: String) -> Result<(), StorageError> {
+        let mut refs = self.refs.write().map_err(|_| StorageError::WriteError)?;
+        refs.remove(&ref_name)
+            .map(drop)
+            .ok_or(StorageError::ReadError)
+    }
+
+    fn update_ref_from(
+        &self,
+        ref_name: String,
+        expected: Option<&str>,
+        hash: Vec<u8>,
+        _reason: &str,
+    ) -> Result<(), StorageError> {
+        let mut refs = self.refs.write().map_err(|_| StorageError::WriteError)?;
+        let actual = refs.get(&ref_name).map(hex::encode);
+        if actual.as_deref() != expected {
+            return Err(StorageError::RefChanged {
+                name: ref_name,
+                expected: expected.map(str::to_string),
+                actual,
+            });
+        }
+        refs.insert(ref_name, hash);
+        Ok(())
+    }
+
+    fn list_refs(&self) -> Result<Vec<String>, StorageError> {
+        let refs = self.refs.read().map_err(|_| StorageError::ReadError)?;
+        Ok(refs.keys().cloned().collect())
+    }
+
+    fn head(&self) -> String {
+        self.head
+            .read()
+            .map_or_else(|_| "master".to_string(), |head| head.clone())
+    }
+
+    fn set_head(&self, branch: String) -> Result<(), StorageError> {
+        *self.head.write().map_err(|_| StorageError::WriteError)? = branch;
+        Ok(())
+    }
+
+    fn setup(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    fn load_index(&self) -> Result<Option<Index>, StorageError> {
+        let index = self.index.read().map_err(|_| StorageError::ReadError)?;
+        Ok(index.clone())
+    }
+
+    fn save_index(&self, index: &Index) -> Result<(), StorageError> {
+        *self.index.write().map_err(|_| StorageError::WriteError)? = Some(index.clone());
+        Ok(())
+    }
+
+    fn update_index(&self, update: &mut dyn FnMut(&mut Index)) -> Result<(), StorageError> {
+        let mut index = self.index.write().map_err(|_| StorageError::WriteError)?;
+        update(index.get_or_insert_default());
+        Ok(())
+    }
+
+    fn delete_index(&self) -> Result<(), StorageError> {
+        *self.index.write().map_err(|_| StorageError::WriteError)? = None;
+        Ok(())
+    }
+}
+
+impl StorageEngine for MemoryStorage {
+    fn user(&self) -> GatoResult<(String, Option<String>)> {
+        Ok(self.user.clone())
+    }
+}
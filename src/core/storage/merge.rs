@@ -0,0 +1,441 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use tracing::instrument;
+
+use crate::core::{
+    add::{
+        index::{ConflictEntry, Index},
+        index_entry,
+    },
+    commit::{
+        Commit, Tree,
+        blob::Blob,
+        graph::CommitGraph,
+        merge::{MergeContext, MergeOptions, Side},
+    },
+    error::{Error, GatoResult},
+    storage::{RefStore, local::LocalStorage},
+};
+
+/// How `merge` treats a target that is a descendant of the current branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FastForward {
+    /// Move the branch ref instead of creating a merge commit when possible.
+    #[default]
+    Allow,
+    /// Always create a merge commit (`--no-ff`).
+    Never,
+    /// Refuse to merge unless the branch can be fast-forwarded (`--ff-only`).
+    Only,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// The target is already part of the current branch; nothing was done.
+    UpToDate,
+    /// The current branch was moved forward to the target commit.
+    FastForward { to: String },
+    /// A merge commit was created.
+    Merged { commit: String },
+    /// The merge stopped with conflicts in these paths; the merge state is kept
+    /// until `merge --continue` or `merge --abort`.
+    Conflicted { paths: Vec<PathBuf> },
+}
+
+impl std::fmt::Display for MergeOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeOutcome::UpToDate => write!(f, "Already up to date."),
+            MergeOutcome::FastForward { to } => write!(f, "Fast-forward to {to}"),
+            MergeOutcome::Merged { commit } => write!(f, "Merge made commit {commit}"),
+            MergeOutcome::Conflicted { paths } => {
+                for path in paths {
+                    writeln!(f, "CONFLICT: {}", path.display())?;
+                }
+                write!(
+                    f,
+                    "Automatic merge failed; fix conflicts, `gato add` them and run `gato merge --continue`."
+                )
+            }
+        }
+    }
+}
+
+/// A command that can stop on conflicts and be resumed with `--continue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Merge,
+    CherryPick,
+    Revert,
+    Rebase,
+}
+
+impl Operation {
+    pub(crate) const ALL: [Operation; 4] = [
+        Operation::Merge,
+        Operation::CherryPick,
+        Operation::Revert,
+        Operation::Rebase,
+    ];
+
+    /// State file holding the commit being applied.
+    fn head_file(self) -> &'static str {
+        match self {
+            Operation::Merge => "MERGE_HEAD",
+            Operation::CherryPick => "CHERRY_PICK_HEAD",
+            Operation::Revert => "REVERT_HEAD",
+            Operation::Rebase => "REBASE_HEAD",
+        }
+    }
+}
+
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Operation::Merge => "merge",
+            Operation::CherryPick => "cherry-pick",
+            Operation::Revert => "revert",
+            Operation::Rebase => "rebase",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl LocalStorage {
+    #[instrument]
+    pub fn merge(
+        &self,
+        target_branch: String,
+        message: Option<String>,
+        fast_forward: FastForward,
+        options: MergeOptions,
+    ) -> GatoResult<MergeOutcome> {
+        if let Some(operation) = self.operation_in_progress() {
+            return Err(Error::OperationInProgress(operation));
+        }
+        let message = message.unwrap_or_else(|| format!("Merge branch '{target_branch}'"));
+        let active_branch = self.get_active_branche();
+        let current_hash = self.read_ref_vec(active_branch.clone())?;
+        let current_commit = Commit::load(hex::encode(&current_hash), self);
+        let current_tree = Tree::load(hex::encode(current_commit.tree_hash()), self)?;
+
+        let target_hash = self.read_ref_vec(target_branch.clone())?;
+        let target_commit = Commit::load(hex::encode(&target_hash), self);
+        let target_tree = Tree::load(hex::encode(target_commit.tree_hash()), self)?;
+
+        let mut graph = CommitGraph::new(self);
+        let (current_hex, target_hex) = (hex::encode(&current_hash), hex::encode(&target_hash));
+        if graph.is_ancestor(&target_hex, &current_hex) {
+            return Ok(MergeOutcome::UpToDate);
+        }
+        let can_fast_forward = graph.is_ancestor(&current_hex, &target_hex);
+        match fast_forward {
+            FastForward::Only if !can_fast_forward => return Err(Error::NotFastForward),
+            FastForward::Allow | FastForward::Only if can_fast_forward => {
+                self.ensure_clean(&current_tree, &target_tree)?;
+                let reason = format!("merge {target_branch}: Fast-forward");
+                self.update_ref_from(active_branch, Some(&current_hex), target_hash, &reason)?;
+                self.update_work_dir(&current_tree, &target_tree)?;
+                // nothing is staged, so the new HEAD is all the index has to match
+                match fs::remove_file(Index::index_file_path(self)) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                }
+                return Ok(MergeOutcome::FastForward { to: target_hex });
+            }
+            _ => {}
+        }
+
+        let base_tree = graph.base_tree(&[current_hex], &target_hex)?;
+        let mut ctx = MergeContext::with_options(options);
+        let merged = Tree::merge(base_tree, current_tree.clone(), target_tree, &mut ctx, self)?;
+        self.ensure_clean(&current_tree, &merged)?;
+        self.update_work_dir(&current_tree, &merged)?;
+
+        if !ctx.conflicts.is_empty() {
+            self.stage_merge_result(&merged, ctx.conflicts.clone())?;
+            self.write_operation_state(Operation::Merge, &target_hash, &message)?;
+            return Ok(MergeOutcome::Conflicted {
+                paths: ctx.conflicts.into_keys().collect(),
+            });
+        }
+
+        let new_commit = Commit::new_merged(
+            message,
+            merged.hash(),
+            vec![current_hash, target_hash],
+            merged.dependencies(self)?,
+            self,
+        )?;
+        new_commit.save(self)?;
+        Ok(MergeOutcome::Merged {
+            commit: new_commit.hash()?,
+        })
+    }
+
+    /// Merge several branches at once into one commit with a parent for each of
+    /// them (an octopus merge).
+    ///
+    /// Branches that are already merged are left out. The targets are merged
+    /// one after the other into a running tree; if any of them conflicts the
+    /// whole merge is refused and nothing is changed.
+    #[instrument]
+    pub fn octopus_merge(
+        &self,
+        targets: Vec<String>,
+        message: Option<String>,
+        options: MergeOptions,
+    ) -> GatoResult<MergeOutcome> {
+        if let Some(operation) = self.operation_in_progress() {
+            return Err(Error::OperationInProgress(operation));
+        }
+        let current_hex = hex::encode(self.read_ref_vec(self.get_active_branche())?);
+        let mut graph = CommitGraph::new(self);
+        let mut heads = vec![current_hex.clone()];
+        let mut pending: Vec<String> = Vec::new();
+        for target in &targets {
+            let hash = hex::encode(self.read_ref_vec(target.clone())?);
+            let merged = heads
+                .iter()
+                .chain(&pending)
+                .any(|head| graph.is_ancestor(&hash, head));
+            if !merged {
+                pending.push(hash);
+            }
+        }
+        if pending.is_empty() {
+            return Ok(MergeOutcome::UpToDate);
+        }
+
+        let tree_of = |hash: &String| {
+            let commit = Commit::load(hash.clone(), self);
+            Tree::load(hex::encode(commit.tree_hash()), self)
+        };
+        let current_tree = tree_of(&current_hex)?;
+        let mut merged = current_tree.clone();
+        for target in pending {
+            let base_tree = graph.base_tree(&heads, &target)?;
+            let mut ctx = MergeContext::with_options(options);
+            merged = Tree::merge(base_tree, merged, tree_of(&target)?, &mut ctx, self)?;
+            if !ctx.conflicts.is_empty() {
+                return Err(Error::OctopusConflict(ctx.conflicts.into_keys().collect()));
+            }
+            heads.push(target);
+        }
+        self.ensure_clean(&current_tree, &merged)?;
+        self.update_work_dir(&current_tree, &merged)?;
+
+        let message = message.unwrap_or_else(|| octopus_message(&targets));
+        let parents = heads.iter().flat_map(hex::decode).collect();
+        let new_commit = Commit::new_merged(
+            message,
+            merged.hash(),
+            parents,
+            merged.dependencies(self)?,
+            self,
+        )?;
+        new_commit.save(self)?;
+        Ok(MergeOutcome::Merged {
+            commit: new_commit.hash()?,
+        })
+    }
+
+    /// Conclude a conflicted merge once every conflict has been staged.
+    #[instrument]
+    pub fn merge_continue(&self, message: Option<String>) -> GatoResult<MergeOutcome> {
+        let Some(target_hash) = self.merge_head() else {
+            return Err(Error::NotInProgress(Operation::Merge));
+        };
+        let message = match message {
+            Some(message) => message,
+            None => fs::read_to_string(self.state_file("MERGE_MSG"))?,
+        };
+        let (tree_hash, dependencies) = Commit::tree_from_index(self)?;
+        let current_hash = self.read_ref_vec(self.get_active_branche())?;
+
+        let new_commit = Commit::new_merged(
+            message,
+            tree_hash,
+            vec![current_hash, target_hash],
+            dependencies,
+            self,
+        )?;
+        new_commit.save(self)?;
+        fs::remove_file(Index::index_file_path(self))?;
+        self.clear_operation_state()?;
+        Ok(MergeOutcome::Merged {
+            commit: new_commit.hash()?,
+        })
+    }
+
+    /// Throw away a conflicted merge and restore the working directory to HEAD.
+    #[instrument]
+    pub fn merge_abort(&self) -> GatoResult<()> {
+        if self.merge_head().is_none() {
+            return Err(Error::NotInProgress(Operation::Merge));
+        }
+        self.restore_head()
+    }
+
+    /// Drop the index and state of a conflicted operation and bring the working
+    /// directory back to HEAD.
+    pub(crate) fn restore_head(&self) -> GatoResult<()> {
+        let mut merged_files: BTreeMap<PathBuf, Vec<u8>> = BTreeMap::new();
+        if let Ok(index) = Index::load(self) {
+            for (path, entry) in index.entries {
+                merged_files.insert(path, entry.hash);
+            }
+            // An empty hash never matches, so conflicted files are always rewritten.
+            for path in index.conflicts.into_keys() {
+                merged_files.insert(path, Vec::new());
+            }
+            fs::remove_file(Index::index_file_path(self))?;
+        }
+        let head_tree = self.get_last_tree()?;
+        self.update_work_files(&merged_files, &head_tree.files(self)?)?;
+        self.clear_operation_state()
+    }
+
+    /// Write one side's version of a conflicted path to the working directory.
+    ///
+    /// The path stays conflicted until it is staged with `gato add`.
+    #[instrument]
+    pub fn checkout_side(&self, path: &Path, side: Side) -> GatoResult<()> {
+        if self.operation_in_progress().is_none() {
+            return Err(Error::NotInProgress(Operation::Merge));
+        }
+        let index = Index::load(self)?;
+        let Some(conflict) = index.conflicts.get(path) else {
+            return Err(Error::NotConflicted(path.to_path_buf()));
+        };
+        let hash = match side {
+            Side::Ours => &conflict.ours,
+            Side::Theirs => &conflict.theirs,
+        };
+        let full_path = self.work_dir().join(path);
+        match hash {
+            Some(hash) => Blob::new(hex::encode(hash), self)?.restore(full_path, self)?,
+            None => match fs::remove_file(full_path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+
+    /// Unresolved conflicts in the current merge, cherry-pick or revert, by path.
+    #[instrument]
+    pub fn conflicts(&self) -> GatoResult<BTreeMap<PathBuf, ConflictEntry>> {
+        match Index::load(self) {
+            Ok(index) => Ok(index.conflicts),
+            Err(_) => Ok(BTreeMap::new()),
+        }
+    }
+
+    /// Mark conflicts under `paths` whose file was removed from the working
+    /// directory as resolved by deletion, and return the paths left to stage.
+    pub(crate) fn resolve_deleted_conflicts(&self, paths: Vec<String>) -> GatoResult<Vec<String>> {
+        let Ok(mut index) = Index::load(self) else {
+            return Ok(paths);
+        };
+        let deleted: Vec<PathBuf> = index
+            .conflicts
+            .keys()
+            .filter(|conflict| {
+                !self.work_dir().join(conflict).exists()
+                    && paths
+                        .iter()
+                        .any(|path| path == "." || conflict.starts_with(path))
+            })
+            .cloned()
+            .collect();
+        if deleted.is_empty() {
+            return Ok(paths);
+        }
+        for path in &deleted {
+            index.conflicts.remove(path);
+        }
+        index.save(self)?;
+        Ok(paths
+            .into_iter()
+            .filter(|path| !deleted.iter().any(|d| d == Path::new(path)))
+            .collect())
+    }
+
+    /// The commit being merged in, while a conflicted merge is in progress.
+    pub fn merge_head(&self) -> Option<Vec<u8>> {
+        self.operation_head(Operation::Merge)
+    }
+
+    /// The operation stopped on conflicts, if any.
+    pub fn operation_in_progress(&self) -> Option<Operation> {
+        Operation::ALL
+            .into_iter()
+            .find(|operation| self.state_file(operation.head_file()).exists())
+    }
+
+    /// The commit applied by the stopped `operation`, if it is the one in progress.
+    pub(crate) fn operation_head(&self, operation: Operation) -> Option<Vec<u8>> {
+        fs::read(self.state_file(operation.head_file())).ok()
+    }
+
+    pub(crate) fn write_operation_state(
+        &self,
+        operation: Operation,
+        head: &[u8],
+        message: &str,
+    ) -> GatoResult<()> {
+        fs::write(self.state_file(operation.head_file()), head)?;
+        fs::write(self.state_file("MERGE_MSG"), message)?;
+        Ok(())
+    }
+
+    pub(crate) fn clear_operation_state(&self) -> GatoResult<()> {
+        let head_files = Operation::ALL.map(Operation::head_file);
+        for name in head_files.into_iter().chain(["MERGE_MSG"]) {
+            match fs::remove_file(self.state_file(name)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the index with the merged tree: clean paths are staged as they are
+    /// in `merged`, conflicted ones only get their stages recorded.
+    pub(crate) fn stage_merge_result(
+        &self,
+        merged: &Tree,
+        conflicts: BTreeMap<PathBuf, ConflictEntry>,
+    ) -> GatoResult<()> {
+        let mut index = Index::new();
+        for (path, hash) in merged.files(self)? {
+            if conflicts.contains_key(&path) {
+                continue;
+            }
+            index
+                .dependencies
+                .extend(Blob::dependencies(hex::encode(&hash), self)?);
+            let entry = index_entry(&self.work_dir().join(&path), hash)?;
+            index.entries.insert(path, entry);
+        }
+        index.conflicts = conflicts;
+        index.save(self)?;
+        Ok(())
+    }
+}
+
+/// `Merge branches 'a', 'b' and 'c'`.
+fn octopus_message(targets: &[String]) -> String {
+    let quoted: Vec<String> = targets.iter().map(|t| format!("'{t}'")).collect();
+    match quoted.split_last() {
+        Some((last, rest)) if !rest.is_empty() => {
+            format!("Merge branches {} and {last}", rest.join(", "))
+        }
+        _ => format!("Merge branch {}", quoted.join("")),
+    }
+}
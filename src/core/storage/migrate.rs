@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use tracing::instrument;
+
+use crate::core::{
+    commit::{Commit, Tree, graph_file::GraphFile},
+    error::GatoResult,
+    storage::{RefStore, gc::Gc, local::LocalStorage},
+};
+
+impl LocalStorage {
+    /// Rewrite every reachable tree in canonical order.
+    ///
+    /// Changing a tree changes its hash, so every commit above a rewritten tree is
+    /// rewritten too and the branch refs are moved to the new commits. The old
+    /// objects are left in place for `gato gc`. Returns the number of commits
+    /// that were rewritten.
+    #[instrument]
+    pub fn migrate_trees(&self) -> GatoResult<usize> {
+        let mut trees: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        let mut commits: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut rewritten = 0;
+
+        for hash in Gc::topo_commits(self)? {
+            let mut commit = Commit::load(hash.clone(), self);
+            let old_tree = commit.tree_hash();
+            let new_tree = Tree::rewrite_canonical(&old_tree, &mut trees, self)?;
+            let old_parents = commit.parents();
+            let new_parents: Vec<Vec<u8>> =
+                old_parents.iter().map(|p| commits[p].clone()).collect();
+
+            let unchanged = new_tree == old_tree
+                && old_parents
+                    .iter()
+                    .zip(&new_parents)
+                    .all(|(old, new)| *old == hex::encode(new));
+            if unchanged {
+                commits.insert(hash.clone(), hex::decode(&hash).unwrap_or_default());
+                continue;
+            }
+
+            let deps = commit
+                .dependices()
+                .into_iter()
+                .map(
+                    |dep| match hex::decode(&dep).ok().and_then(|d| trees.get(&d)) {
+                        Some(new_dep) => hex::encode(new_dep),
+                        None => dep,
+                    },
+                )
+                .collect();
+            commit.rewrite(new_tree, new_parents, deps);
+            commits.insert(hash, commit.put(self)?);
+            rewritten += 1;
+        }
+
+        for branch in self.list_branchs()? {
+            let tip = hex::encode(self.read_ref_vec(branch.clone())?);
+            if let Some(new_tip) = commits.get(&tip)
+                && hex::encode(new_tip) != tip
+            {
+                self.update_ref_from(branch, Some(&tip), new_tip.clone(), "migrate-trees")?;
+            }
+        }
+        GraphFile::rebuild(self, &[])?;
+
+        Ok(rewritten)
+    }
+}
@@ -1,23 +1,143 @@
+use std::fmt::Debug;
+
 use thiserror::Error;
+
+use crate::core::{
+    add::index::Index,
+    commit::{Commit, graph_file::GraphFile},
+    error::GatoResult,
+};
+pub mod clone;
+pub mod fsck;
 pub mod gc;
 pub mod local;
+pub mod lock;
+pub mod memory;
+pub mod merge;
+mod migrate;
+pub mod objects;
+pub mod pick;
+pub mod rebase;
+pub mod reflog;
+pub mod registry;
+pub mod stats;
 mod status;
-pub trait StorageEngine: Send + Sync {
+pub mod store;
+pub mod worktree;
+/// Content-addressed objects: commits, trees, blobs and chunks, stored under
+/// the hex Blake3 hash of their encoding.
+pub trait ObjectStore: Send + Sync + Debug {
     fn get(&self, hash: &String) -> Result<Vec<u8>, StorageError>;
 
     fn put(&self, hash: &String, data: Vec<u8>) -> Result<(), StorageError>;
 
     fn exist(&self, hash: &String) -> bool;
 
+    /// Whether the object exists, marking it as recently needed for stores
+    /// that expire unreferenced objects after a grace period.
+    fn freshen(&self, hash: &String) -> bool {
+        self.exist(hash)
+    }
+
+    /// Hashes of every stored object.
+    fn list_objects(&self) -> Result<Vec<String>, StorageError>;
+
+    /// The zstd level new blobs and chunks are compressed at.
+    fn compression_level(&self) -> i32 {
+        1
+    }
+
+    /// The commit-graph file caching the parents of stored commits, if the
+    /// store keeps one.
+    fn graph_file(&self) -> Option<GraphFile> {
+        None
+    }
+
+    /// Record the newly stored commit `hash` in the commit-graph file, if the
+    /// store keeps one.
+    fn add_to_graph(&self, _hash: &str, _commit: &Commit) {}
+}
+
+/// Named refs of a repository: its branches and the one checked out.
+pub trait RefStore: Send + Sync + Debug {
+    fn read_ref(&self, ref_name: String) -> Result<Vec<u8>, StorageError>;
+
     fn write_ref(&self, ref_name: String, hash: Vec<u8>) -> Result<(), StorageError>;
 
-    // fn read_ref(&self, ref_name: String) -> Result<String, StorageError>;
+    fn delete_ref(&self, ref_name: String) -> Result<(), StorageError>;
+
+    /// Move `ref_name` to `hash` if it still points at `expected` (`None`: the
+    /// ref does not exist yet), recording `reason` where the backend keeps
+    /// reflogs. Fails with [`StorageError::RefChanged`] otherwise.
+    fn update_ref_from(
+        &self,
+        ref_name: String,
+        expected: Option<&str>,
+        hash: Vec<u8>,
+        reason: &str,
+    ) -> Result<(), StorageError>;
+
+    fn list_refs(&self) -> Result<Vec<String>, StorageError>;
+
+    /// The checked out branch.
+    fn head(&self) -> String;
+
+    fn set_head(&self, branch: String) -> Result<(), StorageError>;
 
     fn setup(&self) -> Result<(), StorageError>;
 
-    fn new_branch(&self, name: String) -> Result<(), StorageError>;
+    /// The staging index, or `None` when nothing is staged.
+    fn load_index(&self) -> Result<Option<Index>, StorageError>;
+
+    fn save_index(&self, index: &Index) -> Result<(), StorageError>;
 
-    fn change_branch(&self, name: String) -> Result<(), StorageError>;
+    /// Apply `update` to the staging index (a new one when nothing is staged)
+    /// and save it, keeping other writers out in between.
+    fn update_index(&self, update: &mut dyn FnMut(&mut Index)) -> Result<(), StorageError>;
+
+    /// Drop the staging index, once what it holds has been committed.
+    fn delete_index(&self) -> Result<(), StorageError>;
+}
+
+/// A complete backend: objects and refs, with the branch operations built on
+/// them.
+pub trait StorageEngine: ObjectStore + RefStore {
+    /// Create the branch `name` at the tip of the checked out one, failing
+    /// with [`StorageError::BranchExists`] if there already is one.
+    fn new_branch(&self, name: String) -> Result<(), StorageError> {
+        let active = self.head();
+        let head = self.read_ref(active.clone())?;
+        let reason = format!("branch: Created from {active}");
+        self.update_ref_from(name.clone(), None, head, &reason)
+            .map_err(|err| match err {
+                StorageError::RefChanged { .. } => StorageError::BranchExists(name),
+                err => err,
+            })
+    }
+
+    /// Check the existing branch `name` out.
+    fn change_branch(&self, name: String) -> Result<(), StorageError> {
+        self.read_ref(name.clone())?;
+        self.set_head(name)
+    }
+
+    /// Name and email commits are made with, unless `GATO_AUTHOR_*` or
+    /// `GATO_COMMITTER_*` say otherwise.
+    fn user(&self) -> GatoResult<(String, Option<String>)>;
+
+    /// Commits `gc` keeps besides the history of the branches, such as those
+    /// young reflog entries name.
+    fn kept_commits(&self) -> GatoResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Objects `gc` keeps besides the history: by default what is staged.
+    fn kept_objects(&self) -> GatoResult<Vec<String>> {
+        Ok(self
+            .load_index()?
+            .map(|index| index.objects())
+            .unwrap_or_default())
+    }
 }
 
 #[derive(Debug, Error)]
@@ -34,4 +154,38 @@ pub enum StorageError {
     // UninitializedRepository,
     #[error("IO error")]
     IoError(#[from] std::io::Error),
+
+    #[error(
+        "unable to lock: {} exists; another gato process is running, or one crashed and the file can be removed",
+        .0.display()
+    )]
+    Locked(std::path::PathBuf),
+
+    #[error(
+        "{name} was moved by another process (expected {}, found {}); nothing was changed",
+        describe_ref(.expected),
+        describe_ref(.actual)
+    )]
+    RefChanged {
+        name: String,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+
+    #[error("nothing is staged")]
+    NothingStaged,
+
+    #[error("a branch named {0} already exists")]
+    BranchExists(String),
+
+    #[error("{branch} is already checked out in {}", .path.display())]
+    BranchCheckedOut {
+        branch: String,
+        path: std::path::PathBuf,
+    },
+}
+
+fn describe_ref(hash: &Option<String>) -> &str {
+    hash.as_deref()
+        .map_or("no commit", |hash| &hash[..hash.len().min(8)])
 }
@@ -0,0 +1,208 @@
+use std::{
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, SystemTime},
+};
+
+use colored::Colorize;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use tracing::instrument;
+
+use crate::core::{
+    add::{compute_hash, decompress},
+    commit::blob::Blob,
+    config::{
+        FsyncPolicy,
+        load::{load_config, load_global_config},
+    },
+    error::GatoResult,
+    storage::{ObjectStore, local::LocalStorage, lock::sync_parent},
+};
+
+/// Objects being written are named `.tmp-<hash>-<pid>-<n>` in their final
+/// directory until they are complete.
+const TEMP_PREFIX: &str = ".tmp-";
+
+/// A temporary object older than this was left behind by a crashed write.
+const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// `[storage] fsync` from `gato.toml`, else from the store's `config.toml`.
+pub fn configured_fsync(store_path: &Path, work_dir: &PathBuf) -> FsyncPolicy {
+    load_config(work_dir)
+        .ok()
+        .and_then(|config| config.storage)
+        .and_then(|storage| storage.fsync)
+        .or_else(|| {
+            load_global_config(store_path)
+                .ok()
+                .and_then(|config| config.storage)
+                .and_then(|storage| storage.fsync)
+        })
+        .unwrap_or_default()
+}
+
+/// Whether `name` in an `objects/` fan-out directory is an unfinished write.
+pub fn is_temp_object(name: &str) -> bool {
+    name.starts_with(TEMP_PREFIX)
+}
+
+/// Whether `data` is a complete object stored under `hash`. Trees, commits
+/// and chunked files are named after their stored bytes; chunks after their
+/// uncompressed content, and other blobs after the content they compress.
+pub fn is_intact(hash: &str, data: &[u8]) -> bool {
+    if data.is_empty() {
+        return false;
+    }
+    let named_by = |bytes: &[u8]| hex::encode(compute_hash(bytes)) == hash;
+    if named_by(data) {
+        return true;
+    }
+    if decompress(data).is_ok_and(|raw| named_by(&raw)) {
+        return true;
+    }
+    matches!(
+        bincode::decode_from_slice(data, bincode::config::standard()),
+        Ok((Blob::Normal(content), _)) if decompress(&content).is_ok_and(|raw| named_by(&raw))
+    )
+}
+
+/// Damaged objects and leftover temporary files found in the store.
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    pub checked: usize,
+    /// Empty or truncated objects.
+    pub damaged: Vec<String>,
+    /// Whether `damaged` objects were deleted.
+    pub removed: bool,
+    /// Temporary files of crashed writes that were deleted.
+    pub stale_temp_files: usize,
+}
+
+impl RecoveryReport {
+    pub fn is_ok(&self) -> bool {
+        self.damaged.is_empty()
+    }
+}
+
+impl Display for RecoveryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "checked {} objects", self.checked)?;
+        let label = if self.removed {
+            "removed damaged object"
+        } else {
+            "damaged"
+        };
+        for hash in &self.damaged {
+            writeln!(f, "\t{}: {}", label.red(), hash)?;
+        }
+        if self.stale_temp_files > 0 {
+            writeln!(
+                f,
+                "\tremoved {} unfinished object writes",
+                self.stale_temp_files
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl LocalStorage {
+    /// Write the object `hash` to a temporary file next to its final path,
+    /// flush it under the `objects` and `always` policies, then rename it into
+    /// place, so the object path never holds a partial object.
+    #[instrument(skip(data))]
+    pub(crate) fn write_object(&self, hash: &String, data: &[u8]) -> io::Result<()> {
+        let path = self.objects_path(hash);
+        let dir = path
+            .parent()
+            .expect("object paths have a fan-out directory");
+        fs::create_dir_all(dir)?;
+        let tmp = dir.join(format!(
+            "{TEMP_PREFIX}{}-{}-{}",
+            &hash[2..],
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = (|| {
+            let mut file = File::create(&tmp)?;
+            file.write_all(data)?;
+            if self.fsync_policy() != FsyncPolicy::Never {
+                file.sync_all()?;
+            }
+            fs::rename(&tmp, &path)
+        })();
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written?;
+        if self.fsync_policy() == FsyncPolicy::Always {
+            sync_parent(&path)?;
+        }
+        Ok(())
+    }
+
+    /// Whether the object `hash` is stored, like `exist`, marking it as just
+    /// written so a concurrent `gc` keeps it during its grace period.
+    #[instrument]
+    pub fn freshen(&self, hash: &String) -> bool {
+        if !self.exist(hash) {
+            return false;
+        }
+        // failing to touch it, e.g. in a read-only store, only shortens its grace period
+        let _ = File::options()
+            .write(true)
+            .open(self.objects_path(hash))
+            .and_then(|file| file.set_modified(SystemTime::now()));
+        true
+    }
+
+    /// Check every object of the store, reporting empty or truncated ones and,
+    /// with `repair`, deleting them so they can be written again. Temporary
+    /// files of writes that crashed are always deleted.
+    #[instrument]
+    pub fn recover_objects(&self, repair: bool) -> GatoResult<RecoveryReport> {
+        let objects_dir = self.root_path.join("objects");
+        let mut report = RecoveryReport {
+            removed: repair,
+            ..Default::default()
+        };
+        if !objects_dir.exists() {
+            return Ok(report);
+        }
+        for dir in fs::read_dir(&objects_dir)? {
+            for file in fs::read_dir(dir?.path())? {
+                let file = file?;
+                if !is_temp_object(&file.file_name().to_string_lossy()) {
+                    continue;
+                }
+                let age = file.metadata()?.modified()?.elapsed();
+                if age.unwrap_or(Duration::ZERO) > STALE_TEMP_AGE {
+                    fs::remove_file(file.path())?;
+                    report.stale_temp_files += 1;
+                }
+            }
+        }
+
+        let hashes = self.list_files()?;
+        report.checked = hashes.len();
+        report.damaged = hashes
+            .into_par_iter()
+            .filter(|hash| {
+                let data = fs::read(self.objects_path(hash)).unwrap_or_default();
+                !is_intact(hash, &data)
+            })
+            .collect();
+        report.damaged.sort();
+        if repair {
+            for hash in &report.damaged {
+                fs::remove_file(self.objects_path(hash))?;
+            }
+        }
+        Ok(report)
+    }
+}
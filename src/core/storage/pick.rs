@@ -0,0 +1,178 @@
+use std::{fs, path::PathBuf};
+
+use tracing::instrument;
+
+use crate::core::{
+    add::index::Index,
+    commit::{
+        Commit, Tree,
+        merge::{MergeContext, MergeOptions},
+    },
+    error::{Error, GatoResult},
+    storage::{local::LocalStorage, merge::Operation},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PickOutcome {
+    /// The changes are already on the current branch; no commit was made.
+    Empty,
+    /// A commit was created.
+    Committed { commit: String },
+    /// The changes conflict with these paths; the state is kept until
+    /// `--continue` or `--abort`.
+    Conflicted {
+        operation: Operation,
+        paths: Vec<PathBuf>,
+    },
+}
+
+impl std::fmt::Display for PickOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PickOutcome::Empty => write!(f, "Nothing to commit, the changes are already applied."),
+            PickOutcome::Committed { commit } => write!(f, "Created commit {commit}"),
+            PickOutcome::Conflicted { operation, paths } => {
+                for path in paths {
+                    writeln!(f, "CONFLICT: {}", path.display())?;
+                }
+                write!(
+                    f,
+                    "Could not apply the changes; fix conflicts, `gato add` them and run `gato {operation} --continue`."
+                )
+            }
+        }
+    }
+}
+
+impl LocalStorage {
+    /// Apply the changes introduced by `rev` on top of the current branch.
+    ///
+    /// This is a three-way merge of HEAD and `rev` whose base is the parent of
+    /// `rev` (its first parent for merge commits).
+    #[instrument]
+    pub fn cherry_pick(&self, rev: &str, options: MergeOptions) -> GatoResult<PickOutcome> {
+        let hash = self.resolve_rev(rev)?;
+        let commit = Commit::load(hash.clone(), self);
+        let message = format!("{}\n\n(cherry picked from commit {hash})", commit.message());
+        let base = self.parent_tree(&commit)?;
+        let theirs = Tree::load(hex::encode(commit.tree_hash()), self)?;
+        self.pick(Operation::CherryPick, &hash, base, theirs, message, options)
+    }
+
+    /// Create a commit undoing the changes introduced by `rev`.
+    ///
+    /// This is a three-way merge of HEAD and the parent of `rev`, with `rev`
+    /// itself as the base.
+    #[instrument]
+    pub fn revert(&self, rev: &str, options: MergeOptions) -> GatoResult<PickOutcome> {
+        let hash = self.resolve_rev(rev)?;
+        let commit = Commit::load(hash.clone(), self);
+        let subject = commit.message().lines().next().unwrap_or_default();
+        let message = format!("Revert \"{subject}\"\n\nThis reverts commit {hash}.");
+        let base = Tree::load(hex::encode(commit.tree_hash()), self)?;
+        let theirs = self.parent_tree(&commit)?;
+        self.pick(Operation::Revert, &hash, base, theirs, message, options)
+    }
+
+    /// Commit a cherry-pick or revert once all its conflicts are staged.
+    #[instrument]
+    pub fn pick_continue(
+        &self,
+        operation: Operation,
+        message: Option<String>,
+    ) -> GatoResult<PickOutcome> {
+        let Some(picked) = self.operation_head(operation) else {
+            return Err(Error::NotInProgress(operation));
+        };
+        let message = match message {
+            Some(message) => message,
+            None => fs::read_to_string(self.state_file("MERGE_MSG"))?,
+        };
+        let (tree_hash, dependencies) = Commit::tree_from_index(self)?;
+        let picked = hex::encode(picked);
+        let commit = self.pick_commit(operation, &picked, message, tree_hash, dependencies)?;
+        commit.save_as(self, &format!("{operation}: {}", commit.subject()))?;
+        fs::remove_file(Index::index_file_path(self))?;
+        self.clear_operation_state()?;
+        Ok(PickOutcome::Committed {
+            commit: commit.hash()?,
+        })
+    }
+
+    /// Give up a conflicted cherry-pick or revert and restore HEAD.
+    #[instrument]
+    pub fn pick_abort(&self, operation: Operation) -> GatoResult<()> {
+        if self.operation_head(operation).is_none() {
+            return Err(Error::NotInProgress(operation));
+        }
+        self.restore_head()
+    }
+
+    fn pick(
+        &self,
+        operation: Operation,
+        hash: &str,
+        base: Tree,
+        theirs: Tree,
+        message: String,
+        options: MergeOptions,
+    ) -> GatoResult<PickOutcome> {
+        if let Some(operation) = self.operation_in_progress() {
+            return Err(Error::OperationInProgress(operation));
+        }
+        let head_tree = self.get_last_tree()?;
+        let mut ctx = MergeContext::with_options(options);
+        let merged = Tree::merge(base, head_tree.clone(), theirs, &mut ctx, self)?;
+        if ctx.conflicts.is_empty() && merged.hash() == head_tree.hash() {
+            return Ok(PickOutcome::Empty);
+        }
+        self.ensure_clean(&head_tree, &merged)?;
+        self.update_work_dir(&head_tree, &merged)?;
+
+        if !ctx.conflicts.is_empty() {
+            self.stage_merge_result(&merged, ctx.conflicts.clone())?;
+            let head = hex::decode(hash).unwrap_or_default();
+            self.write_operation_state(operation, &head, &message)?;
+            return Ok(PickOutcome::Conflicted {
+                operation,
+                paths: ctx.conflicts.into_keys().collect(),
+            });
+        }
+
+        let dependencies = merged.dependencies(self)?;
+        let commit = self.pick_commit(operation, hash, message, merged.hash(), dependencies)?;
+        commit.save_as(self, &format!("{operation}: {}", commit.subject()))?;
+        Ok(PickOutcome::Committed {
+            commit: commit.hash()?,
+        })
+    }
+
+    /// A commit of `tree_hash` on top of the current branch; a cherry-pick keeps
+    /// the author of the picked commit.
+    fn pick_commit(
+        &self,
+        operation: Operation,
+        picked: &str,
+        message: String,
+        tree_hash: Vec<u8>,
+        dependencies: Vec<String>,
+    ) -> GatoResult<Commit> {
+        let mut commit = Commit::from_tree(message, tree_hash, dependencies, self)?;
+        if operation == Operation::CherryPick {
+            let author = Commit::load(picked.to_string(), self).author();
+            commit.set_identities(author, commit.committer());
+        }
+        Ok(commit)
+    }
+
+    /// Tree of the first parent of `commit`, or an empty tree for a root commit.
+    pub(crate) fn parent_tree(&self, commit: &Commit) -> GatoResult<Tree> {
+        match commit.parents().first() {
+            Some(parent) => {
+                let parent = Commit::load(parent.clone(), self);
+                Tree::load(hex::encode(parent.tree_hash()), self)
+            }
+            None => Ok(Tree::new("root".to_string())),
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use std::{collections::BTreeSet, fs, path::PathBuf};
+
+use tracing::instrument;
+
+use crate::core::{
+    add::index::Index,
+    commit::{
+        Commit, Tree,
+        graph::CommitGraph,
+        identity::{Identity, Role},
+        merge::{MergeContext, MergeOptions},
+    },
+    error::{Error, GatoResult},
+    storage::{RefStore, local::LocalStorage, merge::Operation},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebaseOutcome {
+    /// The branch already sits on top of its new base; nothing was done.
+    UpToDate,
+    /// Every commit was replayed; the branch now points at `head`.
+    Rebased { head: String },
+    /// Replaying `commit` conflicts in these paths; the rest of the todo list is
+    /// kept until `rebase --continue`, `--skip` or `--abort`.
+    Conflicted { commit: String, paths: Vec<PathBuf> },
+}
+
+impl std::fmt::Display for RebaseOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RebaseOutcome::UpToDate => write!(f, "Current branch is up to date."),
+            RebaseOutcome::Rebased { head } => write!(f, "Successfully rebased, now at {head}"),
+            RebaseOutcome::Conflicted { commit, paths } => {
+                for path in paths {
+                    writeln!(f, "CONFLICT: {}", path.display())?;
+                }
+                write!(
+                    f,
+                    "Could not apply {commit}; fix conflicts, `gato add` them and run `gato rebase --continue`, or use `--skip` / `--abort`."
+                )
+            }
+        }
+    }
+}
+
+/// What to do with a commit of the todo list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// Replay it as a new commit.
+    Pick,
+    /// Fold its changes into the previous commit, keeping that message.
+    Fixup,
+    /// Fold its changes into the previous commit, appending its message.
+    Squash,
+}
+
+/// One line of the todo list: `<action> <hash> <subject>`.
+#[derive(Debug, Clone)]
+struct Step {
+    action: Action,
+    commit: String,
+    subject: String,
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action = match self {
+            Action::Pick => "pick",
+            Action::Fixup => "fixup",
+            Action::Squash => "squash",
+        };
+        write!(f, "{action}")
+    }
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.action, self.commit, self.subject)
+    }
+}
+
+impl std::str::FromStr for Step {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidTodoLine(line.to_string());
+        let mut parts = line.splitn(3, ' ');
+        let action = match parts.next() {
+            Some("pick" | "p") => Action::Pick,
+            Some("fixup" | "f") => Action::Fixup,
+            Some("squash" | "s") => Action::Squash,
+            _ => return Err(invalid()),
+        };
+        let commit = parts.next().filter(|c| !c.is_empty()).ok_or_else(invalid)?;
+        Ok(Step {
+            action,
+            commit: commit.to_string(),
+            subject: parts.next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+impl LocalStorage {
+    /// Replay the commits of the current branch that are not in `upstream` on
+    /// top of `onto` (`upstream` itself by default), one three-way merge each.
+    ///
+    /// Merge commits are dropped, so the result is linear. With `autosquash`,
+    /// commits whose subject starts with `fixup! ` or `squash! ` are moved right
+    /// after the commit they name and folded into it.
+    #[instrument]
+    pub fn rebase(
+        &self,
+        upstream: &str,
+        onto: Option<&str>,
+        autosquash: bool,
+        options: MergeOptions,
+    ) -> GatoResult<RebaseOutcome> {
+        if let Some(operation) = self.operation_in_progress() {
+            return Err(Error::OperationInProgress(operation));
+        }
+        let branch = self.get_active_branche();
+        let head = self.resolve_rev("HEAD")?;
+        let upstream = self.resolve_rev(upstream)?;
+        let onto = match onto {
+            Some(onto) => self.resolve_rev(onto)?,
+            None => upstream.clone(),
+        };
+
+        let mut graph = CommitGraph::new(self);
+        let commits: Vec<String> = graph
+            .only_in(&head, &upstream)
+            .into_iter()
+            .filter(|commit| graph.parents(commit).len() <= 1)
+            .collect();
+        let linear = commits.iter().try_fold(onto.clone(), |tip, commit| {
+            (graph.parents(commit) == [tip]).then(|| commit.clone())
+        });
+        let steps = self.todo(&commits, autosquash)?;
+        if linear.as_ref() == Some(&head) && steps.iter().all(|s| s.action == Action::Pick) {
+            return Ok(RebaseOutcome::UpToDate);
+        }
+        self.ensure_clean_for(&onto, &steps)?;
+
+        let dir = self.rebase_dir();
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("head-name"), &branch)?;
+        fs::write(dir.join("orig-head"), &head)?;
+        self.write_todo(&steps)?;
+
+        let head_tree = self.get_last_tree()?;
+        let reason = format!("rebase: checkout {onto}");
+        let onto_hash = hex::decode(&onto).unwrap_or_default();
+        self.update_ref_from(branch, Some(&head), onto_hash, &reason)?;
+        self.update_work_dir(&head_tree, &self.get_last_tree()?)?;
+        self.replay(options)
+    }
+
+    /// Commit the resolved step once all its conflicts are staged, then replay
+    /// the rest of the todo list.
+    #[instrument]
+    pub fn rebase_continue(
+        &self,
+        message: Option<String>,
+        options: MergeOptions,
+    ) -> GatoResult<RebaseOutcome> {
+        let Some(hash) = self.operation_head(Operation::Rebase) else {
+            return Err(Error::NotInProgress(Operation::Rebase));
+        };
+        let (tree_hash, dependencies) = Commit::tree_from_index(self)?;
+        let mut steps = self.read_todo()?;
+        if !steps.is_empty() {
+            let step = steps.remove(0);
+            let mut commit = Commit::load(hex::encode(hash), self);
+            if let Some(message) = message {
+                commit.set_message(message);
+            }
+            if tree_hash != self.get_last_tree()?.hash() {
+                self.commit_step(&step, commit, tree_hash, dependencies)?;
+            } else if step.action == Action::Pick {
+                unfold(&mut steps);
+            }
+            self.write_todo(&steps)?;
+        }
+        fs::remove_file(Index::index_file_path(self))?;
+        self.clear_operation_state()?;
+        self.replay(options)
+    }
+
+    /// Drop the conflicted step and replay the rest of the todo list.
+    #[instrument]
+    pub fn rebase_skip(&self, options: MergeOptions) -> GatoResult<RebaseOutcome> {
+        if self.operation_head(Operation::Rebase).is_none() {
+            return Err(Error::NotInProgress(Operation::Rebase));
+        }
+        self.restore_head()?;
+        let mut steps = self.read_todo()?;
+        if !steps.is_empty() && steps.remove(0).action == Action::Pick {
+            unfold(&mut steps);
+        }
+        self.write_todo(&steps)?;
+        self.replay(options)
+    }
+
+    /// Give up the rebase and put the branch back where it was.
+    #[instrument]
+    pub fn rebase_abort(&self) -> GatoResult<()> {
+        if self.operation_head(Operation::Rebase).is_none() {
+            return Err(Error::NotInProgress(Operation::Rebase));
+        }
+        self.restore_head()?;
+        let dir = self.rebase_dir();
+        let branch = fs::read_to_string(dir.join("head-name"))?;
+        let orig_head = fs::read_to_string(dir.join("orig-head"))?;
+        let current_tree = self.get_last_tree()?;
+        let reason = format!("rebase (abort): returning to {orig_head}");
+        self.update_ref(branch, hex::decode(&orig_head).unwrap_or_default(), &reason)?;
+        self.update_work_dir(&current_tree, &self.get_last_tree()?)?;
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    /// Refuse to rebase over staged changes, or over local changes to files the
+    /// working directory passes through: those of `onto` and of each replayed
+    /// commit.
+    fn ensure_clean_for(&self, onto: &str, steps: &[Step]) -> GatoResult<()> {
+        let head_tree = self.get_last_tree()?;
+        let onto_tree = Tree::load(
+            hex::encode(Commit::load(onto.to_string(), self).tree_hash()),
+            self,
+        )?;
+        self.ensure_clean(&head_tree, &onto_tree)?;
+        let head_files = head_tree.files(self)?;
+        let mut overwritten = BTreeSet::new();
+        for step in steps {
+            let commit = Commit::load(step.commit.clone(), self);
+            let files = Tree::load(hex::encode(commit.tree_hash()), self)?.files(self)?;
+            overwritten.extend(self.overwritten_files(&head_files, &files)?);
+        }
+        if !overwritten.is_empty() {
+            return Err(Error::LocalChanges(overwritten.into_iter().collect()));
+        }
+        Ok(())
+    }
+
+    /// Apply the todo list until it is empty or a step conflicts.
+    fn replay(&self, options: MergeOptions) -> GatoResult<RebaseOutcome> {
+        let mut steps = self.read_todo()?;
+        while !steps.is_empty() {
+            let step = &steps[0];
+            let commit = Commit::load(step.commit.clone(), self);
+            let base = self.parent_tree(&commit)?;
+            let theirs = Tree::load(hex::encode(commit.tree_hash()), self)?;
+            let head_tree = self.get_last_tree()?;
+            let mut ctx = MergeContext::with_options(options);
+            let merged = Tree::merge(base, head_tree.clone(), theirs, &mut ctx, self)?;
+            self.update_work_dir(&head_tree, &merged)?;
+
+            if !ctx.conflicts.is_empty() {
+                self.stage_merge_result(&merged, ctx.conflicts.clone())?;
+                let head = hex::decode(&step.commit).unwrap_or_default();
+                self.write_operation_state(Operation::Rebase, &head, commit.message())?;
+                return Ok(RebaseOutcome::Conflicted {
+                    commit: step.commit.clone(),
+                    paths: ctx.conflicts.into_keys().collect(),
+                });
+            }
+
+            let step = steps.remove(0);
+            // Commits whose changes are already upstream are dropped.
+            if merged.hash() != head_tree.hash() {
+                self.commit_step(&step, commit, merged.hash(), merged.dependencies(self)?)?;
+            } else if step.action == Action::Pick {
+                unfold(&mut steps);
+            }
+            self.write_todo(&steps)?;
+        }
+        fs::remove_dir_all(self.rebase_dir())?;
+        Ok(RebaseOutcome::Rebased {
+            head: self.resolve_rev("HEAD")?,
+        })
+    }
+
+    /// Record `tree_hash` for `step`: a copy of `commit` on top of the branch for
+    /// a pick, an amended branch tip for a fixup or squash.
+    fn commit_step(
+        &self,
+        step: &Step,
+        commit: Commit,
+        tree_hash: Vec<u8>,
+        dependencies: Vec<String>,
+    ) -> GatoResult<()> {
+        let head = self.resolve_rev("HEAD")?;
+        let (mut new_commit, parents, replaced) = match step.action {
+            Action::Pick => (commit, vec![hex::decode(&head).unwrap_or_default()], None),
+            Action::Fixup | Action::Squash => {
+                let mut tip = Commit::load(head.clone(), self);
+                let squashed = squash_message(&commit);
+                if step.action == Action::Squash && !squashed.is_empty() {
+                    tip.set_message(format!("{}\n\n{squashed}", tip.message()));
+                }
+                let parents = tip
+                    .parents()
+                    .iter()
+                    .map(|p| hex::decode(p).unwrap_or_default())
+                    .collect();
+                (tip, parents, Some(head.as_str()))
+            }
+        };
+        new_commit.set_identities(
+            new_commit.author(),
+            Identity::from_env(Role::Committer, self)?,
+        );
+        new_commit.rewrite(tree_hash, parents, dependencies);
+        let reason = format!("rebase ({}): {}", step.action, new_commit.subject());
+        match replaced {
+            Some(replaced) => new_commit.save_over(self, Some(replaced), &reason)?,
+            None => new_commit.save_as(self, &reason)?,
+        }
+        Ok(())
+    }
+
+    /// The todo list for `commits`, oldest first.
+    fn todo(&self, commits: &[String], autosquash: bool) -> GatoResult<Vec<Step>> {
+        let mut steps: Vec<Step> = Vec::new();
+        for hash in commits {
+            let commit = Commit::load(hash.clone(), self);
+            let subject = commit.message().lines().next().unwrap_or_default();
+            let mut step = Step {
+                action: Action::Pick,
+                commit: hash.clone(),
+                subject: subject.to_string(),
+            };
+            let target = autosquash
+                .then(|| squash_target(subject))
+                .flatten()
+                .and_then(|(action, target)| {
+                    let position = steps.iter().position(|s| {
+                        s.action == Action::Pick
+                            && (s.subject == target
+                                || (target.len() >= 4 && s.commit.starts_with(target)))
+                    })?;
+                    Some((action, position))
+                });
+            match target {
+                Some((action, position)) => {
+                    // after the target and the fixups already attached to it
+                    let mut at = position + 1;
+                    while at < steps.len() && steps[at].action != Action::Pick {
+                        at += 1;
+                    }
+                    step.action = action;
+                    steps.insert(at, step);
+                }
+                None => steps.push(step),
+            }
+        }
+        Ok(steps)
+    }
+
+    fn rebase_dir(&self) -> PathBuf {
+        self.state_file("rebase")
+    }
+
+    fn read_todo(&self) -> GatoResult<Vec<Step>> {
+        let todo = fs::read_to_string(self.rebase_dir().join("todo"))?;
+        todo.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::parse)
+            .collect()
+    }
+
+    fn write_todo(&self, steps: &[Step]) -> GatoResult<()> {
+        let todo: String = steps.iter().map(|step| format!("{step}\n")).collect();
+        fs::write(self.rebase_dir().join("todo"), todo)?;
+        Ok(())
+    }
+}
+
+/// The action and target named by a `fixup! ` or `squash! ` subject.
+fn squash_target(subject: &str) -> Option<(Action, &str)> {
+    let (action, mut target) = if let Some(rest) = subject.strip_prefix("fixup! ") {
+        (Action::Fixup, rest)
+    } else {
+        (Action::Squash, subject.strip_prefix("squash! ")?)
+    };
+    while let Some(rest) = target
+        .strip_prefix("fixup! ")
+        .or_else(|| target.strip_prefix("squash! "))
+    {
+        target = rest;
+    }
+    Some((action, target))
+}
+
+/// The message a squashed commit adds, without its `squash! ` subject.
+fn squash_message(commit: &Commit) -> String {
+    let message = commit.message();
+    match message.split_once('\n') {
+        Some((subject, body)) if subject.starts_with("squash! ") => body.trim().to_string(),
+        None if message.starts_with("squash! ") => String::new(),
+        _ => message.clone(),
+    }
+}
+
+/// Turn the fixups and squashes of a dropped pick into picks of their own, so
+/// they are not folded into an unrelated commit.
+fn unfold(steps: &mut [Step]) {
+    for step in steps.iter_mut().take_while(|s| s.action != Action::Pick) {
+        step.action = Action::Pick;
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use chrono::Local;
+use tracing::{instrument, warn};
+
+use crate::core::{
+    commit::identity::{Identity, Role, parse_offset},
+    error::{Error, GatoResult},
+    storage::{ObjectStore, StorageError, local::LocalStorage},
+};
+
+/// How long reflog entries are kept when `gato.toml` does not say.
+pub const DEFAULT_EXPIRE_DAYS: u64 = 90;
+
+const NULL_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// One change of a ref, as recorded in its reflog.
+///
+/// Stored one per line as `<old> <new> <name> <<email>> <seconds> <+hhmm>\t<reason>`,
+/// with an all-zero hash for a ref that did not exist before or after.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflogEntry {
+    /// `None` when the change created the ref.
+    pub old: Option<String>,
+    /// `None` when the change deleted the ref.
+    pub new: Option<String>,
+    pub identity: Identity,
+    pub reason: String,
+}
+
+impl Display for ReflogEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let offset = self.identity.utc_offset;
+        write!(
+            f,
+            "{} {} {} <{}> {} {}{:02}{:02}\t{}",
+            self.old.as_deref().unwrap_or(NULL_HASH),
+            self.new.as_deref().unwrap_or(NULL_HASH),
+            self.identity.name,
+            self.identity.email.as_deref().unwrap_or_default(),
+            self.identity.timestamp,
+            if offset < 0 { '-' } else { '+' },
+            offset.abs() / 60,
+            offset.abs() % 60,
+            self.reason.replace('\n', " ")
+        )
+    }
+}
+
+impl FromStr for ReflogEntry {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidReflogLine(line.to_string());
+        let (head, reason) = line.split_once('\t').ok_or_else(invalid)?;
+        let mut words = head.splitn(3, ' ');
+        let (Some(old), Some(new), Some(rest)) = (words.next(), words.next(), words.next()) else {
+            return Err(invalid());
+        };
+        let mut tail = rest.rsplitn(3, ' ');
+        let (Some(offset), Some(timestamp), Some(person)) = (tail.next(), tail.next(), tail.next())
+        else {
+            return Err(invalid());
+        };
+        let identity = Identity {
+            name: String::new(),
+            email: None,
+            timestamp: timestamp.parse().map_err(|_| invalid())?,
+            utc_offset: parse_offset(offset).ok_or_else(invalid)?,
+        }
+        .with_person(person)
+        .map_err(|_| invalid())?;
+        let hash = |hash: &str| (hash != NULL_HASH).then(|| hash.to_string());
+        Ok(Self {
+            old: hash(old),
+            new: hash(new),
+            identity,
+            reason: reason.to_string(),
+        })
+    }
+}
+
+impl LocalStorage {
+    fn reflog_dir(&self) -> PathBuf {
+        self.repo_path().join("logs")
+    }
+
+    fn reflog_path(&self, ref_name: &str) -> PathBuf {
+        match ref_name {
+            "HEAD" => self.state_file("logs").join("HEAD"),
+            branch => self.reflog_dir().join("refs").join("heads").join(branch),
+        }
+    }
+
+    /// The reflog of `ref_name` (`HEAD` or a branch), newest entry first. The
+    /// reflog of a deleted branch is kept until its entries expire; lines that
+    /// cannot be parsed are skipped with a warning.
+    #[instrument]
+    pub fn reflog(&self, ref_name: &str) -> GatoResult<Vec<ReflogEntry>> {
+        let text = match fs::read_to_string(self.reflog_path(ref_name)) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut entries: Vec<ReflogEntry> = text
+            .lines()
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| match line.parse() {
+                Ok(entry) => Some(entry),
+                Err(err) => {
+                    warn!("skipping an entry of the {ref_name} reflog: {err}");
+                    None
+                }
+            })
+            .collect();
+        entries.reverse();
+        Ok(entries)
+    }
+
+    /// Append a change of `ref_name` to its reflog.
+    pub(crate) fn log_ref_change(
+        &self,
+        ref_name: &str,
+        old: Option<String>,
+        new: Option<String>,
+        reason: &str,
+    ) -> io::Result<()> {
+        let identity = Identity::from_env(Role::Committer, self).unwrap_or_else(|_| {
+            let now = Local::now();
+            Identity {
+                name: "unknown".to_string(),
+                email: None,
+                timestamp: now.timestamp(),
+                utc_offset: now.offset().local_minus_utc() / 60,
+            }
+        });
+        let entry = ReflogEntry {
+            old,
+            new,
+            identity,
+            reason: reason.to_string(),
+        };
+        let path = self.reflog_path(ref_name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        writeln!(file, "{entry}")
+    }
+
+    /// Point the branch `ref_name` at `hash`, recording the change with `reason`
+    /// in its reflog and, for the checked-out branch, in the reflog of `HEAD`.
+    #[instrument]
+    pub fn update_ref(
+        &self,
+        ref_name: String,
+        hash: Vec<u8>,
+        reason: &str,
+    ) -> Result<(), StorageError> {
+        self.move_ref(ref_name, None, hash, reason)
+    }
+
+    /// Move the branch `ref_name` to `hash` under its lock, logging the change;
+    /// with `expected`, only if the branch still points there.
+    pub(super) fn move_ref(
+        &self,
+        ref_name: String,
+        expected: Option<Option<&str>>,
+        hash: Vec<u8>,
+        reason: &str,
+    ) -> Result<(), StorageError> {
+        let lock = self.lock(&self.get_branch_path(ref_name.clone()))?;
+        let old = fs::read(lock.path()).ok().map(hex::encode);
+        if let Some(expected) = expected
+            && old.as_deref() != expected
+        {
+            return Err(StorageError::RefChanged {
+                name: ref_name,
+                expected: expected.map(str::to_string),
+                actual: old,
+            });
+        }
+        let new = Some(hex::encode(&hash));
+        lock.commit(&hash)?;
+        self.log_ref_change(&ref_name, old.clone(), new.clone(), reason)?;
+        if ref_name == self.get_active_branche() {
+            self.log_ref_change("HEAD", old, new, reason)?;
+        }
+        Ok(())
+    }
+
+    /// Delete the branch `ref_name` under its lock if it still points at
+    /// `expected`, logging the change.
+    pub(crate) fn delete_ref_from(
+        &self,
+        ref_name: String,
+        expected: &str,
+        reason: &str,
+    ) -> Result<(), StorageError> {
+        let lock = self.lock(&self.get_branch_path(ref_name.clone()))?;
+        let old = fs::read(lock.path()).ok().map(hex::encode);
+        if old.as_deref() != Some(expected) {
+            return Err(StorageError::RefChanged {
+                name: ref_name,
+                expected: Some(expected.to_string()),
+                actual: old,
+            });
+        }
+        fs::remove_file(lock.path())?;
+        drop(lock);
+        self.log_ref_change(&ref_name, old, None, reason)?;
+        Ok(())
+    }
+
+    /// The commit `ref_name` pointed to `n` changes ago (`0` is its current
+    /// value), for revisions such as `HEAD@{2}`, `@{1}` or `master@{3}`.
+    pub(crate) fn resolve_reflog(&self, ref_name: &str, n: usize) -> GatoResult<String> {
+        let ref_name = if ref_name.is_empty() {
+            "HEAD"
+        } else {
+            ref_name
+        };
+        let unknown = || Error::UnknownRevision(format!("{ref_name}@{{{n}}}"));
+        let entries = self.reflog(ref_name)?;
+        entries
+            .get(n)
+            .and_then(|e| e.new.clone())
+            .ok_or_else(unknown)
+    }
+
+    /// How many days reflog entries are kept, from `[gc] reflog_expire_days`.
+    pub fn reflog_expire_days(&self) -> u64 {
+        self.work_config()
+            .and_then(|config| config.gc)
+            .and_then(|gc| gc.reflog_expire_days)
+            .unwrap_or(DEFAULT_EXPIRE_DAYS)
+    }
+
+    /// The names of every reflog, deleted branches included.
+    fn reflog_names(&self) -> GatoResult<Vec<String>> {
+        let mut names = Vec::new();
+        if self.reflog_path("HEAD").exists() {
+            names.push("HEAD".to_string());
+        }
+        let heads = self.reflog_dir().join("refs").join("heads");
+        if heads.exists() {
+            for entry in fs::read_dir(heads)? {
+                names.push(entry?.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(names)
+    }
+
+    /// Drop reflog entries older than `days` days, returning how many were
+    /// dropped. The reflogs of deleted branches go once they are empty.
+    #[instrument]
+    pub fn reflog_expire(&self, days: u64) -> GatoResult<usize> {
+        let cutoff = Local::now().timestamp() - (days * 24 * 60 * 60) as i64;
+        let mut dropped = 0;
+        for name in self.reflog_names()? {
+            let mut entries = self.reflog(&name)?;
+            let before = entries.len();
+            entries.retain(|entry| entry.identity.timestamp >= cutoff);
+            if entries.len() == before {
+                continue;
+            }
+            dropped += before - entries.len();
+            let path = self.reflog_path(&name);
+            if entries.is_empty() && name != "HEAD" && self.read_ref_vec(name.clone()).is_err() {
+                fs::remove_file(path)?;
+                continue;
+            }
+            let text: String = entries.iter().rev().map(|e| format!("{e}\n")).collect();
+            self.write_atomic(&path, text.as_bytes())?;
+        }
+        Ok(dropped)
+    }
+
+    /// Commits named by reflog entries younger than `days` days, which `gc`
+    /// keeps together with their history.
+    #[instrument]
+    pub fn reflog_roots(&self, days: u64) -> GatoResult<Vec<String>> {
+        let cutoff = Local::now().timestamp() - (days * 24 * 60 * 60) as i64;
+        let mut roots = BTreeSet::new();
+        for name in self.reflog_names()? {
+            for entry in self.reflog(&name)? {
+                if entry.identity.timestamp >= cutoff {
+                    roots.extend(entry.old.into_iter().chain(entry.new));
+                }
+            }
+        }
+        Ok(roots.into_iter().filter(|hash| self.exist(hash)).collect())
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    fs,
+    path::PathBuf,
+};
+
+use bincode::{config, decode_from_slice, encode_to_vec};
+use colored::Colorize;
+use tracing::instrument;
+
+use crate::core::{
+    config::load::load_config,
+    error::{Error, GatoResult},
+    storage::local::LocalStorage,
+};
+
+/// Start of the `repos` file since it is keyed by repository id; older files
+/// are a bare set of paths.
+const MAGIC: &[u8; 4] = b"GRG1";
+
+/// The repositories sharing the store: each repository id with the working
+/// directory it was last registered from.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Registry {
+    pub repos: BTreeMap<String, PathBuf>,
+}
+
+impl Registry {
+    /// Decode the `repos` file. An old set of paths is keyed by the ids found
+    /// in their `gato.toml`; paths without one are dropped, their repositories
+    /// then show up as unregistered.
+    fn decode(data: &[u8]) -> GatoResult<Self> {
+        if let Some(data) = data.strip_prefix(MAGIC) {
+            let (repos, _) = decode_from_slice(data, config::standard())?;
+            return Ok(Self { repos });
+        }
+        let (paths, _): (HashSet<PathBuf>, usize) = decode_from_slice(data, config::standard())?;
+        let repos = paths
+            .into_iter()
+            .filter_map(|path| Some((load_config(&path).ok()?.id, path)))
+            .collect();
+        Ok(Self { repos })
+    }
+
+    fn encode(&self) -> GatoResult<Vec<u8>> {
+        let mut data = MAGIC.to_vec();
+        data.extend(encode_to_vec(&self.repos, config::standard())?);
+        Ok(data)
+    }
+}
+
+/// Whether a repository of the store can be found where it was registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepoState {
+    Present,
+    /// Its working directory, or its `gato.toml`, is gone.
+    Missing,
+    /// Its working directory now belongs to another repository.
+    Replaced {
+        by: String,
+    },
+    /// It has refs in the store but no entry in the registry.
+    Unregistered,
+}
+
+/// A repository of the store, as listed by `list-repos`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisteredRepo {
+    pub id: String,
+    /// Last known working directory.
+    pub path: Option<PathBuf>,
+    pub state: RepoState,
+}
+
+impl RegisteredRepo {
+    pub fn is_present(&self) -> bool {
+        self.state == RepoState::Present
+    }
+}
+
+impl Display for RegisteredRepo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let path = self
+            .path
+            .as_ref()
+            .map_or("?".to_string(), |path| path.display().to_string());
+        write!(f, "{} {path}", self.id)?;
+        match &self.state {
+            RepoState::Present => Ok(()),
+            RepoState::Missing => write!(
+                f,
+                " {} run `gato repo relocate` from its new location",
+                "missing:".red()
+            ),
+            RepoState::Replaced { by } => {
+                write!(f, " {} the directory now holds {by}", "stale:".yellow())
+            }
+            RepoState::Unregistered => write!(f, " {}", "unregistered".yellow()),
+        }
+    }
+}
+
+impl LocalStorage {
+    fn registry_path(&self) -> PathBuf {
+        self.root_path.join("repos")
+    }
+
+    #[instrument]
+    pub fn registry(&self) -> GatoResult<Registry> {
+        match fs::read(self.registry_path()) {
+            Ok(data) => Registry::decode(&data),
+            Err(_) => Ok(Registry::default()),
+        }
+    }
+
+    /// Change the registry under its lock.
+    fn update_registry(&self, change: impl FnOnce(&mut Registry)) -> GatoResult<()> {
+        let lock = self.lock(&self.registry_path())?;
+        let mut registry = self.registry()?;
+        change(&mut registry);
+        lock.commit(&registry.encode()?)?;
+        Ok(())
+    }
+
+    /// Register the repository with its current working directory.
+    #[instrument]
+    pub fn push_to_repos(&self) -> GatoResult<()> {
+        let path = self.work_dir().canonicalize()?;
+        self.update_registry(|registry| {
+            registry.repos.insert(self.repo_id().to_string(), path);
+        })
+    }
+
+    pub fn pop_from_repos(&self) -> GatoResult<()> {
+        self.update_registry(|registry| {
+            registry.repos.remove(self.repo_id());
+        })
+    }
+
+    /// Record that the repository, or the linked worktree this is, now lives
+    /// in its current working directory, returning where it was registered
+    /// before if that was elsewhere.
+    #[instrument]
+    pub fn relocate(&self) -> GatoResult<Option<PathBuf>> {
+        if !self.repo_path().join("refs").is_dir() {
+            return Err(Error::UnknownRepo(self.repo_id().to_string()));
+        }
+        let path = self.work_dir().canonicalize()?;
+        if let Some(worktree) = self.worktree() {
+            let file = self.worktrees_dir().join(worktree).join("path");
+            let previous = fs::read_to_string(&file).ok().map(PathBuf::from);
+            fs::write(file, path.to_string_lossy().as_bytes())?;
+            return Ok(previous.filter(|previous| previous != &path));
+        }
+        let previous = self.registry()?.repos.remove(self.repo_id());
+        self.push_to_repos()?;
+        Ok(previous.filter(|previous| previous != &path))
+    }
+
+    /// Ids of every repository with refs in the store, registered or not.
+    fn stored_repo_ids(&self) -> GatoResult<Vec<String>> {
+        let mut ids = Vec::new();
+        if !self.root_path.exists() {
+            return Ok(ids);
+        }
+        for entry in fs::read_dir(&self.root_path)? {
+            let entry = entry?;
+            if entry.path().join("refs").is_dir() {
+                ids.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Every repository of the store and whether its working directory is
+    /// still where it was registered.
+    #[instrument]
+    pub fn list_repos(&self) -> GatoResult<Vec<RegisteredRepo>> {
+        let registry = self.registry()?;
+        let mut repos: Vec<RegisteredRepo> = registry
+            .repos
+            .iter()
+            .map(|(id, path)| {
+                let state = match load_config(path) {
+                    Ok(config) if &config.id == id => RepoState::Present,
+                    Ok(config) => RepoState::Replaced { by: config.id },
+                    Err(_) => RepoState::Missing,
+                };
+                RegisteredRepo {
+                    id: id.clone(),
+                    path: Some(path.clone()),
+                    state,
+                }
+            })
+            .collect();
+        for id in self.stored_repo_ids()? {
+            if !registry.repos.contains_key(&id) {
+                repos.push(RegisteredRepo {
+                    id,
+                    path: None,
+                    state: RepoState::Unregistered,
+                });
+            }
+        }
+        Ok(repos)
+    }
+
+    /// A storage for each repository of the store with refs. Repositories
+    /// whose working directory cannot be found get one without it, so their
+    /// refs, reflogs and index still count.
+    #[instrument]
+    pub fn repo_storages(&self) -> GatoResult<Vec<(LocalStorage, RegisteredRepo)>> {
+        Ok(self
+            .list_repos()?
+            .into_iter()
+            .filter(|repo| self.root_path.join(&repo.id).join("refs").is_dir())
+            .map(|repo| {
+                let root_path = self.root_path.clone();
+                let storage = match (&repo.state, &repo.path) {
+                    (RepoState::Present, Some(path)) => {
+                        LocalStorage::new(root_path, repo.id.clone(), path.clone())
+                    }
+                    _ => LocalStorage::in_store(root_path, repo.id.clone()),
+                };
+                (storage, repo)
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    fs, io,
+    path::PathBuf,
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use tracing::instrument;
+
+use crate::core::{
+    commit::{Commit, Tree, TreeEntry, blob::Blob, graph::CommitGraph},
+    error::GatoResult,
+    storage::{ObjectStore, gc::Gc, local::LocalStorage},
+};
+
+/// How many objects of each kind some history needs, and how big it is.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ObjectCounts {
+    pub commits: usize,
+    pub trees: usize,
+    pub blobs: usize,
+    pub chunks: usize,
+    /// Size of every file of every commit, as checked out.
+    pub logical_bytes: u64,
+    /// Size of the distinct file contents and chunks, uncompressed.
+    pub content_bytes: u64,
+    /// Size of the file contents and chunks in the store.
+    pub data_bytes: u64,
+    /// Size of every object in the store, commits and trees included.
+    pub stored_bytes: u64,
+}
+
+impl ObjectCounts {
+    /// How much smaller storing each content once makes the history.
+    pub fn dedup_ratio(&self) -> f64 {
+        ratio(self.logical_bytes, self.content_bytes)
+    }
+
+    /// How much smaller compression makes the stored contents.
+    pub fn compression_ratio(&self) -> f64 {
+        ratio(self.content_bytes, self.data_bytes)
+    }
+}
+
+fn ratio(before: u64, after: u64) -> f64 {
+    if after == 0 {
+        1.0
+    } else {
+        before as f64 / after as f64
+    }
+}
+
+impl Display for ObjectCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "\t{} commits, {} trees, {} blobs, {} chunks",
+            self.commits, self.trees, self.blobs, self.chunks
+        )?;
+        writeln!(
+            f,
+            "\t{} logical bytes, {} after dedup ({:.2}x), {} stored ({:.2}x compression), {} with commits and trees",
+            self.logical_bytes,
+            self.content_bytes,
+            self.dedup_ratio(),
+            self.data_bytes,
+            self.compression_ratio(),
+            self.stored_bytes
+        )
+    }
+}
+
+/// How often chunks of large files are shared.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ChunkReuse {
+    /// Chunks used by more than one file version.
+    pub across_files: usize,
+    /// Chunks used by more than one commit.
+    pub across_commits: usize,
+    /// Chunks used by more than one repository.
+    pub across_repos: usize,
+    /// Uncompressed bytes not stored again because a chunk was reused.
+    pub saved_bytes: u64,
+}
+
+/// The versions a path had over the history of a repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileHistory {
+    pub repo: String,
+    pub path: PathBuf,
+    pub versions: usize,
+    /// Stored size of those versions, with their chunks.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoStats {
+    pub id: String,
+    pub counts: ObjectCounts,
+    pub largest: Vec<FileHistory>,
+}
+
+/// What `gato stats` reports: each repository, then the whole store, where
+/// objects shared by repositories are counted once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreStats {
+    pub repos: Vec<RepoStats>,
+    pub total: ObjectCounts,
+    pub chunk_reuse: ChunkReuse,
+    pub largest: Vec<FileHistory>,
+}
+
+fn write_largest(
+    f: &mut std::fmt::Formatter<'_>,
+    files: &[FileHistory],
+    repo: bool,
+) -> std::fmt::Result {
+    if files.is_empty() {
+        return Ok(());
+    }
+    writeln!(f, "\tlargest files by history:")?;
+    for file in files {
+        let path = match repo {
+            true => format!("{}:{}", file.repo, file.path.display()),
+            false => file.path.display().to_string(),
+        };
+        writeln!(
+            f,
+            "\t\t{} bytes in {} versions\t{path}",
+            file.bytes, file.versions
+        )?;
+    }
+    Ok(())
+}
+
+impl Display for StoreStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for repo in &self.repos {
+            writeln!(f, "repository {}", repo.id)?;
+            write!(f, "{}", repo.counts)?;
+            write_largest(f, &repo.largest, false)?;
+        }
+        writeln!(f, "store")?;
+        write!(f, "{}", self.total)?;
+        let reuse = &self.chunk_reuse;
+        writeln!(
+            f,
+            "\tchunks shared by files: {}, by commits: {}, by repositories: {}, saving {} bytes",
+            reuse.across_files, reuse.across_commits, reuse.across_repos, reuse.saved_bytes
+        )?;
+        write_largest(f, &self.largest, true)
+    }
+}
+
+/// The objects reachable from the roots `gc` keeps for one repository.
+#[derive(Debug, Default)]
+struct Reach {
+    /// Each commit with its root tree.
+    commits: HashMap<String, String>,
+    trees: HashSet<String>,
+    blobs: HashSet<String>,
+    /// The distinct versions of each path.
+    versions: HashMap<PathBuf, HashSet<String>>,
+}
+
+impl Reach {
+    fn extend(&mut self, other: &Reach) {
+        self.commits.extend(other.commits.clone());
+        self.trees.extend(other.trees.iter().cloned());
+        self.blobs.extend(other.blobs.iter().cloned());
+    }
+}
+
+/// Size of a file version and the chunks it is split into.
+#[derive(Debug, Default)]
+struct BlobInfo {
+    size: u64,
+    chunks: Vec<String>,
+}
+
+/// What is known about the objects of the store, loaded once for all repositories.
+#[derive(Debug, Default)]
+struct Census {
+    trees: HashMap<String, Tree>,
+    blobs: HashMap<String, BlobInfo>,
+    chunk_sizes: HashMap<String, u64>,
+    stored: HashMap<String, u64>,
+    tree_sizes: HashMap<String, u64>,
+    /// How many commits need each object.
+    commit_refs: HashMap<String, usize>,
+}
+
+impl Census {
+    fn tree(&mut self, hash: &str, storage: &LocalStorage) -> GatoResult<&Tree> {
+        if !self.trees.contains_key(hash) {
+            let tree = Tree::load(hash.to_string(), storage)?;
+            self.trees.insert(hash.to_string(), tree);
+        }
+        Ok(&self.trees[hash])
+    }
+
+    /// Walk the history of `repo`, visiting each subtree once per path it
+    /// appears at, so unchanged directories are not listed again.
+    fn reach(&mut self, repo: &LocalStorage, store: &LocalStorage) -> GatoResult<Reach> {
+        let mut reach = Reach::default();
+        let mut seen = HashSet::new();
+        let roots = Gc::roots(repo)?;
+        for hash in CommitGraph::new(repo).reachable(&roots) {
+            let commit = Commit::load(hash.clone(), store);
+            if !self.commit_refs.contains_key(&hash) {
+                for dependency in commit.dependices() {
+                    *self.commit_refs.entry(dependency).or_default() += 1;
+                }
+                self.commit_refs.insert(hash.clone(), 1);
+            }
+            let root = hex::encode(commit.tree_hash());
+            reach.commits.insert(hash, root.clone());
+            let mut stack = vec![(PathBuf::new(), root)];
+            while let Some((prefix, tree)) = stack.pop() {
+                if !seen.insert((prefix.clone(), tree.clone())) {
+                    continue;
+                }
+                for entry in &self.tree(&tree, store)?.entries {
+                    match entry {
+                        TreeEntry::Blob(name, hash) => {
+                            let hash = hex::encode(hash);
+                            let versions = reach.versions.entry(prefix.join(name)).or_default();
+                            versions.insert(hash.clone());
+                            reach.blobs.insert(hash);
+                        }
+                        TreeEntry::Tree(name, hash) => {
+                            stack.push((prefix.join(name), hex::encode(hash)));
+                        }
+                    }
+                }
+                reach.trees.insert(tree);
+            }
+        }
+        Ok(reach)
+    }
+
+    /// Decode the blobs and measure the chunks and stored objects of `reach`,
+    /// in parallel.
+    fn measure(&mut self, reach: &Reach, store: &LocalStorage) -> GatoResult<()> {
+        let blobs: Vec<&String> = reach
+            .blobs
+            .iter()
+            .filter(|h| !self.blobs.contains_key(*h))
+            .collect();
+        let infos = blobs
+            .par_iter()
+            .map(|hash| Ok(((*hash).clone(), blob_info(hash, store)?)))
+            .collect::<GatoResult<Vec<_>>>()?;
+        self.blobs.extend(infos);
+
+        let chunks: HashSet<&String> = reach
+            .blobs
+            .iter()
+            .flat_map(|hash| &self.blobs[hash].chunks)
+            .filter(|hash| !self.chunk_sizes.contains_key(*hash))
+            .collect();
+        let sizes = chunks
+            .par_iter()
+            .map(|hash| {
+                let data = store.get(hash)?;
+                Ok(((*hash).clone(), content_len(&data)?))
+            })
+            .collect::<GatoResult<Vec<_>>>()?;
+        self.chunk_sizes.extend(sizes);
+
+        let objects: Vec<&String> = reach
+            .commits
+            .keys()
+            .chain(&reach.trees)
+            .chain(&reach.blobs)
+            .chain(self.chunk_sizes.keys())
+            .filter(|hash| !self.stored.contains_key(*hash))
+            .collect();
+        let stored: Vec<(String, u64)> = objects
+            .par_iter()
+            .map(|hash| {
+                let len = fs::metadata(store.objects_path(hash)).map_or(0, |m| m.len());
+                ((*hash).clone(), len)
+            })
+            .collect();
+        self.stored.extend(stored);
+        Ok(())
+    }
+
+    /// Size of the files below `hash` once checked out.
+    fn tree_size(&mut self, hash: &str) -> u64 {
+        if let Some(size) = self.tree_sizes.get(hash) {
+            return *size;
+        }
+        let entries = self
+            .trees
+            .get(hash)
+            .map(|tree| tree.entries.clone())
+            .unwrap_or_default();
+        let mut size = 0;
+        for entry in entries {
+            size += match entry {
+                TreeEntry::Blob(_, hash) => {
+                    self.blobs.get(&hex::encode(hash)).map_or(0, |b| b.size)
+                }
+                TreeEntry::Tree(_, hash) => self.tree_size(&hex::encode(hash)),
+            };
+        }
+        self.tree_sizes.insert(hash.to_string(), size);
+        size
+    }
+
+    fn chunks_of<'a>(&'a self, reach: &'a Reach) -> HashSet<&'a String> {
+        reach
+            .blobs
+            .iter()
+            .flat_map(|hash| &self.blobs[hash].chunks)
+            .collect()
+    }
+
+    fn counts(&mut self, reach: &Reach) -> ObjectCounts {
+        let chunks = self.chunks_of(reach);
+        let stored = |hash: &String| self.stored.get(hash).copied().unwrap_or(0);
+        let whole_blobs = reach
+            .blobs
+            .iter()
+            .filter(|hash| self.blobs[*hash].chunks.is_empty());
+        let data_bytes: u64 = whole_blobs.clone().map(stored).sum::<u64>()
+            + chunks.iter().copied().map(stored).sum::<u64>();
+        let content_bytes: u64 = whole_blobs.map(|hash| self.blobs[hash].size).sum::<u64>()
+            + chunks
+                .iter()
+                .map(|hash| self.chunk_sizes[*hash])
+                .sum::<u64>();
+        let stored_bytes = reach
+            .commits
+            .keys()
+            .chain(&reach.trees)
+            .chain(&reach.blobs)
+            .chain(chunks.iter().copied())
+            .map(stored)
+            .sum();
+        let mut counts = ObjectCounts {
+            commits: reach.commits.len(),
+            trees: reach.trees.len(),
+            blobs: reach.blobs.len(),
+            chunks: chunks.len(),
+            logical_bytes: 0,
+            content_bytes,
+            data_bytes,
+            stored_bytes,
+        };
+        for root in reach.commits.values() {
+            counts.logical_bytes += self.tree_size(root);
+        }
+        counts
+    }
+
+    /// The `top` paths whose versions take the most room in the store.
+    fn largest(&self, id: &str, reach: &Reach, top: usize) -> Vec<FileHistory> {
+        let stored = |hash: &String| self.stored.get(hash).copied().unwrap_or(0);
+        let mut files: Vec<FileHistory> = reach
+            .versions
+            .iter()
+            .map(|(path, versions)| FileHistory {
+                repo: id.to_string(),
+                path: path.clone(),
+                versions: versions.len(),
+                bytes: versions
+                    .iter()
+                    .map(|hash| {
+                        stored(hash) + self.blobs[hash].chunks.iter().map(stored).sum::<u64>()
+                    })
+                    .sum(),
+            })
+            .collect();
+        sort_largest(&mut files, top);
+        files
+    }
+}
+
+fn sort_largest(files: &mut Vec<FileHistory>, top: usize) {
+    files.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    files.truncate(top);
+}
+
+/// Uncompressed size of a stored content, without keeping it in memory.
+fn content_len(data: &[u8]) -> GatoResult<u64> {
+    let mut decoder = zstd::stream::read::Decoder::new(data)?;
+    Ok(io::copy(&mut decoder, &mut io::sink())?)
+}
+
+fn blob_info(hash: &str, store: &LocalStorage) -> GatoResult<BlobInfo> {
+    Ok(match Blob::new(hash.to_string(), store)? {
+        Blob::Normal(content) => BlobInfo {
+            size: content_len(&content)?,
+            chunks: Vec::new(),
+        },
+        Blob::ChunksMap(index_data) => BlobInfo {
+            size: 0,
+            chunks: index_data.path.iter().map(hex::encode).collect(),
+        },
+    })
+}
+
+impl LocalStorage {
+    /// Count and measure the objects the history of each repository of the
+    /// store needs, from the roots `gc` keeps, and how much deduplication,
+    /// compression and chunking save. `top` bounds the lists of largest files.
+    #[instrument]
+    pub fn stats(&self, top: usize) -> GatoResult<StoreStats> {
+        let mut census = Census::default();
+        let mut reaches = Vec::new();
+        for (repo, registered) in self.repo_storages()? {
+            let reach = census.reach(&repo, self)?;
+            census.measure(&reach, self)?;
+            reaches.push((registered.id, reach));
+        }
+        // a chunked file's size is the size of its chunks
+        let chunk_sizes = &census.chunk_sizes;
+        for info in census.blobs.values_mut() {
+            if !info.chunks.is_empty() {
+                info.size = info.chunks.iter().map(|hash| chunk_sizes[hash]).sum();
+            }
+        }
+
+        let mut all = Reach::default();
+        let mut repos = Vec::new();
+        let mut largest = Vec::new();
+        let mut chunk_repos: HashMap<String, usize> = HashMap::new();
+        for (id, reach) in &reaches {
+            for chunk in census.chunks_of(reach) {
+                *chunk_repos.entry(chunk.clone()).or_default() += 1;
+            }
+            let repo = RepoStats {
+                id: id.clone(),
+                counts: census.counts(reach),
+                largest: census.largest(id, reach, top),
+            };
+            largest.extend(repo.largest.iter().cloned());
+            repos.push(repo);
+            all.extend(reach);
+        }
+        sort_largest(&mut largest, top);
+
+        let mut chunk_files: HashMap<&String, usize> = HashMap::new();
+        let mut saved_bytes = 0;
+        for hash in &all.blobs {
+            let chunks = &census.blobs[hash].chunks;
+            for chunk in chunks.iter().collect::<HashSet<_>>() {
+                *chunk_files.entry(chunk).or_default() += 1;
+            }
+            saved_bytes += chunks
+                .iter()
+                .map(|chunk| census.chunk_sizes[chunk])
+                .sum::<u64>();
+        }
+        let chunks = census.chunks_of(&all);
+        saved_bytes -= chunks
+            .iter()
+            .map(|chunk| census.chunk_sizes[*chunk])
+            .sum::<u64>();
+        let chunk_reuse = ChunkReuse {
+            across_files: chunk_files.values().filter(|n| **n > 1).count(),
+            across_commits: chunks
+                .iter()
+                .filter(|chunk| census.commit_refs.get(**chunk).is_some_and(|n| *n > 1))
+                .count(),
+            across_repos: chunk_repos.values().filter(|n| **n > 1).count(),
+            saved_bytes,
+        };
+        Ok(StoreStats {
+            repos,
+            total: census.counts(&all),
+            chunk_reuse,
+            largest,
+        })
+    }
+}
@@ -8,6 +8,8 @@ pub enum FileStatus {
     ToBeCommited { path: PathBuf },
     NotStagedForCommit { path: PathBuf },
     UntrackedFiles { path: PathBuf },
+    Renamed { from: PathBuf, to: PathBuf },
+    Copied { from: PathBuf, to: PathBuf },
     Unmodified,
 }
 
@@ -55,6 +57,18 @@ impl std::fmt::Display for FileStatus {
                     path.display().to_string().yellow()
                 )
             }
+            FileStatus::Renamed { from, to } | FileStatus::Copied { from, to } => {
+                let label = match self {
+                    FileStatus::Renamed { .. } => "renamed",
+                    _ => "copied",
+                };
+                write!(
+                    f,
+                    "\t{}:   {}",
+                    label.green(),
+                    format!("{} -> {}", from.display(), to.display()).green()
+                )
+            }
             FileStatus::UntrackedFiles { path } => {
                 write!(f, "\t{}", path.display().to_string().red())
             }
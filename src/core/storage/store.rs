@@ -0,0 +1,118 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use tracing::instrument;
+
+use crate::core::{
+    config::load::load_config,
+    error::{Error, GatoResult},
+    storage::{ObjectStore, gc::Gc, local::LocalStorage},
+};
+
+/// What moving a repository to another store did.
+#[derive(Debug)]
+pub struct StoreMigration {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Objects the repository needs, and how many of them were copied
+    /// rather than found in the new store already.
+    pub objects: usize,
+    pub copied: usize,
+    pub bytes: u64,
+}
+
+impl Display for StoreMigration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "moved from {} to {}: copied {} of {} objects, {} bytes",
+            self.from.display(),
+            self.to.display(),
+            self.copied,
+            self.objects,
+            self.bytes
+        )?;
+        writeln!(
+            f,
+            "objects left in {} are removed by `gato gc` once no repository there needs them",
+            self.from.display()
+        )
+    }
+}
+
+/// Copy the directory `from` to `to`, leaving out lock files of writes in
+/// progress.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if !entry.file_name().to_string_lossy().ends_with(".lock") {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+impl LocalStorage {
+    /// Move the repository to the store at `to`: copy every object it needs,
+    /// as `gc` would mark them, then its refs, reflogs, index and worktree
+    /// state, and point `gato.toml` of each of its worktrees at the new store
+    /// (dropping `[storage] path` unless `in_config`). The repository is then
+    /// removed from this store; objects other repositories share stay.
+    #[instrument]
+    pub fn migrate_store(&self, to: &Path, in_config: bool) -> GatoResult<StoreMigration> {
+        fs::create_dir_all(to)?;
+        let to = to.canonicalize()?;
+        let target_repo = to.join(self.repo_id());
+        if target_repo.exists() || self.root_path.canonicalize()? == to {
+            return Err(Error::AlreadyInStore {
+                id: self.repo_id().to_string(),
+                store: to,
+            });
+        }
+        // no collection may delete objects of this store while they are copied
+        let _lock = self.lock(&self.root_path.join("gc"))?;
+        let mark = Gc::new(vec![self.clone()]).mark()?;
+
+        let worktrees = self.worktrees()?;
+        let main_dir = worktrees[0].path.clone();
+        let target = LocalStorage::new(to.clone(), self.repo_id().to_string(), main_dir);
+        let objects: Vec<&String> = mark.objects.iter().collect();
+        let copied = objects
+            .par_iter()
+            .filter(|hash| self.exist(hash) && !target.exist(hash))
+            .map(|hash| {
+                let data = self.get(hash)?;
+                let len = data.len() as u64;
+                target.put(hash, data)?;
+                Ok(len)
+            })
+            .collect::<GatoResult<Vec<u64>>>()?;
+
+        copy_dir(&self.repo_path(), &target_repo)?;
+        target.push_to_repos()?;
+        for worktree in worktrees.iter().filter(|worktree| worktree.is_present()) {
+            let mut config = load_config(&worktree.path)?;
+            config.set_store_path(in_config.then_some(to.as_path()), &worktree.path);
+            fs::write(worktree.path.join("gato.toml"), toml::to_string(&config)?)?;
+        }
+
+        let from = self.root_path.canonicalize()?;
+        self.pop_from_repos()?;
+        fs::remove_dir_all(self.repo_path())?;
+        Ok(StoreMigration {
+            from,
+            to,
+            objects: mark.objects.len(),
+            copied: copied.len(),
+            bytes: copied.iter().sum(),
+        })
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use colored::Colorize;
+use tracing::instrument;
+
+use crate::core::{
+    add::index::Index,
+    commit::Commit,
+    config::load::load_config,
+    error::{Error, GatoResult},
+    storage::{StorageError, local::LocalStorage},
+};
+
+/// A working directory of the repository, with its own `HEAD` and index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Worktree {
+    /// `None` for the main worktree.
+    pub name: Option<String>,
+    pub path: PathBuf,
+    /// The branch checked out in it.
+    pub branch: String,
+}
+
+impl Worktree {
+    /// Whether its working directory is still there.
+    pub fn is_present(&self) -> bool {
+        self.path.join("gato.toml").is_file()
+    }
+}
+
+impl Display for Worktree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.name.as_deref().unwrap_or("(main)"),
+            self.path.display(),
+            format!("[{}]", self.branch).bright_yellow()
+        )?;
+        if !self.is_present() {
+            write!(f, " {}", "missing".red())?;
+        }
+        Ok(())
+    }
+}
+
+impl LocalStorage {
+    pub(crate) fn worktrees_dir(&self) -> PathBuf {
+        self.repo_path().join("worktrees")
+    }
+
+    /// A storage for each linked worktree, reading its own `HEAD`, index and
+    /// operation in progress.
+    #[instrument]
+    pub fn linked_worktrees(&self) -> Result<Vec<LocalStorage>, StorageError> {
+        let dir = self.worktrees_dir();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut linked = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = fs::read_to_string(entry.path().join("path")).unwrap_or_default();
+            linked.push(self.for_worktree(Some(name), PathBuf::from(path)));
+        }
+        linked.sort_by(|a, b| a.worktree().cmp(&b.worktree()));
+        Ok(linked)
+    }
+
+    /// The main worktree, then every linked one.
+    #[instrument]
+    pub fn worktrees(&self) -> Result<Vec<Worktree>, StorageError> {
+        let main_path = match self.worktree() {
+            None => self
+                .work_dir()
+                .canonicalize()
+                .unwrap_or_else(|_| self.work_dir().clone()),
+            Some(_) => self
+                .registry()
+                .ok()
+                .and_then(|registry| registry.repos.get(self.repo_id()).cloned())
+                .unwrap_or_default(),
+        };
+        let main = self.for_worktree(None, main_path);
+        Ok(std::iter::once(main)
+            .chain(self.linked_worktrees()?)
+            .map(|storage| Worktree {
+                name: storage.worktree().map(str::to_string),
+                branch: storage.get_active_branche(),
+                path: storage.work_dir().clone(),
+            })
+            .collect())
+    }
+
+    /// The other worktree `branch` is checked out in, if any.
+    pub fn checked_out_elsewhere(&self, branch: &str) -> Result<Option<Worktree>, StorageError> {
+        Ok(self.worktrees()?.into_iter().find(|worktree| {
+            worktree.branch == branch && worktree.name.as_deref() != self.worktree()
+        }))
+    }
+
+    /// Check `branch` out in a new linked worktree at `path`, which gets its
+    /// own `HEAD` and index. A branch can only be checked out in one worktree.
+    #[instrument]
+    pub fn worktree_add(&self, path: &Path, branch: &str) -> GatoResult<LocalStorage> {
+        let head = self
+            .read_ref_vec(branch.to_string())
+            .map_err(|_| Error::UnknownRevision(branch.to_string()))?;
+        if let Some(other) = self.worktrees()?.into_iter().find(|w| w.branch == branch) {
+            return Err(StorageError::BranchCheckedOut {
+                branch: branch.to_string(),
+                path: other.path,
+            }
+            .into());
+        }
+        if path.exists() && fs::read_dir(path)?.next().is_some() {
+            return Err(Error::TargetNotEmpty(path.to_path_buf()));
+        }
+        fs::create_dir_all(path)?;
+        let path = path.canonicalize()?;
+
+        let base = path.file_name().map_or("worktree".to_string(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.worktrees_dir().join(&name).exists() {
+            n += 1;
+            name = format!("{base}-{n}");
+        }
+        let state = self.worktrees_dir().join(&name);
+        fs::create_dir_all(&state)?;
+        fs::write(state.join("path"), path.to_string_lossy().as_bytes())?;
+        let mut config = load_config(self.work_dir())?;
+        config.worktree = Some(name);
+        if config.store_path(self.work_dir()).is_some() {
+            config.set_store_path(Some(&self.root_path.canonicalize()?), &path);
+        }
+        fs::write(path.join("gato.toml"), toml::to_string(&config)?)?;
+
+        let worktree = LocalStorage::new(self.root_path.clone(), self.repo_id().to_string(), path);
+        worktree.write_atomic(&worktree.state_file("HEAD"), branch.as_bytes())?;
+        let reason = format!("worktree: add {branch}");
+        worktree.log_ref_change("HEAD", None, Some(hex::encode(&head)), &reason)?;
+        Commit::load(hex::encode(head), &worktree).write_tree(worktree.work_dir(), &worktree)?;
+        Ok(worktree)
+    }
+
+    /// Forget the linked worktree named `name`, or at the path `name`, and
+    /// return its path. Its `gato.toml` is removed; other files are left in
+    /// place. Without `force`, staged changes or an operation in progress
+    /// keep it.
+    #[instrument]
+    pub fn worktree_remove(&self, name: &str, force: bool) -> GatoResult<PathBuf> {
+        let path = Path::new(name).canonicalize().ok();
+        let worktree = self
+            .linked_worktrees()?
+            .into_iter()
+            .find(|w| w.worktree() == Some(name) || Some(w.work_dir()) == path.as_ref())
+            .ok_or_else(|| Error::UnknownWorktree(name.to_string()))?;
+        let busy = Index::index_file_path(&worktree).exists()
+            || worktree.operation_in_progress().is_some();
+        if busy && !force {
+            return Err(Error::WorktreeBusy(name.to_string()));
+        }
+        let linked_name = worktree.worktree().expect("linked worktrees have a name");
+        fs::remove_dir_all(self.worktrees_dir().join(linked_name))?;
+        let config = worktree.work_dir().join("gato.toml");
+        if load_config(worktree.work_dir())
+            .is_ok_and(|c| c.worktree.as_deref() == worktree.worktree())
+        {
+            fs::remove_file(config)?;
+        }
+        Ok(worktree.work_dir().clone())
+    }
+}
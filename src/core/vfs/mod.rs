@@ -5,23 +5,23 @@ use fuser::{FileType, Filesystem};
 
 use crate::core::{
     commit::Tree,
-    storage::local::LocalStorage,
+    storage::{ObjectStore, local::LocalStorage},
     vfs::{
         error::{VFSError, VFSResult},
         models::{TreeNode, TreeNodes},
     },
 };
 
-pub struct GatoFS {
+pub struct GatoFS<S: ObjectStore = LocalStorage> {
     // root_tree: Arc<RwLock<Tree>>,
     inodes: TreeNodes,
     next: AtomicU64,
     loaded: Vec<u64>,
-    storage: LocalStorage,
+    storage: S,
 }
 
-impl GatoFS {
-    pub fn new(root_tree: Tree, storage: LocalStorage) -> Self {
+impl<S: ObjectStore> GatoFS<S> {
+    pub fn new(root_tree: Tree, storage: S) -> Self {
         let mut root_entry = root_tree.into_entry();
         root_entry.change_name(".".to_string());
         let root_node = TreeNode::new(1, 1, root_entry);
@@ -92,7 +92,7 @@ impl GatoFS {
     }
 }
 
-impl Filesystem for GatoFS {
+impl<S: ObjectStore + 'static> Filesystem for GatoFS<S> {
     fn destroy(&mut self) {}
     fn lookup(
         &mut self,
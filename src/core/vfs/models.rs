@@ -8,7 +8,7 @@ use fuser::{FileAttr, FileType};
 use crate::core::{
     add::add_file_dry,
     commit::{Tree, TreeEntry, blob::Blob},
-    storage::local::LocalStorage,
+    storage::ObjectStore,
     vfs::error::{VFSError, VFSResult},
 };
 
@@ -69,7 +69,7 @@ impl TreeNode {
 
     pub fn write(
         &mut self,
-        storage: &LocalStorage,
+        storage: &(impl ObjectStore + ?Sized),
         offset: usize,
         data: &[u8],
         nodes: &mut TreeNodes,
@@ -101,7 +101,7 @@ impl TreeNode {
         &mut self,
         nodes: &mut TreeNodes,
         new_entry: TreeEntry,
-        storage: &LocalStorage,
+        storage: &(impl ObjectStore + ?Sized),
         parents: &mut Vec<Arc<RwLock<Self>>>,
     ) -> VFSResult<()> {
         println!("update run ");
@@ -121,7 +121,7 @@ impl TreeNode {
                     // replace here mean it's replace the hash of the same tree name in the tree
                     tree.replace(&new_entry);
                     // this mean i will save the tree to the store
-                    tree.save(&storage);
+                    tree.save(storage);
                     self.replace_entry(tree.into_entry());
 
                     if self.inode != self.parent {
@@ -137,7 +137,7 @@ impl TreeNode {
         Ok(())
     }
 
-    pub fn load(&mut self, storage: &LocalStorage) {
+    pub fn load(&mut self, storage: &(impl ObjectStore + ?Sized)) {
         if !self.loaded {
             match &self.entry {
                 TreeEntry::Blob(_, hash) => {
@@ -155,7 +155,7 @@ impl TreeNode {
         }
     }
 
-    fn get_size(&mut self, storage: &LocalStorage) -> u64 {
+    fn get_size(&mut self, storage: &(impl ObjectStore + ?Sized)) -> u64 {
         self.load(storage);
         match &self.entry {
             TreeEntry::Blob(_, _) => self.len,
@@ -163,7 +163,7 @@ impl TreeNode {
         }
     }
 
-    pub fn make_attr(&mut self, storage: &LocalStorage) -> FileAttr {
+    pub fn make_attr(&mut self, storage: &(impl ObjectStore + ?Sized)) -> FileAttr {
         let now = SystemTime::now();
 
         let kind = match self.entry {
@@ -241,7 +241,7 @@ impl TreeNodes {
         &self,
         parent: u64,
         name: &String,
-        storage: &LocalStorage,
+        storage: &(impl ObjectStore + ?Sized),
     ) -> VFSResult<FileAttr> {
         let read = self.data.read().map_err(|_| VFSError::LockPoisoned)?;
 
@@ -278,7 +278,11 @@ impl TreeNodes {
     //     Ok(parents)
     // }
 
-    pub fn get_node_attr(&self, inode: u64, storage: &LocalStorage) -> VFSResult<FileAttr> {
+    pub fn get_node_attr(
+        &self,
+        inode: u64,
+        storage: &(impl ObjectStore + ?Sized),
+    ) -> VFSResult<FileAttr> {
         let nodes_read = self.data.read().map_err(|_| VFSError::LockPoisoned)?;
         for e in nodes_read.iter() {
             let mut node_read = e.write().map_err(|_| VFSError::LockPoisoned)?;
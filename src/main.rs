@@ -1,52 +1,125 @@
-use std::{path::PathBuf, sync::OnceLock};
+use std::{fs, io, time::Duration};
 
 use clap::Parser;
 use colored::Colorize;
-use directories::ProjectDirs;
-mod core;
-use crate::core::{
+use gato::core;
+use gato::core::{
     cli::{
-        cli::{Cli, Commands},
-        init, init_from,
+        cli::{Cli, Commands, ReflogAction, RepoAction, StoreAction, WorktreeAction},
+        get_store_path, init, init_from, store_for,
+    },
+    commit::{
+        Commit, Tree,
+        graph::CommitGraph,
+        merge::{MergeOptions, Side},
+        message,
+        signature::{self, Verification},
+    },
+    diff::{self, RenameOptions},
+    error::{Error, GatoResult},
+    storage::{
+        StorageEngine,
+        fsck::FsckReport,
+        gc::{Gc, GcOptions},
+        local::LocalStorage,
+        merge::{FastForward, Operation},
     },
-    commit::Commit,
-    error::GatoResult,
-    storage::{StorageEngine, gc::Gc, local::LocalStorage},
 };
 
-static GLOBAL_STORE_PATH: OnceLock<PathBuf> = OnceLock::new();
-pub fn get_store_path() -> &'static PathBuf {
-    GLOBAL_STORE_PATH.get_or_init(|| {
-        if let Some(proj_dirs) = ProjectDirs::from("com", "gatocloud", "gato") {
-            proj_dirs.data_local_dir().to_path_buf()
-        } else {
-            PathBuf::from(".gato")
-        }
-    })
-}
-
 fn run() -> GatoResult<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Init { id } => {
+        Commands::Init { id, store } => {
             if let Some(id) = id {
-                init_from(cli.path, id);
+                init_from(cli.path, id, store);
             } else {
-                init(cli.path)
+                init(cli.path, store)
             }
         }
+        Commands::Clone { source, dest } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), source)?;
+            let clone = storage.clone_repo(&dest)?;
+            println!(
+                "cloned {} into {} as {}",
+                storage.repo_id(),
+                dest.display(),
+                clone.repo_id()
+            );
+        }
         Commands::Add { paths } => {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
             storage.add_paths(paths)?;
         }
-        Commands::Commit { message } => {
+        Commands::Commit {
+            message,
+            file,
+            all,
+            amend,
+            no_edit,
+            author,
+            trailers,
+            sign,
+        } => {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
-            storage.commit(message)?
+            if all {
+                storage.stage_tracked()?;
+            }
+            let message = match (message, file) {
+                (Some(message), _) => Some(message::cleanup(&message, false)),
+                (None, Some(file)) if file.as_os_str() == "-" => {
+                    Some(message::cleanup(&io::read_to_string(io::stdin())?, false))
+                }
+                (None, Some(file)) => Some(message::cleanup(&fs::read_to_string(file)?, false)),
+                (None, None) => None,
+            };
+            if message.as_ref().is_some_and(String::is_empty) {
+                return Err(Error::EmptyCommitMessage);
+            }
+            if amend {
+                let message = match message {
+                    None if !no_edit => {
+                        let last = Commit::load_by_index(0, &storage)
+                            .map(|commit| commit.message().clone())
+                            .unwrap_or_default();
+                        Some(message::edit(&last, &storage)?)
+                    }
+                    message => message,
+                };
+                println!(
+                    "Amended commit {}",
+                    storage.amend(message, author.as_deref(), sign)?
+                );
+            } else {
+                let message = match message {
+                    Some(message) => message,
+                    None => {
+                        // a stopped merge, cherry-pick or revert suggests its message
+                        let suggested =
+                            fs::read_to_string(storage.state_file("MERGE_MSG")).unwrap_or_default();
+                        message::edit(&suggested, &storage)?
+                    }
+                };
+                if author.is_none() && trailers.is_empty() && !sign {
+                    storage.commit(message)?
+                } else {
+                    let headers = trailers.into_iter().collect();
+                    storage.commit_as(message, author.as_deref(), headers, sign)?
+                }
+            }
         }
-        Commands::Checkout { commit_index } => {
+        Commands::Checkout {
+            commit_index,
+            ours,
+            theirs,
+        } => {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
-            storage.check_out(commit_index)?
+            match (commit_index, ours, theirs) {
+                (_, Some(path), _) => storage.checkout_side(&path, Side::Ours)?,
+                (_, _, Some(path)) => storage.checkout_side(&path, Side::Theirs)?,
+                (Some(commit_index), ..) => storage.check_out(commit_index)?,
+                _ => {}
+            }
         }
         Commands::NewBranch { branch_name } => {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
@@ -60,17 +133,88 @@ fn run() -> GatoResult<()> {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
             storage.soft_reset(commit_index);
         }
-        Commands::Gc => {
-            let storage = LocalStorage::tmp(get_store_path().clone());
-            storage.gc()?;
+        Commands::Gc {
+            dry_run,
+            grace_period,
+        } => {
+            let storage = LocalStorage::tmp(store_for(&cli.path));
+            let report = storage.gc(GcOptions {
+                dry_run,
+                grace_period: grace_period.map(|minutes| Duration::from_secs(minutes * 60)),
+            })?;
+            print!("{report}");
+        }
+        Commands::Stats { top } => {
+            let storage = LocalStorage::tmp(store_for(&cli.path));
+            print!("{}", storage.stats(top)?);
+        }
+        Commands::Reflog {
+            action: Some(ReflogAction::Expire { expire }),
+            ..
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let days = expire.unwrap_or_else(|| storage.reflog_expire_days());
+            println!("dropped {} reflog entries", storage.reflog_expire(days)?);
+        }
+        Commands::Reflog {
+            action: None,
+            ref_name,
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let ref_name = ref_name.unwrap_or_else(|| "HEAD".to_string());
+            for (n, entry) in storage.reflog(&ref_name)?.iter().enumerate() {
+                let hash = entry.new.as_deref().unwrap_or("(deleted)");
+                println!(
+                    "{} {ref_name}@{{{n}}}: {}",
+                    hash.get(..8).unwrap_or(hash).bright_yellow(),
+                    entry.reason
+                );
+            }
         }
         Commands::ListRepos => {
-            let storage = LocalStorage::tmp(get_store_path().clone());
-            let repos_path = storage.list_repos()?;
-            for repo in repos_path {
-                println!("{:?}", repo);
+            let storage = LocalStorage::tmp(store_for(&cli.path));
+            for repo in storage.list_repos()? {
+                println!("{repo}");
+            }
+        }
+        Commands::Worktree { action } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            match action {
+                WorktreeAction::Add { path, branch } => {
+                    let worktree = storage.worktree_add(&path, &branch)?;
+                    println!("checked out {branch} in {}", worktree.work_dir().display());
+                }
+                WorktreeAction::List => {
+                    for worktree in storage.worktrees()? {
+                        println!("{worktree}");
+                    }
+                }
+                WorktreeAction::Remove { worktree, force } => {
+                    let path = storage.worktree_remove(&worktree, force)?;
+                    println!(
+                        "removed worktree {worktree}; its files are still in {}",
+                        path.display()
+                    );
+                }
             }
         }
+        Commands::Repo {
+            action: RepoAction::Relocate,
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            match storage.relocate()? {
+                Some(old) => println!("moved {} from {}", storage.repo_id(), old.display()),
+                None => println!("{} is registered here", storage.repo_id()),
+            }
+        }
+        Commands::Store {
+            action: StoreAction::Migrate { to },
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let in_config = to.is_some();
+            let to = to.unwrap_or_else(|| get_store_path().clone());
+            print!("{}", storage.migrate_store(&to, in_config)?);
+        }
         Commands::DeleteRepo => {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
             storage.delete_repo()?;
@@ -86,31 +230,230 @@ fn run() -> GatoResult<()> {
             storage.status()?;
         }
         Commands::Merge {
-            target_branch,
+            targets,
             message,
+            no_ff,
+            ff_only,
+            strategy,
+            binary,
+            continue_merge,
+            abort,
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            if abort {
+                storage.merge_abort()?;
+                return Ok(());
+            }
+            if continue_merge {
+                // with `--continue` a positional argument is the message
+                let outcome = storage.merge_continue(message.or(targets.into_iter().next()))?;
+                println!("{outcome}");
+                return Ok(());
+            }
+            let options = MergeOptions { strategy, binary };
+            if targets.len() > 1 {
+                if ff_only {
+                    return Err(Error::NotFastForward);
+                }
+                println!("{}", storage.octopus_merge(targets, message, options)?);
+                return Ok(());
+            }
+            let Some(target_branch) = targets.into_iter().next() else {
+                return Err(Error::NoMergeTarget);
+            };
+            let fast_forward = if no_ff {
+                FastForward::Never
+            } else if ff_only {
+                FastForward::Only
+            } else {
+                FastForward::Allow
+            };
+            let outcome = storage.merge(target_branch, message, fast_forward, options)?;
+            println!("{outcome}");
+        }
+        Commands::CherryPick {
+            rev,
+            strategy,
+            binary,
+            continue_pick,
+            abort,
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let options = MergeOptions { strategy, binary };
+            let action = PickAction::from_flags(rev, continue_pick, abort);
+            run_pick(&storage, Operation::CherryPick, action, options)?;
+        }
+        Commands::Revert {
+            rev,
+            strategy,
+            binary,
+            continue_pick,
+            abort,
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let options = MergeOptions { strategy, binary };
+            let action = PickAction::from_flags(rev, continue_pick, abort);
+            run_pick(&storage, Operation::Revert, action, options)?;
+        }
+        Commands::Rebase {
+            upstream,
+            onto,
+            autosquash,
+            strategy,
+            binary,
+            continue_rebase,
+            skip,
+            abort,
         } => {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
-            storage.merge(target_branch, message)?;
+            let options = MergeOptions { strategy, binary };
+            let outcome = match upstream {
+                _ if abort => return storage.rebase_abort(),
+                _ if continue_rebase => storage.rebase_continue(None, options)?,
+                _ if skip => storage.rebase_skip(options)?,
+                Some(upstream) => {
+                    storage.rebase(&upstream, onto.as_deref(), autosquash, options)?
+                }
+                None => storage.rebase_continue(None, options)?,
+            };
+            println!("{outcome}");
+        }
+        Commands::Conflicts => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            for (path, conflict) in storage.conflicts()? {
+                println!(
+                    "{:>16}: {}",
+                    conflict.kind,
+                    path.display().to_string().red()
+                );
+            }
+        }
+        Commands::MergeBase { first, second, all } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let first = storage.resolve_rev(&first)?;
+            let second = storage.resolve_rev(&second)?;
+            let bases = CommitGraph::new(&storage).merge_bases(&[first], &second);
+            let shown = if all { bases.len() } else { 1 };
+            for base in bases.iter().take(shown) {
+                println!("{base}");
+            }
         }
         Commands::VerifyCommit { commit_hash } => {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
-            let commit = Commit::load(commit_hash, &storage);
+            let hash = storage.resolve_rev(&commit_hash)?;
+            let commit = Commit::load(hash.clone(), &storage);
             let result = commit.verify_commit(&storage)?;
             if result {
                 println!("{}", "the integrity of the commit is OK!".green());
             } else {
                 println!("{}", "some files deleted".red());
             }
+            let verification = commit.verify_signature(&signature::allowed_signers(&storage)?);
+            if !verification.is_good() {
+                return Err(Error::UnverifiedCommit(hash, verification));
+            }
+            println!("{}", verification.to_string().green());
+        }
+        Commands::Keygen { path } => {
+            let key = signature::generate_key(&path)?;
+            println!("{}", signature::key_hex(&key));
         }
-        Commands::ListCommits => {
+        Commands::ListCommits {
+            follow: Some(path), ..
+        } => {
             let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let head = storage.resolve_rev("HEAD")?;
+            for (hash, path) in diff::follow(head, path, RenameOptions::default(), &storage)? {
+                let commit = Commit::load(hash.clone(), &storage);
+                println!(
+                    "message : {} \nhash : {}\npath : {}\n\n",
+                    commit.message().green(),
+                    hash.bright_yellow(),
+                    path.display()
+                );
+            }
+        }
+        Commands::ListCommits {
+            follow: None,
+            show_signature,
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let allowed = match show_signature {
+                true => signature::allowed_signers(&storage)?,
+                false => Vec::new(),
+            };
             let commits = Gc::list_repo_commits(&storage)?;
             for commit in commits {
                 println!(
-                    "message : {} \nhash : {}\n\n",
+                    "message : {} \nhash : {}\nauthor : {}",
                     commit.message().green(),
-                    commit.hash()?.bright_yellow()
+                    commit.hash()?.bright_yellow(),
+                    commit.author()
                 );
+                if show_signature {
+                    let verification = commit.verify_signature(&allowed);
+                    match verification {
+                        Verification::Good { .. } => {
+                            println!("{}", verification.to_string().green())
+                        }
+                        Verification::Unsigned => println!("{verification}"),
+                        _ => println!("{}", verification.to_string().red()),
+                    }
+                }
+                for (key, value) in commit.headers() {
+                    if key != signature::SIGNATURE_HEADER {
+                        println!("{key} : {value}");
+                    }
+                }
+                println!("\n");
+            }
+        }
+        Commands::Diff {
+            from,
+            to,
+            find_renames,
+            no_renames,
+            find_copies,
+        } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let from = Commit::load(storage.resolve_rev(&from)?, &storage);
+            let to = Commit::load(
+                storage.resolve_rev(to.as_deref().unwrap_or("HEAD"))?,
+                &storage,
+            );
+            let options = RenameOptions {
+                threshold: (!no_renames).then_some(find_renames),
+                copies: find_copies,
+            };
+            let old = Tree::load(hex::encode(from.tree_hash()), &storage)?;
+            let new = Tree::load(hex::encode(to.tree_hash()), &storage)?;
+            for change in diff::diff_trees(&old, &new, options, &storage)? {
+                println!("{change}");
+            }
+        }
+        Commands::Fsck { repair } => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let recovery = storage.recover_objects(repair)?;
+            print!("{recovery}");
+            if !recovery.is_ok() && !repair {
+                println!("run `gato fsck --repair` to delete damaged objects.");
+            }
+            let report = FsckReport::run(&storage)?;
+            print!("{report}");
+            if !report.non_canonical_trees.is_empty() {
+                println!("run `gato migrate-trees` to rewrite non-canonical trees.");
+            }
+            if !report.is_ok() || !(recovery.is_ok() || repair) {
+                return Err(Error::FsckFailed);
+            }
+            println!("{}", "no problems found".green());
+        }
+        Commands::MigrateTrees => {
+            let storage = LocalStorage::load_from(get_store_path().clone(), cli.path.clone())?;
+            let rewritten = storage.migrate_trees()?;
+            println!("rewrote {rewritten} commits.");
+            if rewritten > 0 {
+                println!("you may need to run `gato gc`.");
             }
         }
         #[cfg(unix)]
@@ -135,3 +478,39 @@ fn main() {
         Err(e) => println!("{}: {}", "Error".red().bold(), e),
     }
 }
+
+enum PickAction {
+    Start(String),
+    Continue,
+    Abort,
+}
+
+impl PickAction {
+    fn from_flags(rev: Option<String>, continue_pick: bool, abort: bool) -> Self {
+        match rev {
+            _ if abort => PickAction::Abort,
+            _ if continue_pick => PickAction::Continue,
+            Some(rev) => PickAction::Start(rev),
+            None => PickAction::Continue,
+        }
+    }
+}
+
+/// Shared driver of `cherry-pick` and `revert`.
+fn run_pick(
+    storage: &LocalStorage,
+    operation: Operation,
+    action: PickAction,
+    options: MergeOptions,
+) -> GatoResult<()> {
+    let outcome = match action {
+        PickAction::Abort => return storage.pick_abort(operation),
+        PickAction::Continue => storage.pick_continue(operation, None)?,
+        PickAction::Start(rev) if operation == Operation::Revert => {
+            storage.revert(&rev, options)?
+        }
+        PickAction::Start(rev) => storage.cherry_pick(&rev, options)?,
+    };
+    println!("{outcome}");
+    Ok(())
+}
@@ -4,7 +4,6 @@
 /// covering the edge-cases called out in the problem statement.
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 
 use tempfile::TempDir;
 
@@ -12,10 +11,26 @@ use gato::core::add::{
     add_all, add_file, compute_hash, compress, decompress, find_files, is_ignored, smart_read,
     FileContent,
 };
-use gato::core::add::index::Index;
-use gato::core::commit::Commit;
-use gato::core::storage::StorageEngine;
+use gato::core::add::index::{ConflictKind, Index};
+use gato::core::commit::{Commit, Tree, TreeEntry};
+use gato::core::commit::graph::CommitGraph;
+use gato::core::commit::graph_file::GraphFile;
+use gato::core::commit::merge::{MergeOptions, MergeStrategy, Side};
+use gato::core::commit::identity::{Identity, Role, parse_date};
+use gato::core::commit::message;
+use gato::core::commit::signature::{self, Verification};
+use gato::core::config::FsyncPolicy;
+use gato::core::error::Error;
+use gato::core::diff::{Change, RenameOptions, diff_files, follow};
+use gato::core::storage::fsck::FsckReport;
+use gato::core::storage::gc::GcOptions;
+use gato::core::storage::{ObjectStore, RefStore, StorageEngine, StorageError};
 use gato::core::storage::local::LocalStorage;
+use gato::core::storage::lock::{LockFile, lock_path};
+use gato::core::storage::merge::{FastForward, MergeOutcome, Operation};
+use gato::core::storage::pick::PickOutcome;
+use gato::core::storage::rebase::RebaseOutcome;
+use gato::core::storage::reflog::ReflogEntry;
 
 // ── helpers ──────────────────────────────────────────────────────────────────
 
@@ -428,7 +443,7 @@ fn add_file_empty_file_succeeds() {
 #[test]
 fn add_all_empty_paths_leaves_index_empty() {
     let e = env();
-    add_all(vec![], Arc::new(e.storage.clone())).unwrap();
+    add_all(vec![], e.storage.work_dir(), &e.storage).unwrap();
     let idx = Index::load(&e.storage).unwrap();
     assert!(idx.entries.is_empty());
 }
@@ -441,7 +456,8 @@ fn add_all_indexes_multiple_files() {
 
     add_all(
         vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")],
-        Arc::new(e.storage.clone()),
+        e.storage.work_dir(),
+        &e.storage,
     )
     .unwrap();
 
@@ -459,7 +475,8 @@ fn add_all_deduplicates_objects_for_identical_content() {
 
     add_all(
         vec![PathBuf::from("x.txt"), PathBuf::from("y.txt")],
-        Arc::new(e.storage.clone()),
+        e.storage.work_dir(),
+        &e.storage,
     )
     .unwrap();
 
@@ -558,3 +575,2141 @@ fn deleting_active_branch_returns_error() {
         "deleting the active branch must return an error"
     );
 }
+
+// ── canonical trees ──────────────────────────────────────────────────────────
+
+fn blob_entry(name: &str, content: &[u8]) -> TreeEntry {
+    TreeEntry::Blob(name.to_string(), compute_hash(content).to_vec())
+}
+
+#[test]
+fn tree_hash_does_not_depend_on_entry_order() {
+    let e = env();
+    let mut t1 = Tree::new("root".to_string());
+    t1.entries = vec![blob_entry("b.txt", b"b"), blob_entry("a.txt", b"a")];
+    let mut t2 = Tree::new("root".to_string());
+    t2.entries = vec![blob_entry("a.txt", b"a"), blob_entry("b.txt", b"b")];
+
+    assert_eq!(t1.save(&e.storage), t2.save(&e.storage));
+    assert!(t1.is_canonical());
+}
+
+#[test]
+fn commit_tree_entries_are_sorted_by_name() {
+    let e = env();
+    fs::create_dir_all(e.storage.work_dir().join("a")).unwrap();
+    fs::write(e.storage.work_dir().join("a/x.txt"), b"x").unwrap();
+    fs::write(e.storage.work_dir().join("b.txt"),   b"b").unwrap();
+    fs::write(e.storage.work_dir().join("c.txt"),   b"c").unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit("sorted".to_string()).unwrap();
+
+    let tree  = e.storage.get_last_tree().unwrap();
+    let names: Vec<&String> = tree.entries.iter().map(|entry| entry.name()).collect();
+    assert_eq!(names, vec!["a", "b.txt", "c.txt"]);
+}
+
+#[test]
+fn fsck_detects_and_migrate_trees_fixes_non_canonical_trees() {
+    let e = env();
+    let legacy = Tree {
+        name:    "root".to_string(),
+        entries: vec![blob_entry("z.txt", b"z"), blob_entry("a.txt", b"a")],
+    };
+    let bytes = bincode::encode_to_vec(&legacy, bincode::config::standard()).unwrap();
+    let legacy_hash = compute_hash(&bytes).to_vec();
+    e.storage.put(&hex::encode(&legacy_hash), bytes).unwrap();
+    Commit::V1 {
+        message:      "legacy".to_string(),
+        author:       "Tester".to_string(),
+        timestamp:    0,
+        email:        None,
+        tree_hash:    legacy_hash.clone(),
+        parent_hash:  None,
+        dependencies: vec![hex::encode(&legacy_hash)],
+    }
+    .save(&e.storage)
+    .unwrap();
+
+    let report = FsckReport::run(&e.storage).unwrap();
+    assert_eq!(report.non_canonical_trees, vec![hex::encode(&legacy_hash)]);
+
+    assert_eq!(e.storage.migrate_trees().unwrap(), 1);
+    assert!(FsckReport::run(&e.storage).unwrap().non_canonical_trees.is_empty());
+    let commit = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_ne!(commit.tree_hash(), legacy_hash);
+    assert_eq!(commit.message(), "legacy");
+}
+
+// ── merge base ───────────────────────────────────────────────────────────────
+
+/// Store a commit with an empty tree and the given parents, returning its hash.
+fn raw_commit(e: &Env, message: &str, parents: &[&Vec<u8>]) -> Vec<u8> {
+    let mut tree = Tree::new("root".to_string());
+    tree.save(&e.storage);
+    let commit = match parents {
+        [first, second] => Commit::MergedCommitV1 {
+            message:      message.to_string(),
+            author:       "Tester".to_string(),
+            timestamp:    0,
+            email:        None,
+            tree_hash:    tree.hash(),
+            parent_hash1: (*first).clone(),
+            parent_hash2: (*second).clone(),
+            dependencies: vec![],
+        },
+        _ => Commit::V1 {
+            message:      message.to_string(),
+            author:       "Tester".to_string(),
+            timestamp:    0,
+            email:        None,
+            tree_hash:    tree.hash(),
+            parent_hash:  parents.first().map(|p| (*p).clone()),
+            dependencies: vec![],
+        },
+    };
+    commit.put(&e.storage).unwrap()
+}
+
+#[test]
+fn merge_base_of_ancestor_is_the_ancestor_itself() {
+    let e = env();
+    let a = raw_commit(&e, "a", &[]);
+    let b = raw_commit(&e, "b", &[&a]);
+
+    let bases = CommitGraph::new(&e.storage).merge_bases(&[hex::encode(&a)], &hex::encode(&b));
+    assert_eq!(bases, vec![hex::encode(&a)]);
+}
+
+#[test]
+fn merge_base_follows_second_parents() {
+    let e  = env();
+    let a  = raw_commit(&e, "a", &[]);
+    let m  = raw_commit(&e, "m", &[&a]);
+    let b  = raw_commit(&e, "b", &[&a]);
+    let x  = raw_commit(&e, "merge b into master", &[&m, &b]);
+    let b2 = raw_commit(&e, "b2", &[&b]);
+
+    let bases = CommitGraph::new(&e.storage).merge_bases(&[hex::encode(&x)], &hex::encode(&b2));
+    assert_eq!(bases, vec![hex::encode(&b)]);
+}
+
+#[test]
+fn merge_base_of_criss_cross_merge_returns_both_bases() {
+    let e  = env();
+    let a  = raw_commit(&e, "a", &[]);
+    let m1 = raw_commit(&e, "m1", &[&a]);
+    let b1 = raw_commit(&e, "b1", &[&a]);
+    let x  = raw_commit(&e, "x", &[&m1, &b1]);
+    let y  = raw_commit(&e, "y", &[&b1, &m1]);
+
+    let mut bases = CommitGraph::new(&e.storage).merge_bases(&[hex::encode(&x)], &hex::encode(&y));
+    bases.sort();
+    let mut expected = vec![hex::encode(&m1), hex::encode(&b1)];
+    expected.sort();
+    assert_eq!(bases, expected);
+}
+
+#[test]
+fn resolve_rev_accepts_branches_hashes_and_prefixes() {
+    let e = env();
+    let a = raw_commit(&e, "a", &[]);
+    e.storage.write_ref("master".to_string(), a.clone()).unwrap();
+    let full = hex::encode(&a);
+
+    assert_eq!(e.storage.resolve_rev("master").unwrap(), full);
+    assert_eq!(e.storage.resolve_rev("HEAD").unwrap(), full);
+    assert_eq!(e.storage.resolve_rev(&full).unwrap(), full);
+    assert_eq!(e.storage.resolve_rev(&full[..12]).unwrap(), full);
+    assert!(e.storage.resolve_rev("no-such-branch").is_err());
+}
+
+#[test]
+fn resolve_rev_only_matches_commits() {
+    let e = env();
+    let a = raw_commit(&e, "a", &[]);
+    let full = hex::encode(&a);
+    // an object that is not a commit, sharing the commit's prefix
+    let other = format!("{}{}", &full[..12], "0".repeat(52));
+    e.storage.put(&other, b"not a commit".to_vec()).unwrap();
+
+    assert_eq!(e.storage.resolve_rev(&full[..12]).unwrap(), full);
+    let err = e.storage.resolve_rev(&other).unwrap_err();
+    assert!(matches!(err, Error::UnknownRevision(_)), "{err}");
+    let err = e.storage.resolve_rev(&other[..20]).unwrap_err();
+    assert!(matches!(err, Error::UnknownRevision(_)), "{err}");
+}
+
+// ── fast-forward merges ──────────────────────────────────────────────────────
+
+/// Write `files`, stage the whole working directory and commit it.
+fn commit_files(e: &Env, files: &[(&str, &str)], message: &str) {
+    for (name, content) in files {
+        let path = e.storage.work_dir().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit(message.to_string()).unwrap();
+}
+
+/// master: base ── feature: base + feature commit, with master checked out.
+fn feature_ahead_of_master(e: &Env) {
+    commit_files(e, &[("a.txt", "a")], "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    e.storage.change_branch("feature".to_string()).unwrap();
+    commit_files(e, &[("b.txt", "b")], "feature");
+    e.storage.change_branch("master".to_string()).unwrap();
+}
+
+#[test]
+fn merge_fast_forwards_when_target_descends_from_current() {
+    let e = env();
+    feature_ahead_of_master(&e);
+
+    let outcome = e.storage.merge("feature".to_string(), None, FastForward::Allow, MergeOptions::default()).unwrap();
+    let feature = e.storage.read_ref_vec("feature".to_string()).unwrap();
+    assert_eq!(outcome, MergeOutcome::FastForward { to: hex::encode(&feature) });
+    assert_eq!(e.storage.read_ref_vec("master".to_string()).unwrap(), feature);
+    assert_eq!(fs::read_to_string(e.storage.work_dir().join("b.txt")).unwrap(), "b");
+}
+
+#[test]
+fn merge_of_already_merged_branch_is_up_to_date() {
+    let e = env();
+    feature_ahead_of_master(&e);
+    e.storage.change_branch("feature".to_string()).unwrap();
+
+    let before  = e.storage.read_ref_vec("feature".to_string()).unwrap();
+    let outcome = e.storage.merge("master".to_string(), None, FastForward::Allow, MergeOptions::default()).unwrap();
+    assert_eq!(outcome, MergeOutcome::UpToDate);
+    assert_eq!(e.storage.read_ref_vec("feature".to_string()).unwrap(), before);
+}
+
+#[test]
+fn merge_no_ff_creates_merge_commit() {
+    let e = env();
+    feature_ahead_of_master(&e);
+
+    let outcome = e.storage.merge("feature".to_string(), None, FastForward::Never, MergeOptions::default()).unwrap();
+    assert!(matches!(outcome, MergeOutcome::Merged { .. }));
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.parents().len(), 2);
+    assert_eq!(head.message(), "Merge branch 'feature'");
+}
+
+#[test]
+fn merge_ff_only_refuses_diverged_branches() {
+    let e = env();
+    feature_ahead_of_master(&e);
+    commit_files(&e, &[("c.txt", "c")], "master moves on");
+
+    let before = e.storage.read_ref_vec("master".to_string()).unwrap();
+    assert!(e.storage.merge("feature".to_string(), None, FastForward::Only, MergeOptions::default()).is_err());
+    assert_eq!(e.storage.read_ref_vec("master".to_string()).unwrap(), before);
+}
+
+#[test]
+fn merge_refuses_to_overwrite_local_changes() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    e.storage.change_branch("feature".to_string()).unwrap();
+    commit_files(&e, &[("a.txt", "feature")], "feature");
+    e.storage.change_branch("master".to_string()).unwrap();
+    fs::write(e.storage.work_dir().join("a.txt"), "local edit").unwrap();
+
+    let before = e.storage.read_ref_vec("master".to_string()).unwrap();
+    let err = e.storage.merge("feature".to_string(), None, FastForward::Allow, MergeOptions::default());
+    assert!(matches!(err, Err(Error::LocalChanges(paths)) if paths == vec![PathBuf::from("a.txt")]));
+    assert_eq!(e.storage.read_ref_vec("master".to_string()).unwrap(), before);
+    assert_eq!(read_work_file(&e, "a.txt"), "local edit");
+}
+
+// ── conflicted merges ────────────────────────────────────────────────────────
+
+fn conflicted_paths(e: &Env) -> Vec<PathBuf> {
+    e.storage.conflicts().unwrap().into_keys().collect()
+}
+
+/// master and feature both rewrite `a.txt`, with master checked out.
+fn conflicting_branches(e: &Env) {
+    commit_files(e, &[("a.txt", "line\n")], "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    e.storage.change_branch("feature".to_string()).unwrap();
+    commit_files(e, &[("a.txt", "feature\n")], "feature");
+    e.storage.change_branch("master".to_string()).unwrap();
+    commit_files(e, &[("a.txt", "master\n")], "master");
+}
+
+#[test]
+fn conflicted_merge_keeps_state_and_blocks_commit() {
+    let e = env();
+    conflicting_branches(&e);
+
+    let outcome = e.storage.merge("feature".to_string(), None, FastForward::Allow, MergeOptions::default()).unwrap();
+    assert_eq!(outcome, MergeOutcome::Conflicted { paths: vec![PathBuf::from("a.txt")] });
+    assert!(e.storage.merge_head().is_some());
+    assert_eq!(conflicted_paths(&e), vec![PathBuf::from("a.txt")]);
+
+    let content = fs::read_to_string(e.storage.work_dir().join("a.txt")).unwrap();
+    assert!(content.contains("<<<<<<<") && content.contains(">>>>>>>"));
+    assert!(e.storage.commit("too early".to_string()).is_err());
+    assert!(e.storage.merge("feature".to_string(), None, FastForward::Allow, MergeOptions::default()).is_err());
+}
+
+#[test]
+fn merge_continue_commits_resolved_conflicts_with_both_parents() {
+    let e = env();
+    conflicting_branches(&e);
+    e.storage.merge("feature".to_string(), None, FastForward::Allow, MergeOptions::default()).unwrap();
+
+    fs::write(e.storage.work_dir().join("a.txt"), "resolved\n").unwrap();
+    e.storage.add_paths(vec!["a.txt".to_string()]).unwrap();
+    assert!(e.storage.conflicts().unwrap().is_empty());
+
+    let outcome = e.storage.merge_continue(None).unwrap();
+    assert!(matches!(outcome, MergeOutcome::Merged { .. }));
+    assert!(e.storage.merge_head().is_none());
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.parents().len(), 2);
+    assert_eq!(head.message(), "Merge branch 'feature'");
+}
+
+#[test]
+fn merge_abort_restores_working_directory() {
+    let e = env();
+    conflicting_branches(&e);
+    e.storage.merge("feature".to_string(), None, FastForward::Allow, MergeOptions::default()).unwrap();
+
+    e.storage.merge_abort().unwrap();
+    assert!(e.storage.merge_head().is_none());
+    assert!(e.storage.conflicts().unwrap().is_empty());
+    assert_eq!(fs::read_to_string(e.storage.work_dir().join("a.txt")).unwrap(), "master\n");
+    assert!(e.storage.merge_abort().is_err());
+}
+
+#[test]
+fn merge_refuses_to_start_with_staged_changes() {
+    let e = env();
+    conflicting_branches(&e);
+    fs::write(e.storage.work_dir().join("b.txt"), "staged").unwrap();
+    e.storage.add_paths(vec!["b.txt".to_string()]).unwrap();
+
+    let err = e.storage.merge("feature".to_string(), None, FastForward::Allow, MergeOptions::default());
+    assert!(matches!(err, Err(Error::StagedChanges)));
+    assert!(e.storage.merge_head().is_none());
+    assert!(Index::load(&e.storage).unwrap().entries.contains_key(Path::new("b.txt")));
+    assert_eq!(read_work_file(&e, "a.txt"), "master\n");
+}
+
+#[test]
+fn index_files_without_conflicts_still_load() {
+    let e = env();
+    let entry = gato::core::add::index::IndexEntry { hash: compute_hash(b"a").to_vec(), size: 1, mtime: 0, mode: 0o644 };
+    let entries = std::collections::BTreeMap::from([(PathBuf::from("a.txt"), entry)]);
+    let legacy = bincode::encode_to_vec((entries, vec!["dep".to_string()]), bincode::config::standard()).unwrap();
+    fs::write(Index::index_file_path(&e.storage), legacy).unwrap();
+
+    fs::write(e.storage.work_dir().join("b.txt"), "b").unwrap();
+    e.storage.add_paths(vec!["b.txt".to_string()]).unwrap();
+    let index = Index::load(&e.storage).unwrap();
+    assert!(index.entries.contains_key(Path::new("a.txt")));
+    assert!(index.entries.contains_key(Path::new("b.txt")));
+    assert!(index.conflicts.is_empty());
+}
+
+#[test]
+fn add_refuses_to_replace_an_unreadable_index() {
+    let e = env();
+    fs::write(Index::index_file_path(&e.storage), b"garbage").unwrap();
+    fs::write(e.storage.work_dir().join("b.txt"), "b").unwrap();
+
+    assert!(e.storage.add_paths(vec!["b.txt".to_string()]).is_err());
+    assert_eq!(fs::read(Index::index_file_path(&e.storage)).unwrap(), b"garbage");
+}
+
+// ── merge strategies ─────────────────────────────────────────────────────────
+
+fn merge_with(e: &Env, strategy: MergeStrategy, binary: Option<Side>) -> MergeOutcome {
+    let options = MergeOptions { strategy, binary };
+    e.storage.merge("feature".to_string(), None, FastForward::Allow, options).unwrap()
+}
+
+fn read_work_file(e: &Env, name: &str) -> String {
+    fs::read_to_string(e.storage.work_dir().join(name)).unwrap()
+}
+
+#[test]
+fn strategy_ours_and_theirs_settle_conflicting_hunks() {
+    for (strategy, expected) in [(MergeStrategy::Ours, "master\n"), (MergeStrategy::Theirs, "feature\n")] {
+        let e = env();
+        conflicting_branches(&e);
+        commit_files(&e, &[("b.txt", "only on master")], "unrelated");
+
+        assert!(matches!(merge_with(&e, strategy, None), MergeOutcome::Merged { .. }));
+        assert_eq!(read_work_file(&e, "a.txt"), expected);
+        assert_eq!(read_work_file(&e, "b.txt"), "only on master");
+    }
+}
+
+#[test]
+fn strategy_union_keeps_both_hunks() {
+    let e = env();
+    conflicting_branches(&e);
+
+    assert!(matches!(merge_with(&e, MergeStrategy::Union, None), MergeOutcome::Merged { .. }));
+    assert_eq!(read_work_file(&e, "a.txt"), "master\nfeature\n");
+}
+
+#[test]
+fn binary_option_picks_a_side_for_binary_files() {
+    let e = env();
+    let write = |content: &[u8]| {
+        fs::write(e.storage.work_dir().join("img.bin"), content).unwrap();
+        e.storage.add_paths(vec![".".to_string()]).unwrap();
+    };
+    write(&[0xff, 0x00, 0x01]);
+    e.storage.commit("base".to_string()).unwrap();
+    e.storage.new_branch("feature".to_string()).unwrap();
+    e.storage.change_branch("feature".to_string()).unwrap();
+    write(&[0xff, 0x00, 0x02]);
+    e.storage.commit("feature".to_string()).unwrap();
+    e.storage.change_branch("master".to_string()).unwrap();
+    write(&[0xff, 0x00, 0x03]);
+    e.storage.commit("master".to_string()).unwrap();
+
+    assert!(matches!(merge_with(&e, MergeStrategy::Normal, Some(Side::Theirs)), MergeOutcome::Merged { .. }));
+    assert_eq!(fs::read(e.storage.work_dir().join("img.bin")).unwrap(), vec![0xff, 0x00, 0x02]);
+}
+
+#[test]
+fn checkout_side_restores_one_version_of_a_conflicted_path() {
+    let e = env();
+    conflicting_branches(&e);
+    merge_with(&e, MergeStrategy::Normal, None);
+
+    e.storage.checkout_side(Path::new("a.txt"), Side::Theirs).unwrap();
+    assert_eq!(read_work_file(&e, "a.txt"), "feature\n");
+    e.storage.checkout_side(Path::new("a.txt"), Side::Ours).unwrap();
+    assert_eq!(read_work_file(&e, "a.txt"), "master\n");
+    assert_eq!(conflicted_paths(&e), vec![PathBuf::from("a.txt")]);
+    assert!(e.storage.checkout_side(Path::new("missing.txt"), Side::Ours).is_err());
+}
+
+// ── deletions, type changes and renames in merges ────────────────────────────
+
+/// Commit `base_files` on master, then let `on_feature` and `on_master` change
+/// the working directory before committing each side; master is checked out.
+fn diverge(e: &Env, base_files: &[(&str, &str)], on_feature: impl Fn(&Path), on_master: impl Fn(&Path)) {
+    commit_files(e, base_files, "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    e.storage.change_branch("feature".to_string()).unwrap();
+    on_feature(e.storage.work_dir());
+    commit_files(e, &[], "feature");
+    let feature_tree = e.storage.get_last_tree().unwrap();
+    e.storage.change_branch("master".to_string()).unwrap();
+    let master_tree = e.storage.get_last_tree().unwrap();
+    e.storage.update_work_dir(&feature_tree, &master_tree).unwrap();
+    on_master(e.storage.work_dir());
+    commit_files(e, &[], "master");
+}
+
+fn head_files(e: &Env) -> Vec<PathBuf> {
+    e.storage.get_last_tree().unwrap().files(&e.storage).unwrap().into_keys().collect()
+}
+
+#[test]
+fn merge_propagates_clean_deletions() {
+    let e = env();
+    diverge(
+        &e,
+        &[("a.txt", "a"), ("dir/b.txt", "b"), ("c.txt", "c")],
+        |w| {
+            fs::remove_file(w.join("c.txt")).unwrap();
+            fs::remove_dir_all(w.join("dir")).unwrap();
+        },
+        |w| fs::write(w.join("a.txt"), "a2").unwrap(),
+    );
+
+    let outcome = merge_with(&e, MergeStrategy::Normal, None);
+    assert!(matches!(outcome, MergeOutcome::Merged { .. }));
+    assert_eq!(head_files(&e), vec![PathBuf::from("a.txt")]);
+    assert!(!e.storage.work_dir().join("dir").exists());
+    assert!(!e.storage.work_dir().join("c.txt").exists());
+}
+
+#[test]
+fn modify_delete_conflict_keeps_modified_file_until_resolved() {
+    let e = env();
+    diverge(
+        &e,
+        &[("a.txt", "a"), ("keep.txt", "k")],
+        |w| fs::remove_file(w.join("a.txt")).unwrap(),
+        |w| fs::write(w.join("a.txt"), "a2").unwrap(),
+    );
+
+    let outcome = merge_with(&e, MergeStrategy::Normal, None);
+    assert_eq!(outcome, MergeOutcome::Conflicted { paths: vec![PathBuf::from("a.txt")] });
+    let conflict = &e.storage.conflicts().unwrap()[Path::new("a.txt")];
+    assert_eq!(conflict.kind, ConflictKind::DeletedByThem);
+    assert!(conflict.theirs.is_none());
+    assert_eq!(read_work_file(&e, "a.txt"), "a2");
+
+    fs::remove_file(e.storage.work_dir().join("a.txt")).unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.merge_continue(None).unwrap();
+    assert_eq!(head_files(&e), vec![PathBuf::from("keep.txt")]);
+}
+
+#[test]
+fn file_directory_conflict_moves_the_file_aside() {
+    let e = env();
+    diverge(
+        &e,
+        &[("a.txt", "a")],
+        |w| {
+            fs::create_dir(w.join("x")).unwrap();
+            fs::write(w.join("x/y.txt"), "y").unwrap();
+        },
+        |w| fs::write(w.join("x"), "file").unwrap(),
+    );
+
+    let outcome = merge_with(&e, MergeStrategy::Normal, None);
+    assert_eq!(outcome, MergeOutcome::Conflicted { paths: vec![PathBuf::from("x~ours")] });
+    assert_eq!(e.storage.conflicts().unwrap()[Path::new("x~ours")].kind, ConflictKind::FileDirectory);
+    assert_eq!(read_work_file(&e, "x~ours"), "file");
+    assert_eq!(read_work_file(&e, "x/y.txt"), "y");
+}
+
+#[test]
+fn edits_follow_a_file_moved_on_the_other_side() {
+    let e = env();
+    diverge(
+        &e,
+        &[("a.txt", "one\ntwo\n")],
+        |w| fs::rename(w.join("a.txt"), w.join("moved.txt")).unwrap(),
+        |w| fs::write(w.join("a.txt"), "one\ntwo\nthree\n").unwrap(),
+    );
+
+    let outcome = merge_with(&e, MergeStrategy::Normal, None);
+    assert!(matches!(outcome, MergeOutcome::Merged { .. }));
+    assert_eq!(head_files(&e), vec![PathBuf::from("moved.txt")]);
+    assert_eq!(read_work_file(&e, "moved.txt"), "one\ntwo\nthree\n");
+    assert!(!e.storage.work_dir().join("a.txt").exists());
+}
+
+// ── rename and copy detection ────────────────────────────────────────────────
+
+fn files_of(e: &Env, rev: &str) -> std::collections::BTreeMap<PathBuf, Vec<u8>> {
+    let commit = Commit::load(e.storage.resolve_rev(rev).unwrap(), &e.storage);
+    Tree::load(hex::encode(commit.tree_hash()), &e.storage).unwrap().files(&e.storage).unwrap()
+}
+
+/// Numbered lines, enough for a one-line edit to stay well above 50% similar.
+fn lines(n: usize) -> String {
+    (0..n).map(|i| format!("line number {i}\n")).collect()
+}
+
+fn remove_work_files(e: &Env, names: &[&str]) {
+    for name in names {
+        fs::remove_file(e.storage.work_dir().join(name)).unwrap();
+    }
+}
+
+#[test]
+fn diff_detects_exact_and_similar_renames() {
+    let e = env();
+    commit_files(&e, &[("a.txt", &lines(20)), ("b.txt", &lines(30)), ("gone.txt", "x")], "base");
+    let old = files_of(&e, "HEAD");
+    remove_work_files(&e, &["a.txt", "b.txt", "gone.txt"]);
+    let edited = lines(30).replace("line number 7\n", "changed\n");
+    commit_files(&e, &[("moved/a.txt", &lines(20)), ("c.txt", &edited), ("new.txt", "y")], "move");
+    let new = files_of(&e, "HEAD");
+
+    let changes = diff_files(&old, &new, RenameOptions::default(), &e.storage).unwrap();
+    let Change::Renamed { similarity, .. } = changes[0] else {
+        panic!("expected a rename, got {:?}", changes[0]);
+    };
+    assert!((50..100).contains(&similarity));
+    assert_eq!(changes, vec![
+        Change::Renamed { from: "b.txt".into(), to: "c.txt".into(), similarity },
+        Change::Deleted(PathBuf::from("gone.txt")),
+        Change::Renamed { from: "a.txt".into(), to: "moved/a.txt".into(), similarity: 100 },
+        Change::Added(PathBuf::from("new.txt")),
+    ]);
+
+    let off = RenameOptions { threshold: None, copies: false };
+    let changes = diff_files(&old, &new, off, &e.storage).unwrap();
+    assert!(changes.iter().all(|c| matches!(c, Change::Added(_) | Change::Deleted(_))));
+}
+
+#[test]
+fn diff_reports_copies_only_when_asked() {
+    let e = env();
+    commit_files(&e, &[("a.txt", &lines(20))], "base");
+    let old = files_of(&e, "HEAD");
+    commit_files(&e, &[("copy.txt", &lines(20))], "copy");
+    let new = files_of(&e, "HEAD");
+
+    let plain = diff_files(&old, &new, RenameOptions::default(), &e.storage).unwrap();
+    assert_eq!(plain, vec![Change::Added(PathBuf::from("copy.txt"))]);
+    let copies = RenameOptions { copies: true, ..RenameOptions::default() };
+    assert_eq!(diff_files(&old, &new, copies, &e.storage).unwrap(), vec![Change::Copied {
+        from: "a.txt".into(),
+        to: "copy.txt".into(),
+        similarity: 100,
+    }]);
+}
+
+#[test]
+fn chunked_files_are_compared_by_shared_chunks() {
+    let e = env();
+    // Pseudo-random content so the chunker finds content-defined boundaries.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut big: Vec<u8> = (0..9 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(e.storage.work_dir().join("big.bin"), &big).unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit("base".to_string()).unwrap();
+    let old = files_of(&e, "HEAD");
+
+    big[4 * 1024 * 1024] ^= 0xff;
+    fs::remove_file(e.storage.work_dir().join("big.bin")).unwrap();
+    fs::write(e.storage.work_dir().join("renamed.bin"), &big).unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit("move".to_string()).unwrap();
+    let new = files_of(&e, "HEAD");
+
+    let changes = diff_files(&old, &new, RenameOptions::default(), &e.storage).unwrap();
+    assert!(matches!(
+        &changes[..],
+        [Change::Renamed { from, similarity, .. }] if from == Path::new("big.bin") && (50..100).contains(similarity)
+    ));
+}
+
+#[test]
+fn follow_tracks_a_file_across_renames() {
+    let e = env();
+    commit_files(&e, &[("a.txt", &lines(20)), ("other.txt", "o")], "create");
+    commit_files(&e, &[("other.txt", "o2")], "unrelated");
+    remove_work_files(&e, &["a.txt"]);
+    commit_files(&e, &[("b.txt", &lines(21))], "rename");
+    let head = e.storage.resolve_rev("HEAD").unwrap();
+
+    let history = follow(head, PathBuf::from("b.txt"), RenameOptions::default(), &e.storage).unwrap();
+    let messages: Vec<(String, PathBuf)> = history
+        .into_iter()
+        .map(|(hash, path)| (Commit::load(hash, &e.storage).message().clone(), path))
+        .collect();
+    assert_eq!(messages, vec![
+        ("rename".to_string(), PathBuf::from("b.txt")),
+        ("create".to_string(), PathBuf::from("a.txt")),
+    ]);
+}
+
+#[test]
+fn edits_follow_a_file_moved_and_edited_on_the_other_side() {
+    let e = env();
+    let base = lines(20);
+    diverge(
+        &e,
+        &[("a.txt", &base)],
+        |w| {
+            fs::remove_file(w.join("a.txt")).unwrap();
+            fs::write(w.join("moved.txt"), base.replace("line number 2\n", "theirs\n")).unwrap();
+        },
+        |w| fs::write(w.join("a.txt"), base.replace("line number 15\n", "ours\n")).unwrap(),
+    );
+
+    let outcome = merge_with(&e, MergeStrategy::Normal, None);
+    assert!(matches!(outcome, MergeOutcome::Merged { .. }));
+    assert_eq!(head_files(&e), vec![PathBuf::from("moved.txt")]);
+    let merged = read_work_file(&e, "moved.txt");
+    assert!(merged.contains("theirs\n") && merged.contains("ours\n"));
+}
+
+// ── cherry-pick and revert ───────────────────────────────────────────────────
+
+#[test]
+fn cherry_pick_applies_only_the_picked_commit() {
+    let e = env();
+    diverge(
+        &e,
+        &[("a.txt", &lines(10))],
+        |w| fs::write(w.join("first.txt"), "first").unwrap(),
+        |w| fs::write(w.join("ours.txt"), "ours").unwrap(),
+    );
+    e.storage.change_branch("feature".to_string()).unwrap();
+    commit_files(&e, &[("a.txt", &lines(10).replace("line number 3\n", "fixed\n"))], "fix a");
+    let fix = e.storage.resolve_rev("feature").unwrap();
+    e.storage.change_branch("master".to_string()).unwrap();
+    let master_tree = e.storage.get_last_tree().unwrap();
+    let feature_tree = Tree::load(hex::encode(Commit::load(fix.clone(), &e.storage).tree_hash()), &e.storage).unwrap();
+    e.storage.update_work_dir(&feature_tree, &master_tree).unwrap();
+
+    let outcome = e.storage.cherry_pick("feature", MergeOptions::default()).unwrap();
+    assert!(matches!(outcome, PickOutcome::Committed { .. }));
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.parents().len(), 1);
+    assert!(head.message().contains(&format!("(cherry picked from commit {fix})")));
+    assert_eq!(head_files(&e), vec![PathBuf::from("a.txt"), PathBuf::from("ours.txt")]);
+    assert!(read_work_file(&e, "a.txt").contains("fixed\n"));
+
+    let again = e.storage.cherry_pick("feature", MergeOptions::default()).unwrap();
+    assert_eq!(again, PickOutcome::Empty);
+}
+
+#[test]
+fn revert_undoes_a_commit_below_later_changes() {
+    let e = env();
+    commit_files(&e, &[("a.txt", &lines(10))], "base");
+    commit_files(&e, &[("a.txt", &lines(10).replace("line number 1\n", "bad\n"))], "bad change");
+    let bad = e.storage.resolve_rev("HEAD").unwrap();
+    commit_files(&e, &[("a.txt", &lines(10).replace("line number 1\n", "bad\n").replace("line number 8\n", "good\n"))], "later");
+
+    let outcome = e.storage.revert(&bad, MergeOptions::default()).unwrap();
+    assert!(matches!(outcome, PickOutcome::Committed { .. }));
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.message(), &format!("Revert \"bad change\"\n\nThis reverts commit {bad}."));
+    assert_eq!(read_work_file(&e, "a.txt"), lines(10).replace("line number 8\n", "good\n"));
+}
+
+#[test]
+fn conflicting_cherry_pick_stops_and_continues_with_a_single_parent() {
+    let e = env();
+    conflicting_branches(&e);
+
+    let outcome = e.storage.cherry_pick("feature", MergeOptions::default()).unwrap();
+    assert_eq!(outcome, PickOutcome::Conflicted {
+        operation: Operation::CherryPick,
+        paths: vec![PathBuf::from("a.txt")],
+    });
+    assert_eq!(e.storage.operation_in_progress(), Some(Operation::CherryPick));
+    assert!(e.storage.merge("feature".to_string(), None, FastForward::Allow, MergeOptions::default()).is_err());
+    assert!(e.storage.pick_continue(Operation::CherryPick, None).is_err());
+
+    fs::write(e.storage.work_dir().join("a.txt"), "resolved\n").unwrap();
+    e.storage.add_paths(vec!["a.txt".to_string()]).unwrap();
+    e.storage.commit("picked by hand".to_string()).unwrap();
+    assert_eq!(e.storage.operation_in_progress(), None);
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.parents().len(), 1);
+    assert_eq!(head.message(), "picked by hand");
+}
+
+#[test]
+fn pick_abort_restores_head() {
+    let e = env();
+    conflicting_branches(&e);
+    e.storage.revert("feature", MergeOptions::default()).unwrap();
+    assert_eq!(e.storage.operation_in_progress(), Some(Operation::Revert));
+
+    assert!(e.storage.pick_abort(Operation::CherryPick).is_err());
+    e.storage.pick_abort(Operation::Revert).unwrap();
+    assert_eq!(e.storage.operation_in_progress(), None);
+    assert_eq!(read_work_file(&e, "a.txt"), "master\n");
+}
+
+#[test]
+fn cherry_pick_refuses_to_overwrite_local_or_staged_changes() {
+    let e = env();
+    diverge(
+        &e,
+        &[("a.txt", "a")],
+        |w| {
+            fs::write(w.join("a.txt"), "feature").unwrap();
+            fs::write(w.join("c.txt"), "feature c").unwrap();
+        },
+        |w| fs::write(w.join("ours.txt"), "ours").unwrap(),
+    );
+    let head = e.storage.resolve_rev("HEAD").unwrap();
+    fs::write(e.storage.work_dir().join("a.txt"), "local edit").unwrap();
+    fs::write(e.storage.work_dir().join("c.txt"), "local c").unwrap();
+
+    let err = e.storage.cherry_pick("feature", MergeOptions::default());
+    assert!(matches!(err, Err(Error::LocalChanges(paths)) if paths == vec![PathBuf::from("a.txt"), PathBuf::from("c.txt")]));
+    assert_eq!(read_work_file(&e, "a.txt"), "local edit");
+    assert_eq!(read_work_file(&e, "c.txt"), "local c");
+
+    fs::write(e.storage.work_dir().join("a.txt"), "a").unwrap();
+    fs::remove_file(e.storage.work_dir().join("c.txt")).unwrap();
+    fs::write(e.storage.work_dir().join("b.txt"), "staged").unwrap();
+    e.storage.add_paths(vec!["b.txt".to_string()]).unwrap();
+    let err = e.storage.cherry_pick("feature", MergeOptions::default());
+    assert!(matches!(err, Err(Error::StagedChanges)));
+    assert!(Index::load(&e.storage).unwrap().entries.contains_key(Path::new("b.txt")));
+    assert_eq!(e.storage.operation_in_progress(), None);
+    assert_eq!(e.storage.resolve_rev("HEAD").unwrap(), head);
+}
+
+// ── rebase ───────────────────────────────────────────────────────────────────
+
+/// Check out `branch`, bringing the working directory along.
+fn switch(e: &Env, branch: &str) {
+    let from = e.storage.get_last_tree().unwrap();
+    e.storage.change_branch(branch.to_string()).unwrap();
+    e.storage.update_work_dir(&from, &e.storage.get_last_tree().unwrap()).unwrap();
+}
+
+/// Messages of the first-parent history of HEAD, newest first.
+fn history(e: &Env) -> Vec<String> {
+    (0..)
+        .map_while(|i| Commit::load_by_index(i, &e.storage))
+        .map(|commit| commit.message().clone())
+        .collect()
+}
+
+#[test]
+fn rebase_replays_branch_commits_on_upstream() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    switch(&e, "feature");
+    commit_files(&e, &[("b.txt", "b")], "add b");
+    commit_files(&e, &[("c.txt", "c")], "add c");
+    switch(&e, "master");
+    commit_files(&e, &[("m.txt", "m")], "add m");
+    switch(&e, "feature");
+
+    let outcome = e.storage.rebase("master", None, false, MergeOptions::default()).unwrap();
+    assert!(matches!(outcome, RebaseOutcome::Rebased { .. }));
+    assert_eq!(history(&e), vec!["add c", "add b", "add m", "base"]);
+    assert_eq!(Commit::load_by_index(0, &e.storage).unwrap().parents().len(), 1);
+    assert_eq!(read_work_file(&e, "m.txt"), "m");
+    assert!(!e.storage.state_file("rebase").exists());
+
+    let again = e.storage.rebase("master", None, false, MergeOptions::default()).unwrap();
+    assert_eq!(again, RebaseOutcome::UpToDate);
+}
+
+#[test]
+fn rebase_refuses_to_overwrite_local_or_staged_changes() {
+    // nothing to replay: the branch only moves to master
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    commit_files(&e, &[("a.txt", "master")], "change a");
+    switch(&e, "feature");
+    let feature = e.storage.resolve_rev("feature").unwrap();
+    fs::write(e.storage.work_dir().join("a.txt"), "MY UNSAVED WORK").unwrap();
+
+    let err = e.storage.rebase("master", None, false, MergeOptions::default());
+    assert!(matches!(err, Err(Error::LocalChanges(paths)) if paths == vec![PathBuf::from("a.txt")]));
+    assert_eq!(read_work_file(&e, "a.txt"), "MY UNSAVED WORK");
+    assert_eq!(e.storage.resolve_rev("feature").unwrap(), feature);
+    assert!(!e.storage.state_file("rebase").exists());
+
+    fs::write(e.storage.work_dir().join("a.txt"), "a").unwrap();
+    fs::write(e.storage.work_dir().join("s.txt"), "staged").unwrap();
+    e.storage.add_paths(vec!["s.txt".to_string()]).unwrap();
+    let err = e.storage.rebase("master", None, false, MergeOptions::default());
+    assert!(matches!(err, Err(Error::StagedChanges)));
+    assert!(Index::load(&e.storage).unwrap().entries.contains_key(Path::new("s.txt")));
+    assert_eq!(e.storage.resolve_rev("feature").unwrap(), feature);
+}
+
+#[test]
+fn rebase_refuses_to_replay_over_local_changes() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    switch(&e, "feature");
+    commit_files(&e, &[("b.txt", "b")], "add b");
+    switch(&e, "master");
+    commit_files(&e, &[("m.txt", "m")], "add m");
+    switch(&e, "feature");
+    let feature = e.storage.resolve_rev("feature").unwrap();
+    fs::write(e.storage.work_dir().join("b.txt"), "MY UNSAVED WORK").unwrap();
+
+    let err = e.storage.rebase("master", None, false, MergeOptions::default());
+    assert!(matches!(err, Err(Error::LocalChanges(paths)) if paths == vec![PathBuf::from("b.txt")]));
+    assert_eq!(read_work_file(&e, "b.txt"), "MY UNSAVED WORK");
+    assert_eq!(e.storage.resolve_rev("feature").unwrap(), feature);
+    assert_eq!(e.storage.operation_in_progress(), None);
+    assert!(!e.storage.state_file("rebase").exists());
+}
+
+#[test]
+fn rebase_stops_on_conflict_and_continues() {
+    let e = env();
+    conflicting_branches(&e);
+    let master = e.storage.resolve_rev("master").unwrap();
+    switch(&e, "feature");
+
+    let outcome = e.storage.rebase("master", None, false, MergeOptions::default()).unwrap();
+    assert!(matches!(outcome, RebaseOutcome::Conflicted { ref paths, .. } if paths == &[PathBuf::from("a.txt")]));
+    assert_eq!(e.storage.operation_in_progress(), Some(Operation::Rebase));
+    assert!(e.storage.cherry_pick("master", MergeOptions::default()).is_err());
+
+    fs::write(e.storage.work_dir().join("a.txt"), "resolved\n").unwrap();
+    e.storage.add_paths(vec!["a.txt".to_string()]).unwrap();
+    let outcome = e.storage.rebase_continue(None, MergeOptions::default()).unwrap();
+    assert!(matches!(outcome, RebaseOutcome::Rebased { .. }));
+    assert_eq!(e.storage.operation_in_progress(), None);
+    assert_eq!(history(&e), vec!["feature", "master", "base"]);
+    assert_eq!(Commit::load_by_index(0, &e.storage).unwrap().parents(), vec![master]);
+    assert_eq!(read_work_file(&e, "a.txt"), "resolved\n");
+}
+
+#[test]
+fn rebase_skip_and_abort() {
+    let e = env();
+    conflicting_branches(&e);
+    switch(&e, "feature");
+    let feature = e.storage.resolve_rev("feature").unwrap();
+
+    e.storage.rebase("master", None, false, MergeOptions::default()).unwrap();
+    e.storage.rebase_abort().unwrap();
+    assert_eq!(e.storage.operation_in_progress(), None);
+    assert_eq!(e.storage.resolve_rev("feature").unwrap(), feature);
+    assert_eq!(read_work_file(&e, "a.txt"), "feature\n");
+
+    e.storage.rebase("master", None, false, MergeOptions::default()).unwrap();
+    let outcome = e.storage.rebase_skip(MergeOptions::default()).unwrap();
+    assert!(matches!(outcome, RebaseOutcome::Rebased { .. }));
+    assert_eq!(e.storage.resolve_rev("feature").unwrap(), e.storage.resolve_rev("master").unwrap());
+    assert_eq!(read_work_file(&e, "a.txt"), "master\n");
+}
+
+#[test]
+fn rebase_onto_moves_only_the_commits_after_upstream() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    e.storage.new_branch("topic".to_string()).unwrap();
+    switch(&e, "topic");
+    commit_files(&e, &[("t.txt", "t")], "topic work");
+    e.storage.new_branch("subtopic".to_string()).unwrap();
+    switch(&e, "subtopic");
+    commit_files(&e, &[("s.txt", "s")], "subtopic work");
+
+    e.storage.rebase("topic", Some("master"), false, MergeOptions::default()).unwrap();
+    assert_eq!(history(&e), vec!["subtopic work", "base"]);
+    assert_eq!(head_files(&e), vec![PathBuf::from("a.txt"), PathBuf::from("s.txt")]);
+    assert!(!e.storage.work_dir().join("t.txt").exists());
+}
+
+#[test]
+fn rebase_autosquash_folds_fixups_into_their_target() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    switch(&e, "feature");
+    commit_files(&e, &[("b.txt", "b1")], "add b");
+    commit_files(&e, &[("c.txt", "c")], "add c");
+    commit_files(&e, &[("b.txt", "b2")], "fixup! add b");
+    commit_files(&e, &[("b.txt", "b3")], "squash! add b\n\nalso tweak b");
+
+    e.storage.rebase("master", None, true, MergeOptions::default()).unwrap();
+    assert_eq!(history(&e), vec!["add c", "add b\n\nalso tweak b", "base"]);
+    assert_eq!(read_work_file(&e, "b.txt"), "b3");
+}
+
+// ── commit --amend, -a and messages ──────────────────────────────────────────
+
+#[test]
+fn amend_replaces_head_and_keeps_its_parent() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    let base = e.storage.resolve_rev("HEAD").unwrap();
+    commit_files(&e, &[("b.txt", "b")], "tpyo");
+
+    e.storage.amend(Some("typo fixed".to_string()), None, false).unwrap();
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.message(), "typo fixed");
+    assert_eq!(head.parents(), vec![base.clone()]);
+    assert_eq!(head_files(&e), vec![PathBuf::from("a.txt"), PathBuf::from("b.txt")]);
+
+    fs::write(e.storage.work_dir().join("c.txt"), "c").unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.amend(None, None, false).unwrap();
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.message(), "typo fixed");
+    assert_eq!(head.parents(), vec![base]);
+    assert_eq!(head_files(&e).len(), 3);
+    assert!(!Index::index_file_path(&e.storage).exists());
+}
+
+#[test]
+fn stage_tracked_picks_up_modifications_and_deletions_only() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a"), ("b.txt", "b")], "base");
+    fs::write(e.storage.work_dir().join("a.txt"), "a2").unwrap();
+    fs::remove_file(e.storage.work_dir().join("b.txt")).unwrap();
+    fs::write(e.storage.work_dir().join("untracked.txt"), "u").unwrap();
+
+    e.storage.stage_tracked().unwrap();
+    e.storage.commit("update".to_string()).unwrap();
+    assert_eq!(head_files(&e), vec![PathBuf::from("a.txt")]);
+    assert_eq!(read_work_file(&e, "a.txt"), "a2");
+}
+
+#[test]
+fn message_cleanup_strips_comments_and_blank_lines() {
+    let raw = "\n\nsubject  \n# a comment\n\n\n\nbody line\n#another\n\n";
+    assert_eq!(message::cleanup(raw, true), "subject\n\nbody line");
+    assert_eq!(message::cleanup(raw, false), "subject\n# a comment\n\nbody line\n#another");
+    assert_eq!(message::cleanup("# only comments\n", true), "");
+}
+
+#[cfg(unix)]
+#[test]
+fn editor_message_is_cleaned_up() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let e = env();
+    let script = e.storage.work_dir().join("editor.sh");
+    fs::write(&script, "#!/bin/sh\nprintf 'from editor\\n# hint\\n' > \"$1\"\n").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    // SAFETY: no other test reads or writes GATO_EDITOR.
+    unsafe { std::env::set_var("GATO_EDITOR", &script) };
+
+    let message = message::edit("", &e.storage).unwrap();
+    assert_eq!(message, "from editor");
+}
+
+// ── author and committer ─────────────────────────────────────────────────────
+
+#[test]
+fn legacy_commits_report_one_identity_for_both_roles() {
+    let e = env();
+    let hash = raw_commit(&e, "legacy", &[]);
+    let commit = Commit::load(hex::encode(hash), &e.storage);
+    let author = commit.author();
+    assert_eq!((author.name.as_str(), author.timestamp, author.utc_offset), ("Tester", 0, 0));
+    assert_eq!(commit.committer(), author);
+}
+
+#[test]
+fn new_commits_record_author_and_committer() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert!(matches!(head, Commit::V2 { .. }));
+    assert_eq!(head.author().name, "Tester");
+    assert_eq!(head.committer().name, "Tester");
+
+    fs::write(e.storage.work_dir().join("b.txt"), "b").unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit_as("by someone".to_string(), Some("Someone Else <else@example.com>"), Default::default(), false).unwrap();
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.author().name, "Someone Else");
+    assert_eq!(head.author().email.as_deref(), Some("else@example.com"));
+    assert_eq!(head.committer().name, "Tester");
+
+    let original_author = head.author();
+    e.storage.amend(Some("reworded".to_string()), None, false).unwrap();
+    let amended = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(amended.author(), original_author);
+    assert_eq!(amended.committer().name, "Tester");
+}
+
+#[test]
+fn identity_variables_override_the_config() {
+    let e = env();
+    let vars = std::collections::HashMap::from([
+        ("GATO_COMMITTER_NAME", "Bot"),
+        ("GATO_COMMITTER_EMAIL", "bot@example.com"),
+        ("GATO_COMMITTER_DATE", "1700000000 +0530"),
+    ]);
+    let lookup = |name: &str| vars.get(name).map(|v| v.to_string());
+
+    let committer = Identity::from_vars(Role::Committer, &e.storage, lookup).unwrap();
+    assert_eq!(committer.name, "Bot");
+    assert_eq!((committer.timestamp, committer.utc_offset), (1_700_000_000, 330));
+    assert_eq!(committer.to_string(), "Bot <bot@example.com> 2023-11-15 03:43:20 +0530");
+
+    let author = Identity::from_vars(Role::Author, &e.storage, lookup).unwrap();
+    assert_eq!(author.name, "Tester");
+
+    let bad = |name: &str| (name == "GATO_AUTHOR_DATE").then(|| "yesterday".to_string());
+    assert!(Identity::from_vars(Role::Author, &e.storage, bad).is_err());
+}
+
+#[test]
+fn dates_parse_in_several_formats() {
+    assert_eq!(parse_date("@1700000000 -0100"), Some((1_700_000_000, -60)));
+    assert_eq!(parse_date("1700000000"), Some((1_700_000_000, 0)));
+    assert_eq!(parse_date("2023-11-15T00:13:20+02:00"), Some((1_700_000_000, 120)));
+    assert_eq!(parse_date("Tue, 14 Nov 2023 22:13:20 +0000"), Some((1_700_000_000, 0)));
+    assert_eq!(parse_date("1700000000 +5"), None);
+}
+
+#[test]
+fn cherry_pick_keeps_the_original_author() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    switch(&e, "feature");
+    fs::write(e.storage.work_dir().join("b.txt"), "b").unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit_as("contributed".to_string(), Some("Contributor <c@example.com>"), Default::default(), false).unwrap();
+    switch(&e, "master");
+
+    e.storage.cherry_pick("feature", MergeOptions::default()).unwrap();
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.author().name, "Contributor");
+    assert_eq!(head.committer().name, "Tester");
+}
+
+// ── V2 commits and octopus merges ────────────────────────────────────────────
+
+#[test]
+fn headers_upgrade_legacy_commits_to_v2() {
+    let e = env();
+    let a = raw_commit(&e, "a", &[]);
+    let b = raw_commit(&e, "b", &[&a]);
+    let c = raw_commit(&e, "c", &[&a]);
+    let mut merge = Commit::load(hex::encode(raw_commit(&e, "merge", &[&b, &c])), &e.storage);
+    let author = merge.author();
+
+    merge.set_header("encoding".to_string(), "utf-8".to_string());
+    assert!(matches!(merge, Commit::V2 { .. }));
+    assert_eq!(merge.parents(), vec![hex::encode(&b), hex::encode(&c)]);
+    assert_eq!(merge.parent_hash(), Some(hex::encode(&b)));
+    assert_eq!(merge.author(), author);
+    assert_eq!(merge.message(), "merge");
+
+    let stored = Commit::load(hex::encode(merge.put(&e.storage).unwrap()), &e.storage);
+    assert_eq!(stored.headers().get("encoding").map(String::as_str), Some("utf-8"));
+    assert!(stored.to_string().contains("encoding: utf-8"));
+}
+
+#[test]
+fn commit_as_records_trailers_as_headers() {
+    let e = env();
+    fs::write(e.storage.work_dir().join("a.txt"), "a").unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    let headers = [("Reviewed-by".to_string(), "Someone".to_string())].into();
+    e.storage.commit_as("reviewed".to_string(), None, headers, false).unwrap();
+
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.headers()["Reviewed-by"], "Someone");
+    assert_eq!(head.author().name, "Tester");
+}
+
+/// master: base ── one branch per name, each adding `<name>.txt`.
+fn octopus_branches(e: &Env, names: &[&str]) {
+    commit_files(e, &[("base.txt", "base")], "base");
+    for name in names {
+        e.storage.new_branch(name.to_string()).unwrap();
+        switch(e, name);
+        commit_files(e, &[(&format!("{name}.txt"), name)], name);
+        switch(e, "master");
+    }
+}
+
+#[test]
+fn octopus_merge_records_one_parent_per_branch() {
+    let e = env();
+    octopus_branches(&e, &["one", "two", "three"]);
+    let master = e.storage.resolve_rev("master").unwrap();
+
+    let targets = vec!["one".to_string(), "two".to_string(), "three".to_string()];
+    let outcome = e.storage.octopus_merge(targets.clone(), None, MergeOptions::default()).unwrap();
+    assert!(matches!(outcome, MergeOutcome::Merged { .. }));
+
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    let expected: Vec<String> = std::iter::once(master)
+        .chain(targets.iter().map(|t| e.storage.resolve_rev(t).unwrap()))
+        .collect();
+    assert_eq!(head.parents(), expected);
+    assert_eq!(head.message(), "Merge branches 'one', 'two' and 'three'");
+    assert_eq!(head_files(&e).len(), 4);
+    assert_eq!(read_work_file(&e, "three.txt"), "three");
+
+    let again = e.storage.octopus_merge(targets, None, MergeOptions::default()).unwrap();
+    assert_eq!(again, MergeOutcome::UpToDate);
+}
+
+#[test]
+fn octopus_merge_refuses_conflicts_without_changes() {
+    let e = env();
+    octopus_branches(&e, &["one", "two"]);
+    switch(&e, "two");
+    commit_files(&e, &[("base.txt", "changed on two")], "two edits base");
+    switch(&e, "master");
+    commit_files(&e, &[("base.txt", "changed on master")], "master edits base");
+    let master = e.storage.resolve_rev("master").unwrap();
+
+    let targets = vec!["one".to_string(), "two".to_string()];
+    let err = e.storage.octopus_merge(targets, None, MergeOptions::default()).unwrap_err();
+    assert!(err.to_string().contains("base.txt"));
+    assert_eq!(e.storage.resolve_rev("master").unwrap(), master);
+    assert_eq!(e.storage.operation_in_progress(), None);
+    assert!(!e.storage.work_dir().join("one.txt").exists());
+}
+
+#[test]
+fn octopus_merge_refuses_to_overwrite_untracked_files() {
+    let e = env();
+    octopus_branches(&e, &["one", "two"]);
+    fs::write(e.storage.work_dir().join("two.txt"), "untracked").unwrap();
+    let master = e.storage.resolve_rev("master").unwrap();
+
+    let targets = vec!["one".to_string(), "two".to_string()];
+    let err = e.storage.octopus_merge(targets, None, MergeOptions::default());
+    assert!(matches!(err, Err(Error::LocalChanges(paths)) if paths == vec![PathBuf::from("two.txt")]));
+    assert_eq!(e.storage.resolve_rev("master").unwrap(), master);
+    assert_eq!(read_work_file(&e, "two.txt"), "untracked");
+}
+
+// ── signing ──────────────────────────────────────────────────────────────────
+
+/// Generate a key in the store and point `gato.toml` at it, trusting it as
+/// `tester@example.com`. Returns the public key in hex.
+fn configure_signing(e: &Env) -> String {
+    let key_path = e.storage.root_path.join("signing_key");
+    let public = signature::key_hex(&signature::generate_key(&key_path).unwrap());
+    let signers = e.storage.root_path.join("allowed_signers");
+    fs::write(&signers, format!("# trusted keys\ntester@example.com {public}\n")).unwrap();
+    let config = e.storage.work_dir().join("gato.toml");
+    let mut text = fs::read_to_string(&config).unwrap();
+    text.push_str(&format!(
+        "\n[signing]\nkey = {:?}\nallowed_signers = {:?}\n",
+        key_path, signers
+    ));
+    fs::write(config, text).unwrap();
+    public
+}
+
+#[test]
+fn signed_commits_verify_against_allowed_signers() {
+    let e = env();
+    let public = configure_signing(&e);
+    fs::write(e.storage.work_dir().join("a.txt"), "a").unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit_as("signed".to_string(), None, Default::default(), true).unwrap();
+
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    let allowed = signature::allowed_signers(&e.storage).unwrap();
+    assert_eq!(
+        head.verify_signature(&allowed),
+        Verification::Good { principal: "tester@example.com".to_string(), key: public.clone() }
+    );
+    assert_eq!(head.verify_signature(&[]), Verification::Untrusted { key: public });
+
+    let mut forged = head.clone();
+    if let Commit::V2 { message, .. } = &mut forged {
+        *message = "forged".to_string();
+    }
+    assert_eq!(forged.verify_signature(&allowed), Verification::Bad);
+}
+
+#[test]
+fn rewriting_a_commit_drops_its_signature() {
+    let e = env();
+    configure_signing(&e);
+    fs::write(e.storage.work_dir().join("a.txt"), "a").unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit_as("signed".to_string(), None, Default::default(), true).unwrap();
+    let allowed = signature::allowed_signers(&e.storage).unwrap();
+
+    e.storage.amend(Some("reworded".to_string()), None, false).unwrap();
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert_eq!(head.verify_signature(&allowed), Verification::Unsigned);
+
+    e.storage.amend(None, None, true).unwrap();
+    let head = Commit::load_by_index(0, &e.storage).unwrap();
+    assert!(head.verify_signature(&allowed).is_good());
+    assert_eq!(head.message(), "reworded");
+}
+
+#[test]
+fn signing_needs_a_configured_key() {
+    let e = env();
+    fs::write(e.storage.work_dir().join("a.txt"), "a").unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    let err = e.storage.commit_as("signed".to_string(), None, Default::default(), true);
+    assert!(matches!(err, Err(Error::NoSigningKey)));
+    assert!(Commit::load_by_index(0, &e.storage).is_none(), "nothing is committed");
+}
+
+#[test]
+fn signing_key_can_come_from_the_global_config() {
+    let e = env();
+    let public = signature::key_hex(
+        &signature::generate_key(&e.storage.root_path.join("global_key")).unwrap(),
+    );
+    fs::write(e.storage.root_path.join("config.toml"), "[signing]\nkey = \"global_key\"\n").unwrap();
+    assert_eq!(signature::key_hex(&signature::signing_key(&e.storage).unwrap().verifying_key()), public);
+}
+
+#[test]
+fn allowed_signers_reject_malformed_lines() {
+    assert!(signature::parse_allowed_signers("# nothing\n\n").unwrap().is_empty());
+    assert!(matches!(
+        signature::parse_allowed_signers("someone not-a-key"),
+        Err(Error::InvalidAllowedSigner(_))
+    ));
+}
+
+// ── commit-graph file ────────────────────────────────────────────────────────
+
+#[test]
+fn commits_are_recorded_in_the_commit_graph_file() {
+    let e = env();
+    feature_ahead_of_master(&e);
+    e.storage.merge("feature".to_string(), None, FastForward::Never, MergeOptions::default()).unwrap();
+
+    let file = GraphFile::open(&e.storage).expect("written by the first commit");
+    let entries = file.entries();
+    assert_eq!(entries.len(), 3);
+    let head = e.storage.resolve_rev("HEAD").unwrap();
+    let commit = Commit::load(head.clone(), &e.storage);
+    let entry = file.get(&head).unwrap();
+    assert_eq!(entry.parents, commit.parents());
+    assert_eq!(entry.tree_hash, commit.tree_hash());
+    assert_eq!(entry.timestamp, commit.committer().timestamp);
+    assert_eq!(entry.generation, 3);
+    assert_eq!(CommitGraph::new(&e.storage).generation(&head), 3);
+}
+
+#[test]
+fn commit_graph_falls_back_to_objects_for_unknown_commits() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "base");
+    let base = hex::decode(e.storage.resolve_rev("HEAD").unwrap()).unwrap();
+    // stored without going through `save`, so the graph file does not know them
+    let left = raw_commit(&e, "left", &[&base]);
+    let right = raw_commit(&e, "right", &[&base]);
+    let file = GraphFile::open(&e.storage).unwrap();
+    assert!(!file.contains(&hex::encode(&left)));
+
+    let mut graph = CommitGraph::new(&e.storage);
+    assert_eq!(graph.merge_bases(&[hex::encode(&left)], &hex::encode(&right)), vec![hex::encode(&base)]);
+    assert_eq!(graph.generation(&hex::encode(&left)), 2);
+}
+
+#[test]
+fn corrupt_commit_graph_files_are_ignored() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    commit_files(&e, &[("b.txt", "b")], "two");
+    let path = GraphFile::path(&e.storage);
+    let data = fs::read(&path).unwrap();
+    fs::write(&path, &data[..data.len() - 1]).unwrap();
+
+    assert!(GraphFile::open(&e.storage).is_none());
+    assert_eq!(history(&e), vec!["two", "one"]);
+    commit_files(&e, &[("c.txt", "c")], "three");
+    assert_eq!(GraphFile::open(&e.storage).unwrap().entries().len(), 3, "rebuilt on commit");
+}
+
+#[test]
+fn new_commits_are_appended_to_the_commit_graph_tail() {
+    let e = env();
+    for n in 0..9 {
+        commit_files(&e, &[("a.txt", &n.to_string())], &format!("commit {n}"));
+    }
+    let head = e.storage.resolve_rev("HEAD").unwrap();
+    let file = GraphFile::open(&e.storage).unwrap();
+    assert_eq!(file.entries().len(), 9);
+    assert_eq!(file.generation(&head), Some(9));
+
+    // a torn append only loses the commits from there on
+    let tail = GraphFile::tail_path(&e.storage);
+    let data = fs::read(&tail).unwrap();
+    fs::write(&tail, &data[..data.len() - 1]).unwrap();
+    let file = GraphFile::open(&e.storage).unwrap();
+    assert!(!file.contains(&head));
+    assert_eq!(CommitGraph::new(&e.storage).generation(&head), 9);
+
+    assert_eq!(GraphFile::rebuild(&e.storage, &[]).unwrap(), 9);
+    assert!(!tail.exists());
+    assert!(GraphFile::open(&e.storage).unwrap().contains(&head));
+}
+
+#[test]
+fn commit_graph_files_failing_their_checksum_are_ignored() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let path = GraphFile::path(&e.storage);
+    let mut data = fs::read(&path).unwrap();
+    data[20] ^= 1;
+    fs::write(&path, &data).unwrap();
+
+    assert!(GraphFile::open(&e.storage).is_none());
+    assert_eq!(history(&e), vec!["one"]);
+}
+
+#[test]
+fn rebuilding_the_commit_graph_drops_unreachable_commits() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    commit_files(&e, &[("b.txt", "b")], "two");
+    e.storage.amend(Some("two, reworded".to_string()), None, false).unwrap();
+    assert_eq!(GraphFile::open(&e.storage).unwrap().entries().len(), 3);
+
+    assert_eq!(GraphFile::rebuild(&e.storage, &[]).unwrap(), 2);
+    let entries = GraphFile::open(&e.storage).unwrap().entries();
+    assert!(entries.contains_key(&e.storage.resolve_rev("HEAD").unwrap()));
+}
+
+// ── reflog ───────────────────────────────────────────────────────────────────
+
+fn reasons(e: &Env, ref_name: &str) -> Vec<String> {
+    e.storage.reflog(ref_name).unwrap().into_iter().map(|entry| entry.reason).collect()
+}
+
+#[test]
+fn reflog_records_commits_amends_and_resets() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    commit_files(&e, &[("b.txt", "b")], "two");
+    let two = e.storage.resolve_rev("HEAD").unwrap();
+    e.storage.amend(Some("two, reworded".to_string()), None, false).unwrap();
+    e.storage.soft_reset(1);
+
+    assert_eq!(
+        reasons(&e, "HEAD")[..3],
+        ["reset: moving to ".to_string() + &e.storage.resolve_rev("HEAD").unwrap(),
+         "commit (amend): two, reworded".to_string(),
+         "commit: two".to_string()]
+    );
+    assert_eq!(reasons(&e, "master").last().unwrap(), "commit (initial): one");
+    assert_eq!(e.storage.resolve_rev("HEAD@{2}").unwrap(), two);
+    assert_eq!(e.storage.resolve_rev("@{2}").unwrap(), two);
+    assert_eq!(e.storage.resolve_rev("master@{0}").unwrap(), e.storage.resolve_rev("HEAD").unwrap());
+    assert!(matches!(e.storage.resolve_rev("HEAD@{9}"), Err(Error::UnknownRevision(_))));
+}
+
+#[test]
+fn reflog_survives_branch_deletion() {
+    let e = env();
+    feature_ahead_of_master(&e);
+    let feature = e.storage.resolve_rev("feature").unwrap();
+    e.storage.delete_branch("feature".to_string()).unwrap();
+
+    assert_eq!(reasons(&e, "feature")[0], "branch: deleted");
+    assert!(e.storage.resolve_rev("feature@{0}").is_err());
+    assert_eq!(e.storage.resolve_rev("feature@{1}").unwrap(), feature);
+    assert!(e.storage.reflog_roots(90).unwrap().contains(&feature));
+    assert!(reasons(&e, "HEAD").contains(&"checkout: moving from feature to master".to_string()));
+}
+
+#[test]
+fn reflog_expire_drops_old_entries() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let one = e.storage.resolve_rev("HEAD").unwrap();
+    let log = e.storage.repo_path().join("logs").join("HEAD");
+    let mut old: ReflogEntry = fs::read_to_string(&log).unwrap().lines().next().unwrap().parse().unwrap();
+    old.identity.timestamp -= 100 * 24 * 60 * 60;
+    old.reason = "ancient".to_string();
+    fs::write(&log, format!("{old}\n{}", fs::read_to_string(&log).unwrap())).unwrap();
+    assert_eq!(reasons(&e, "HEAD"), vec!["commit (initial): one", "ancient"]);
+
+    assert_eq!(e.storage.reflog_expire(90).unwrap(), 1);
+    assert_eq!(reasons(&e, "HEAD"), vec!["commit (initial): one"]);
+    assert_eq!(e.storage.reflog_roots(90).unwrap(), vec![one]);
+}
+
+#[test]
+fn malformed_reflog_lines_are_skipped() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let one = e.storage.resolve_rev("HEAD").unwrap();
+    let log = e.storage.repo_path().join("logs").join("HEAD");
+    let nameless = format!("{} {}  <> 1700000000 +0000\tnameless", "0".repeat(64), one);
+    fs::write(&log, format!("garbage\n{nameless}\n{}", fs::read_to_string(&log).unwrap())).unwrap();
+
+    assert_eq!(reasons(&e, "HEAD"), vec!["commit (initial): one"]);
+    assert_eq!(e.storage.resolve_rev("HEAD@{0}").unwrap(), one);
+    assert_eq!(e.storage.reflog_roots(90).unwrap(), vec![one]);
+}
+
+#[test]
+fn failed_ref_updates_are_not_logged() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let one = e.storage.resolve_rev("HEAD").unwrap();
+    fs::create_dir_all(e.storage.get_branch_path("blocked".to_string()).join("in-the-way")).unwrap();
+
+    assert!(e.storage.update_ref("blocked".to_string(), hex::decode(&one).unwrap(), "never happened").is_err());
+    assert!(e.storage.reflog("blocked").unwrap().is_empty());
+}
+
+#[test]
+fn reflog_entries_round_trip() {
+    let line = format!("{} {} A U Thor <a@example.com> 1700000000 -0130\tcommit: x y", "0".repeat(64), "ab".repeat(32));
+    let entry: ReflogEntry = line.parse().unwrap();
+    assert_eq!(entry.old, None);
+    assert_eq!(entry.new, Some("ab".repeat(32)));
+    assert_eq!(entry.identity.name, "A U Thor");
+    assert_eq!(entry.identity.utc_offset, -90);
+    assert_eq!(entry.to_string(), line);
+    assert!(matches!("garbage".parse::<ReflogEntry>(), Err(Error::InvalidReflogLine(_))));
+}
+
+// ── locking ──────────────────────────────────────────────────────────────────
+
+#[test]
+fn held_ref_lock_blocks_commits_until_released() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let one = e.storage.resolve_rev("HEAD").unwrap();
+    let lock = e.storage.get_branch_path("master".to_string()).with_file_name("master.lock");
+    fs::write(&lock, "").unwrap();
+
+    fs::write(e.storage.work_dir().join("b.txt"), "b").unwrap();
+    e.storage.add_paths(vec!["b.txt".to_string()]).unwrap();
+    let err = e.storage.commit("two".to_string()).unwrap_err();
+    assert!(err.to_string().contains("master.lock"), "{err}");
+    assert_eq!(e.storage.resolve_rev("HEAD").unwrap(), one);
+    assert_eq!(e.storage.list_branchs().unwrap(), vec!["master"]);
+
+    fs::remove_file(&lock).unwrap();
+    e.storage.commit("two".to_string()).unwrap();
+    assert_ne!(e.storage.resolve_rev("HEAD").unwrap(), one);
+    assert!(!lock.exists());
+}
+
+#[test]
+fn ref_updates_check_the_expected_old_value() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let one = e.storage.resolve_rev("HEAD").unwrap();
+    commit_files(&e, &[("b.txt", "b")], "two");
+    let two = e.storage.resolve_rev("HEAD").unwrap();
+
+    let err = e.storage.update_ref_from("master".to_string(), Some(&one), vec![0u8; 32], "x").unwrap_err();
+    assert!(matches!(err, StorageError::RefChanged { ref actual, .. } if actual.as_deref() == Some(two.as_str())));
+    assert_eq!(e.storage.resolve_rev("HEAD").unwrap(), two);
+    assert!(e.storage.update_ref_from("fresh".to_string(), None, hex::decode(&one).unwrap(), "x").is_ok());
+    assert!(e.storage.update_ref_from("fresh".to_string(), None, hex::decode(&two).unwrap(), "x").is_err());
+
+    // a commit made from a branch tip that has since moved is refused
+    fs::write(e.storage.work_dir().join("c.txt"), "c").unwrap();
+    e.storage.add_paths(vec!["c.txt".to_string()]).unwrap();
+    let commit = Commit::new("racing".to_string(), &e.storage).unwrap();
+    e.storage.update_ref("master".to_string(), hex::decode(&one).unwrap(), "moved meanwhile").unwrap();
+    assert!(commit.save(&e.storage).is_err());
+    assert_eq!(e.storage.resolve_rev("HEAD").unwrap(), one);
+}
+
+#[test]
+fn index_and_head_writes_take_their_locks() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let index_lock = e.storage.repo_path().join("index.lock");
+    fs::write(&index_lock, "").unwrap();
+    fs::write(e.storage.work_dir().join("b.txt"), "b").unwrap();
+    let err = e.storage.add_paths(vec!["b.txt".to_string()]).unwrap_err();
+    assert!(err.to_string().contains("index.lock"), "{err}");
+    fs::remove_file(&index_lock).unwrap();
+
+    e.storage.new_branch("feature".to_string()).unwrap();
+    fs::write(e.storage.repo_path().join("HEAD.lock"), "").unwrap();
+    assert!(matches!(e.storage.change_branch("feature".to_string()), Err(StorageError::Locked(_))));
+    assert_eq!(e.storage.get_active_branche(), "master");
+}
+
+#[test]
+fn branches_are_created_and_deleted_under_their_locks() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    e.storage.new_branch("feature".to_string()).unwrap();
+    commit_files(&e, &[("b.txt", "b")], "two");
+    let feature = e.storage.resolve_rev("feature").unwrap();
+
+    let err = e.storage.new_branch("feature".to_string()).unwrap_err();
+    assert!(matches!(err, StorageError::BranchExists(ref name) if name == "feature"));
+    assert_eq!(e.storage.resolve_rev("feature").unwrap(), feature);
+
+    let lock = e.storage.get_branch_path("feature".to_string()).with_file_name("feature.lock");
+    fs::write(&lock, "").unwrap();
+    assert!(e.storage.delete_branch("feature".to_string()).is_err());
+    assert_eq!(e.storage.resolve_rev("feature").unwrap(), feature);
+    fs::remove_file(&lock).unwrap();
+    e.storage.delete_branch("feature".to_string()).unwrap();
+    assert_eq!(e.storage.list_branchs().unwrap(), vec!["master"]);
+    assert!(!lock.exists());
+}
+
+#[test]
+fn failed_lock_commits_release_the_lock() {
+    let dir = TempDir::new().unwrap();
+    let target = dir.path().join("ref");
+    fs::create_dir_all(target.join("in-the-way")).unwrap();
+
+    let lock = LockFile::acquire(&target).unwrap();
+    assert!(lock.commit(b"data").is_err());
+    assert!(!lock_path(&target).exists());
+    assert!(LockFile::acquire(&target).is_ok());
+}
+
+// ── durable objects ──────────────────────────────────────────────────────────
+
+fn object_files(e: &Env) -> Vec<String> {
+    let mut names = Vec::new();
+    for dir in fs::read_dir(e.storage.root_path.join("objects")).unwrap() {
+        for file in fs::read_dir(dir.unwrap().path()).unwrap() {
+            names.push(file.unwrap().file_name().to_string_lossy().into_owned());
+        }
+    }
+    names
+}
+
+#[test]
+fn empty_objects_count_as_missing_and_are_rewritten() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "hello")], "one");
+    assert!(object_files(&e).iter().all(|name| !name.starts_with(".tmp-")));
+
+    let hash = hex::encode(compute_hash(b"hello"));
+    fs::write(e.storage.objects_path(&hash), "").unwrap();
+    assert!(!e.storage.exist(&hash));
+    assert!(e.storage.list_files().unwrap().contains(&hash));
+
+    e.storage.add_paths(vec!["a.txt".to_string()]).unwrap();
+    assert!(e.storage.exist(&hash));
+    assert!(gato::core::storage::objects::is_intact(&hash, &e.storage.get(&hash).unwrap()));
+}
+
+#[test]
+fn recovery_finds_truncated_objects_and_stale_writes() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "some content"), ("dir/b.txt", "more")], "one");
+    let report = e.storage.recover_objects(false).unwrap();
+    assert!(report.is_ok(), "{report}");
+    assert_eq!(report.checked, 5);
+
+    let blob = hex::encode(compute_hash(b"some content"));
+    let commit = e.storage.resolve_rev("HEAD").unwrap();
+    let data = e.storage.get(&commit).unwrap();
+    fs::write(e.storage.objects_path(&commit), &data[..data.len() / 2]).unwrap();
+    let data = e.storage.get(&blob).unwrap();
+    fs::write(e.storage.objects_path(&blob), &data[..data.len() - 3]).unwrap();
+
+    let dir = e.storage.objects_path(&blob).parent().unwrap().to_path_buf();
+    let stale = dir.join(".tmp-stale-1-0");
+    let fresh = dir.join(".tmp-fresh-1-1");
+    fs::write(&stale, "partial").unwrap();
+    fs::write(&fresh, "partial").unwrap();
+    let two_hours_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 60 * 60);
+    fs::File::options().write(true).open(&stale).unwrap().set_modified(two_hours_ago).unwrap();
+
+    let mut damaged = vec![blob.clone(), commit.clone()];
+    damaged.sort();
+    let report = e.storage.recover_objects(false).unwrap();
+    assert_eq!(report.damaged, damaged);
+    assert_eq!(report.stale_temp_files, 1);
+    assert!(!stale.exists() && fresh.exists());
+    assert!(e.storage.objects_path(&blob).exists());
+
+    let report = e.storage.recover_objects(true).unwrap();
+    assert_eq!(report.damaged, damaged);
+    assert!(!e.storage.objects_path(&blob).exists());
+    assert!(e.storage.recover_objects(false).unwrap().is_ok());
+}
+
+#[test]
+fn fsync_policy_comes_from_the_repo_or_the_store() {
+    let e = env();
+    assert_eq!(e.storage.fsync_policy(), FsyncPolicy::Objects);
+
+    fs::write(e.storage.root_path.join("config.toml"), "[storage]\nfsync = \"never\"\n").unwrap();
+    let reload = || LocalStorage::new(e.storage.root_path.clone(), e.storage.repo_id().to_string(), e.storage.work_dir().clone());
+    assert_eq!(reload().fsync_policy(), FsyncPolicy::Never);
+
+    let config = e.storage.work_dir().join("gato.toml");
+    let text = fs::read_to_string(&config).unwrap() + "\n[storage]\nfsync = \"always\"\n";
+    fs::write(&config, text).unwrap();
+    let storage = reload();
+    assert_eq!(storage.fsync_policy(), FsyncPolicy::Always);
+    commit_files(&Env { storage, ..e }, &[("a.txt", "a")], "synced");
+}
+
+// ── gc ───────────────────────────────────────────────────────────────────────
+
+fn age_objects(e: &Env, hours: u64) {
+    let then = std::time::SystemTime::now() - std::time::Duration::from_secs(hours * 60 * 60);
+    for hash in e.storage.list_files().unwrap() {
+        let file = fs::File::options().write(true).open(e.storage.objects_path(&hash)).unwrap();
+        file.set_modified(then).unwrap();
+    }
+}
+
+fn orphan(e: &Env, content: &[u8]) -> String {
+    let hash = hex::encode(compute_hash(content));
+    e.storage.put(&hash, content.to_vec()).unwrap();
+    hash
+}
+
+#[test]
+fn gc_keeps_staged_objects_and_removes_orphans() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    fs::write(e.storage.work_dir().join("b.txt"), "staged only").unwrap();
+    e.storage.add_paths(vec!["b.txt".to_string()]).unwrap();
+    let staged = hex::encode(compute_hash(b"staged only"));
+    let orphan = orphan(&e, b"nobody needs me");
+    age_objects(&e, 2);
+
+    let report = e.storage.gc(GcOptions::default()).unwrap();
+    assert_eq!(report.removed, vec![orphan.clone()]);
+    assert_eq!(report.bytes, "nobody needs me".len() as u64);
+    assert!(e.storage.exist(&staged));
+    assert!(!e.storage.exist(&orphan));
+    assert!(!e.storage.root_path.join("gc.lock").exists());
+    e.storage.commit("two".to_string()).unwrap();
+    assert!(FsckReport::run(&e.storage).unwrap().is_ok());
+}
+
+#[test]
+fn gc_spares_objects_younger_than_the_grace_period() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let orphan = orphan(&e, b"just written");
+
+    let report = e.storage.gc(GcOptions::default()).unwrap();
+    assert!(report.removed.is_empty());
+    assert_eq!(report.recent, 1);
+
+    fs::write(e.storage.root_path.join("config.toml"), "[gc]\ngrace_period_minutes = 0\n").unwrap();
+    let report = e.storage.gc(GcOptions::default()).unwrap();
+    assert_eq!(report.removed, vec![orphan]);
+}
+
+#[test]
+fn gc_dry_run_reports_without_deleting() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let orphan = orphan(&e, b"reclaimable");
+    let options = GcOptions { dry_run: true, grace_period: Some(std::time::Duration::ZERO) };
+
+    fs::write(e.storage.root_path.join("gc.lock"), "").unwrap();
+    let report = e.storage.gc(options).unwrap();
+    assert_eq!(report.removed, vec![orphan.clone()]);
+    assert_eq!(report.bytes, 11);
+    assert!(report.to_string().contains("would reclaim 11 bytes"));
+    assert!(e.storage.exist(&orphan));
+
+    let err = e.storage.gc(GcOptions { dry_run: false, ..options }).unwrap_err();
+    assert!(err.to_string().contains("gc.lock"), "{err}");
+    assert!(e.storage.exist(&orphan));
+}
+
+#[test]
+fn gc_keeps_commits_reachable_from_the_reflog_in_the_commit_graph() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    commit_files(&e, &[("b.txt", "b")], "two");
+    let amended = e.storage.resolve_rev("HEAD").unwrap();
+    e.storage.amend(Some("two, reworded".to_string()), None, false).unwrap();
+
+    e.storage.gc(GcOptions::default()).unwrap();
+    assert!(e.storage.exist(&amended));
+    let entries = GraphFile::open(&e.storage).unwrap().entries();
+    assert!(entries.contains_key(&amended));
+    assert_eq!(entries.len(), 3);
+}
+
+#[test]
+fn adding_existing_content_refreshes_its_object() {
+    let e = env();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    age_objects(&e, 2);
+    let hash = hex::encode(compute_hash(b"a"));
+    e.storage.add_paths(vec!["a.txt".to_string()]).unwrap();
+    let modified = fs::metadata(e.storage.objects_path(&hash)).unwrap().modified().unwrap();
+    assert!(modified.elapsed().unwrap() < std::time::Duration::from_secs(60));
+}
+
+#[test]
+fn gc_marks_every_parent_of_merges_once() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    feature_ahead_of_master(&e);
+    let feature = e.storage.resolve_rev("feature").unwrap();
+    e.storage.merge("feature".to_string(), None, FastForward::Never, MergeOptions::default()).unwrap();
+    e.storage.delete_branch("feature".to_string()).unwrap();
+    fs::remove_dir_all(e.storage.repo_path().join("logs")).unwrap();
+    let orphan = orphan(&e, b"unused");
+    age_objects(&e, 2);
+
+    let report = e.storage.gc(GcOptions::default()).unwrap();
+    assert_eq!(report.removed, vec![orphan]);
+    assert_eq!(report.commits, 3);
+    assert_eq!(report.scanned, report.marked + 1);
+    assert!(report.to_string().contains("marked"));
+    assert!(e.storage.exist(&feature));
+    assert!(FsckReport::run(&e.storage).unwrap().is_ok());
+}
+
+#[test]
+fn moved_repos_keep_their_history_until_relocated() {
+    use gato::core::storage::registry::RepoState;
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let head = e.storage.resolve_rev("HEAD").unwrap();
+    let old_path = e.storage.work_dir().canonicalize().unwrap();
+    let moved = TempDir::new().unwrap();
+    let new_path = moved.path().join("moved");
+    fs::rename(e.storage.work_dir(), &new_path).unwrap();
+    age_objects(&e, 2);
+
+    let repos = e.storage.list_repos().unwrap();
+    assert_eq!(repos.len(), 1);
+    assert_eq!(repos[0].state, RepoState::Missing);
+    let (orphan, _) = &e.storage.repo_storages().unwrap()[0];
+    assert!(orphan.work_dir().as_os_str().is_empty());
+    assert_eq!(orphan.worktree(), None);
+    let report = e.storage.gc(GcOptions::default()).unwrap();
+    assert!(report.removed.is_empty());
+    assert_eq!(report.unreachable, vec![e.storage.repo_id().to_string()]);
+    assert!(e.storage.exist(&head));
+
+    let relocated = LocalStorage::load_from(e.storage.root_path.clone(), new_path.clone()).unwrap();
+    assert_eq!(relocated.relocate().unwrap(), Some(old_path));
+    assert_eq!(relocated.relocate().unwrap(), None);
+    let repos = relocated.list_repos().unwrap();
+    assert_eq!(repos[0].path, Some(new_path.canonicalize().unwrap()));
+    assert!(repos[0].is_present());
+    assert!(relocated.gc(GcOptions::default()).unwrap().unreachable.is_empty());
+}
+
+#[test]
+fn list_repos_reads_the_old_registry_and_finds_unregistered_repos() {
+    use gato::core::storage::registry::RepoState;
+    let e = env();
+    let path = e.storage.work_dir().canonicalize().unwrap();
+    let paths: std::collections::HashSet<PathBuf> = [path.clone()].into();
+    let old = bincode::encode_to_vec(paths, bincode::config::standard()).unwrap();
+    fs::write(e.storage.root_path.join("repos"), old).unwrap();
+    let other = LocalStorage::in_store(e.storage.root_path.clone(), "other".to_string());
+    other.setup().unwrap();
+
+    let repos = e.storage.list_repos().unwrap();
+    assert_eq!(repos.len(), 2);
+    assert_eq!(repos[0].id, e.storage.repo_id());
+    assert_eq!(repos[0].path, Some(path));
+    assert!(repos[0].is_present());
+    assert_eq!(repos[1].id, "other");
+    assert_eq!(repos[1].state, RepoState::Unregistered);
+
+    e.storage.push_to_repos().unwrap();
+    assert!(fs::read(e.storage.root_path.join("repos")).unwrap().starts_with(b"GRG1"));
+    assert_eq!(e.storage.list_repos().unwrap().len(), 2);
+}
+
+#[test]
+fn stats_count_deduplicated_and_shared_chunks() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    commit_files(&e, &[("a.txt", "hello"), ("b.txt", "hello")], "one");
+    let stats = e.storage.stats(10).unwrap();
+    let counts = &stats.repos[0].counts;
+    assert_eq!((counts.commits, counts.blobs, counts.chunks), (1, 1, 0));
+    assert_eq!((counts.logical_bytes, counts.content_bytes), (10, 5));
+    assert_eq!(counts.dedup_ratio(), 2.0);
+
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut big: Vec<u8> = (0..9 * 1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    fs::write(e.storage.work_dir().join("big.bin"), &big).unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit("big".to_string()).unwrap();
+    let other_work = TempDir::new().unwrap();
+    write_config(other_work.path(), "other");
+    fs::write(other_work.path().join("copy.bin"), &big).unwrap();
+    let other = LocalStorage::new(e.storage.root_path.clone(), "other".to_string(), other_work.path().to_path_buf());
+    other.setup().unwrap();
+    other.push_to_repos().unwrap();
+    other.add_paths(vec![".".to_string()]).unwrap();
+    other.commit("copy".to_string()).unwrap();
+    big[4 * 1024 * 1024] ^= 0xff;
+    fs::write(e.storage.work_dir().join("big.bin"), &big).unwrap();
+    e.storage.add_paths(vec![".".to_string()]).unwrap();
+    e.storage.commit("change".to_string()).unwrap();
+
+    let stats = e.storage.stats(1).unwrap();
+    assert_eq!(stats.repos.len(), 2);
+    let reuse = &stats.chunk_reuse;
+    assert!(reuse.across_files > 0 && reuse.across_commits > 0 && reuse.across_repos > 0, "{reuse:?}");
+    assert!(reuse.saved_bytes > 0);
+    let chunks: usize = stats.repos.iter().map(|repo| repo.counts.chunks).sum();
+    assert!(stats.total.chunks < chunks);
+    assert_eq!(stats.total.commits, 4);
+    assert!(stats.total.dedup_ratio() > 1.0);
+    assert_eq!(stats.largest.len(), 1);
+    assert_eq!(stats.largest[0].path, PathBuf::from("big.bin"));
+    assert_eq!(stats.largest[0].versions, 2);
+    assert!(stats.to_string().contains("chunks shared by files"));
+}
+
+#[test]
+fn clone_makes_an_independent_repo_with_the_same_history() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    feature_ahead_of_master(&e);
+    let master = e.storage.resolve_rev("master").unwrap();
+    let feature = e.storage.resolve_rev("feature").unwrap();
+    let dest = TempDir::new().unwrap();
+    let dest = dest.path().join("clone");
+
+    let clone = e.storage.clone_repo(&dest).unwrap();
+    assert_ne!(clone.repo_id(), e.storage.repo_id());
+    assert_eq!(clone.resolve_rev("HEAD").unwrap(), master);
+    assert_eq!(clone.resolve_rev("feature").unwrap(), feature);
+    assert_eq!(fs::read_to_string(dest.join("a.txt")).unwrap(), "a");
+    assert!(!dest.join("b.txt").exists());
+    let config = gato::core::config::load::load_config(&dest).unwrap();
+    let origin = &config.remotes.unwrap()["origin"];
+    assert_eq!(origin.id, e.storage.repo_id());
+    assert_eq!(origin.path, e.storage.work_dir().canonicalize().unwrap());
+    assert_eq!(clone.reflog("master").unwrap().len(), 1);
+
+    fs::write(dest.join("c.txt"), "c").unwrap();
+    clone.add_paths(vec![".".to_string()]).unwrap();
+    clone.commit("in the clone".to_string()).unwrap();
+    assert_eq!(e.storage.resolve_rev("master").unwrap(), master);
+    assert!(e.storage.list_repos().unwrap().iter().all(|repo| repo.is_present()));
+
+    let err = e.storage.clone_repo(&dest).unwrap_err();
+    assert!(matches!(err, Error::TargetNotEmpty(_)), "{err}");
+}
+
+#[test]
+fn worktrees_have_their_own_head_and_index() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    feature_ahead_of_master(&e);
+    let master = e.storage.resolve_rev("master").unwrap();
+    let dest = TempDir::new().unwrap();
+    let path = dest.path().join("feature");
+
+    let wt = e.storage.worktree_add(&path, "feature").unwrap();
+    assert_eq!(wt.worktree(), Some("feature"));
+    assert_eq!(wt.get_active_branche(), "feature");
+    assert_eq!(e.storage.get_active_branche(), "master");
+    assert_eq!(fs::read_to_string(path.join("b.txt")).unwrap(), "b");
+    let reopened = LocalStorage::load_from(e.storage.root_path.clone(), path.clone()).unwrap();
+    assert_eq!(reopened.repo_id(), e.storage.repo_id());
+    assert_eq!(reopened.get_active_branche(), "feature");
+
+    fs::write(path.join("c.txt"), "c").unwrap();
+    wt.add_paths(vec!["c.txt".to_string()]).unwrap();
+    assert!(Index::load(&wt).is_ok());
+    assert!(Index::load(&e.storage).is_err());
+    wt.commit("in the worktree".to_string()).unwrap();
+    assert_eq!(e.storage.resolve_rev("master").unwrap(), master);
+    assert_eq!(e.storage.resolve_rev("feature").unwrap(), wt.resolve_rev("HEAD").unwrap());
+    assert_eq!(wt.reflog("HEAD").unwrap().len(), 2);
+    assert!(e.storage.reflog("HEAD").unwrap().iter().all(|entry| !entry.reason.contains("worktree")));
+
+    let worktrees = e.storage.worktrees().unwrap();
+    assert_eq!(worktrees.len(), 2);
+    assert_eq!((worktrees[0].name.as_deref(), worktrees[0].branch.as_str()), (None, "master"));
+    assert_eq!(worktrees[1].path, path.canonicalize().unwrap());
+}
+
+#[test]
+fn a_branch_is_checked_out_in_one_worktree_at_a_time() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    feature_ahead_of_master(&e);
+    let dest = TempDir::new().unwrap();
+    let wt = e.storage.worktree_add(&dest.path().join("feature"), "feature").unwrap();
+
+    let err = e.storage.change_branch("feature".to_string()).unwrap_err();
+    assert!(matches!(err, StorageError::BranchCheckedOut { .. }), "{err}");
+    assert!(e.storage.worktree_add(&dest.path().join("again"), "master").is_err());
+    assert!(e.storage.delete_branch("feature".to_string()).is_err());
+    assert!(wt.change_branch("master".to_string()).is_err());
+
+    fs::write(wt.work_dir().join("staged.txt"), "staged in a worktree").unwrap();
+    wt.add_paths(vec!["staged.txt".to_string()]).unwrap();
+    let staged = hex::encode(compute_hash(b"staged in a worktree"));
+    age_objects(&e, 2);
+    e.storage.gc(GcOptions::default()).unwrap();
+    assert!(e.storage.exist(&staged));
+
+    let err = e.storage.worktree_remove("feature", false).unwrap_err();
+    assert!(matches!(err, Error::WorktreeBusy(_)), "{err}");
+    let removed = e.storage.worktree_remove("feature", true).unwrap();
+    assert!(!removed.join("gato.toml").exists());
+    assert!(removed.join("staged.txt").exists());
+    assert_eq!(e.storage.worktrees().unwrap().len(), 1);
+    e.storage.change_branch("feature".to_string()).unwrap();
+}
+
+#[test]
+fn a_repository_local_store_is_named_in_gato_toml() {
+    let work = TempDir::new().unwrap();
+    let shared = TempDir::new().unwrap();
+    let storage = LocalStorage::new(
+        work.path().join("objects-here"),
+        "aaaabbbb-cccc-dddd-eeee-ffffffffffff".to_string(),
+        work.path().to_path_buf(),
+    );
+    gato::core::init::create_file_layout(&storage, Some(Path::new("objects-here"))).unwrap();
+    storage.setup().unwrap();
+
+    let reopened = LocalStorage::load_from(shared.path().to_path_buf(), work.path().to_path_buf()).unwrap();
+    assert_eq!(reopened.root_path, work.path().join("objects-here"));
+    let e = Env { _store: shared, _work: work, storage: reopened };
+    commit_files(&e, &[("a.txt", "a")], "one");
+    let head = e.storage.resolve_rev("HEAD").unwrap();
+    assert!(e.storage.objects_path(&head).starts_with(e.storage.work_dir()));
+    let tree = e.storage.get_last_tree().unwrap();
+    assert_eq!(tree.entries.len(), 1, "the store is not committed");
+}
+
+#[test]
+fn migrate_store_moves_refs_and_needed_objects() {
+    let e = env();
+    e.storage.push_to_repos().unwrap();
+    feature_ahead_of_master(&e);
+    let feature = e.storage.resolve_rev("feature").unwrap();
+    let shared = e.storage.root_path.clone();
+    let target = TempDir::new().unwrap();
+
+    let report = e.storage.migrate_store(target.path(), true).unwrap();
+    assert!(report.copied > 0);
+    assert_eq!(report.copied, report.objects);
+    assert!(!shared.join(e.storage.repo_id()).exists());
+    assert!(e.storage.list_repos().unwrap().is_empty());
+    let moved = LocalStorage::load_from(shared.clone(), e.storage.work_dir().clone()).unwrap();
+    assert_eq!(moved.root_path, target.path().canonicalize().unwrap());
+    assert_eq!(moved.resolve_rev("feature").unwrap(), feature);
+    assert!(moved.exist(&feature));
+    assert_eq!(moved.list_repos().unwrap().len(), 1);
+    let err = moved.migrate_store(target.path(), true).unwrap_err();
+    assert!(matches!(err, Error::AlreadyInStore { .. }), "{err}");
+
+    // back to the store the objects were left in: nothing to copy
+    let report = moved.migrate_store(&shared, false).unwrap();
+    assert_eq!(report.copied, 0);
+    let config = gato::core::config::load::load_config(e.storage.work_dir()).unwrap();
+    assert!(config.storage.and_then(|storage| storage.path).is_none());
+    let back = LocalStorage::load_from(shared.clone(), e.storage.work_dir().clone()).unwrap();
+    assert_eq!(back.resolve_rev("feature").unwrap(), feature);
+}
+
+// ── alternative backends ─────────────────────────────────────────────────────
+
+/// Write `files` to a scratch directory, stage them in `storage` and commit
+/// them on top of its checked out branch, as `gato add` and `gato commit` do.
+fn commit_in(storage: &dyn StorageEngine, files: &[(&str, &str)], message: &str) -> String {
+    let dir = TempDir::new().unwrap();
+    let paths = files
+        .iter()
+        .map(|(path, content)| {
+            let full = dir.path().join(path);
+            fs::create_dir_all(full.parent().unwrap()).unwrap();
+            fs::write(full, content).unwrap();
+            PathBuf::from(path)
+        })
+        .collect();
+    add_all(paths, dir.path(), storage).unwrap();
+    let commit = Commit::new(message.to_string(), storage).unwrap();
+    commit.save(storage).unwrap();
+    storage.delete_index().unwrap();
+    commit.hash().unwrap()
+}
+
+#[test]
+fn history_can_be_built_and_read_in_memory() {
+    use gato::core::storage::gc::Gc;
+    use gato::core::storage::memory::MemoryStorage;
+    let storage = MemoryStorage::new().with_user("Tester", Some("tester@example.com"));
+    let first = commit_in(&storage, &[("a.txt", "a"), ("src/b.txt", "b")], "one");
+    let second = commit_in(&storage, &[("a.txt", "a2"), ("src/b.txt", "b")], "two");
+
+    assert_eq!(Commit::get_last_commit_hash(&storage), Some(second.clone()));
+    assert_eq!(Commit::load_by_index(1, &storage).unwrap().message(), "one");
+    assert_eq!(Gc::topo_commits(&storage).unwrap(), vec![first.clone(), second.clone()]);
+    assert!(CommitGraph::new(&storage).is_ancestor(&first, &second));
+    let latest = Commit::load(second.clone(), &storage);
+    assert!(latest.verify_commit(&storage).unwrap());
+
+    let old = Tree::load(hex::encode(Commit::load(first, &storage).tree_hash()), &storage).unwrap();
+    let new = Tree::load(hex::encode(latest.tree_hash()), &storage).unwrap();
+    let changes = gato::core::diff::diff_trees(&old, &new, RenameOptions::default(), &storage).unwrap();
+    assert_eq!(changes, vec![Change::Modified(PathBuf::from("a.txt"))]);
+
+    let out = TempDir::new().unwrap();
+    latest.write_tree(out.path(), &storage).unwrap();
+    assert_eq!(fs::read_to_string(out.path().join("a.txt")).unwrap(), "a2");
+    assert_eq!(fs::read_to_string(out.path().join("src/b.txt")).unwrap(), "b");
+    assert_eq!(latest.author().name, "Tester");
+    assert_eq!(latest.author().email.as_deref(), Some("tester@example.com"));
+
+    let mark = Gc::new(vec![storage]).mark().unwrap();
+    assert_eq!(mark.commits, 2);
+}
+
+#[test]
+fn merges_run_in_memory() {
+    use gato::core::commit::merge::MergeContext;
+    use gato::core::storage::memory::MemoryStorage;
+    let storage = MemoryStorage::new();
+    let base = commit_in(&storage, &[("a.txt", "one\ntwo\nthree\n")], "base");
+    storage.new_branch("feature".to_string()).unwrap();
+    let ours = commit_in(&storage, &[("a.txt", "ONE\ntwo\nthree\n")], "ours");
+    storage.change_branch("feature".to_string()).unwrap();
+    let theirs = commit_in(&storage, &[("a.txt", "one\ntwo\nTHREE\n")], "theirs");
+
+    let mut graph = CommitGraph::new(&storage);
+    assert_eq!(graph.merge_bases(&[ours.clone()], &theirs), vec![base]);
+    let base_tree = graph.base_tree(&[ours.clone()], &theirs).unwrap();
+    let tree = |hash: &String| {
+        Tree::load(hex::encode(Commit::load(hash.clone(), &storage).tree_hash()), &storage).unwrap()
+    };
+    let mut ctx = MergeContext::new();
+    let merged = Tree::merge(base_tree, tree(&ours), tree(&theirs), &mut ctx, &storage).unwrap();
+    assert!(ctx.conflicts.is_empty());
+
+    let out = TempDir::new().unwrap();
+    let merge = Commit::new_merged(
+        "merge".to_string(),
+        merged.hash(),
+        vec![hex::decode(&theirs).unwrap(), hex::decode(&ours).unwrap()],
+        merged.dependencies(&storage).unwrap(),
+        &storage,
+    )
+    .unwrap();
+    merge.save(&storage).unwrap();
+    merge.write_tree(out.path(), &storage).unwrap();
+    assert_eq!(fs::read_to_string(out.path().join("a.txt")).unwrap(), "ONE\ntwo\nTHREE\n");
+    assert_eq!(Commit::load_by_index(0, &storage).unwrap().parents().len(), 2);
+}
+
+#[test]
+fn branches_work_the_same_behind_a_dyn_storage_engine() {
+    use gato::core::storage::gc::Gc;
+    use gato::core::storage::memory::MemoryStorage;
+    let memory = MemoryStorage::new();
+    let storage: &dyn StorageEngine = &memory;
+    commit_in(storage, &[("a.txt", "a")], "base");
+    storage.new_branch("feature".to_string()).unwrap();
+    storage.change_branch("feature".to_string()).unwrap();
+    commit_in(storage, &[("a.txt", "a"), ("b.txt", "b")], "feature");
+
+    assert_eq!(storage.head(), "feature");
+    assert_eq!(storage.list_refs().unwrap(), vec!["feature", "master"]);
+    assert_eq!(Gc::list_repo_commits(storage).unwrap().len(), 3);
+    assert!(storage.change_branch("missing".to_string()).is_err());
+    storage.change_branch("master".to_string()).unwrap();
+    storage.delete_ref("feature".to_string()).unwrap();
+    assert_eq!(Gc::topo_commits(storage).unwrap().len(), 1);
+}